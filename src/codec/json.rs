@@ -0,0 +1,46 @@
+//! The json codec. The integers out of i64 are decoded as floats
+use serde_json::{Number, Map};
+use crate::codec::{Value, unsupported};
+use crate::store::{StoreResult, StoreError};
+
+pub fn decode(bytes: &[u8]) -> StoreResult<Value> {
+    let doc: serde_json::Value = serde_json::from_slice(bytes).map_err(|e| StoreError(format!("the value is not a json: {}", e)))?;
+    Ok(from_json(doc))
+}
+
+pub fn encode(val: &Value) -> StoreResult<Vec<u8>> {
+    serde_json::to_vec(&to_json(val)?).map_err(|e| StoreError(e.to_string()))
+}
+
+fn from_json(doc: serde_json::Value) -> Value {
+    match doc {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Value::String(s),
+        serde_json::Value::Array(a) => Value::List(a.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(o) => Value::Map(o.into_iter().map(|(k, v)| (k, from_json(v))).collect()),
+    }
+}
+
+fn to_json(val: &Value) -> StoreResult<serde_json::Value> {
+    Ok(match val {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Int(i) => serde_json::Value::Number((*i).into()),
+        Value::Float(f) => serde_json::Value::Number(Number::from_f64(*f).ok_or_else(|| unsupported("json", val))?),
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::Bytes(_) => return Err(unsupported("json", val)),
+        Value::List(l) => serde_json::Value::Array(l.iter().map(to_json).collect::<StoreResult<_>>()?),
+        Value::Map(m) => {
+            let mut obj = Map::new();
+            for (k, v) in m.iter() {
+                obj.insert(k.clone(), to_json(v)?);
+            }
+            serde_json::Value::Object(obj)
+        }
+    })
+}
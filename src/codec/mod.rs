@@ -0,0 +1,164 @@
+//! Typed config values.
+//! The values are kept as bytes, the codec of the content type turns them into `Value` and back:
+//! json, toml and yaml documents, the text as a string and the binary as bytes.
+//! The maps are ordered by keys so the order of the document members is not kept.
+//! # Examples
+//! ```
+//!  let val = decode(ContentType::Toml, b"[pool]\nsize = 10")?;
+//!  assert_eq!(val.path("pool.size").and_then(Value::as_i64), Some(10));
+//!  let bytes = encode(ContentType::Json, &val)?;
+//! ```
+pub mod json;
+pub mod toml;
+pub mod yaml;
+
+use std::collections::BTreeMap;
+use crate::store::memory::content_type::ContentType;
+use crate::store::{StoreResult, StoreError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// the float or the int converted to float
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            Value::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Map(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    /// the member of the map
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_map().and_then(|m| m.get(key))
+    }
+
+    /// the nested value by the path of map keys and list indexes separated by dots (`pool.hosts.0`)
+    pub fn path(&self, path: &str) -> Option<&Value> {
+        path.split('.').try_fold(self, |v, step| match v {
+            Value::Map(m) => m.get(step),
+            Value::List(l) => step.parse::<usize>().ok().and_then(|i| l.get(i)),
+            _ => None,
+        })
+    }
+}
+
+/// decodes the bytes according to the content type
+pub fn decode(content_type: ContentType, bytes: &[u8]) -> StoreResult<Value> {
+    match content_type {
+        ContentType::Json => json::decode(bytes),
+        ContentType::Toml => toml::decode(bytes),
+        ContentType::Yaml => yaml::decode(bytes),
+        ContentType::Text => text(bytes).map(|t| Value::String(t.to_string())),
+        ContentType::Binary => Ok(Value::Bytes(bytes.to_vec())),
+    }
+}
+
+/// encodes the value according to the content type.
+/// Can return `StoreError` if the format can not keep the value (e.g. bytes in json)
+pub fn encode(content_type: ContentType, val: &Value) -> StoreResult<Vec<u8>> {
+    match (content_type, val) {
+        (ContentType::Json, _) => json::encode(val),
+        (ContentType::Toml, _) => toml::encode(val),
+        (ContentType::Yaml, _) => yaml::encode(val),
+        (ContentType::Text, Value::String(s)) => Ok(s.as_bytes().to_vec()),
+        (ContentType::Binary, Value::Bytes(b)) => Ok(b.clone()),
+        (ct, _) => Err(StoreError(format!("the {} value should be a {}", ct, if ct == ContentType::Text { "string" } else { "bytes" }))),
+    }
+}
+
+pub(crate) fn text(bytes: &[u8]) -> StoreResult<&str> {
+    std::str::from_utf8(bytes).map_err(|e| StoreError(format!("the value is not a utf-8 text: {}", e)))
+}
+
+pub(crate) fn unsupported(format: &str, val: &Value) -> StoreError {
+    StoreError(format!("the {} format can not keep the value {:?}", format, val))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{Value, decode, encode};
+    use crate::store::memory::content_type::ContentType;
+    use std::collections::BTreeMap;
+
+    pub(crate) fn sample() -> Value {
+        let mut pool = BTreeMap::new();
+        pool.insert(String::from("size"), Value::Int(10));
+        pool.insert(String::from("ratio"), Value::Float(0.5));
+        pool.insert(String::from("hosts"), Value::List(vec![Value::String(String::from("a")), Value::String(String::from("b"))]));
+        let mut root = BTreeMap::new();
+        root.insert(String::from("name"), Value::String(String::from("cfg")));
+        root.insert(String::from("enabled"), Value::Bool(true));
+        root.insert(String::from("pool"), Value::Map(pool));
+        Value::Map(root)
+    }
+
+    #[test]
+    fn path_test() {
+        let val = sample();
+        assert_eq!(val.path("pool.size").and_then(Value::as_i64), Some(10));
+        assert_eq!(val.path("pool.hosts.1").and_then(Value::as_str), Some("b"));
+        assert_eq!(val.path("pool.ratio").and_then(Value::as_f64), Some(0.5));
+        assert_eq!(val.get("enabled").and_then(Value::as_bool), Some(true));
+        assert!(val.path("pool.hosts.2").is_none());
+        assert!(val.path("name.first").is_none());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        for ct in [ContentType::Json, ContentType::Toml, ContentType::Yaml] {
+            let bytes = encode(ct, &sample()).unwrap();
+            assert_eq!(decode(ct, &bytes).unwrap(), sample(), "{}", ct);
+            assert!(encode(ct, &Value::Bytes(vec![1])).is_err());
+        }
+        assert_eq!(decode(ContentType::Text, b"plain").unwrap(), Value::String(String::from("plain")));
+        assert!(decode(ContentType::Text, &[0xFF]).is_err());
+        assert_eq!(encode(ContentType::Binary, &Value::Bytes(vec![0, 1])).unwrap(), vec![0, 1]);
+        assert!(encode(ContentType::Text, &Value::Int(1)).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! The toml codec. The document is a table so only maps can be encoded,
+//! toml has no null and the datetimes are decoded as strings
+use crate::codec::{Value, text, unsupported};
+use crate::store::{StoreResult, StoreError};
+
+pub fn decode(bytes: &[u8]) -> StoreResult<Value> {
+    let doc: ::toml::Table = text(bytes)?.parse().map_err(|e| StoreError(format!("the value is not a toml: {}", e)))?;
+    Ok(from_toml(::toml::Value::Table(doc)))
+}
+
+pub fn encode(val: &Value) -> StoreResult<Vec<u8>> {
+    match to_toml(val)? {
+        ::toml::Value::Table(t) => ::toml::to_string(&t).map(String::into_bytes).map_err(|e| StoreError(e.to_string())),
+        _ => Err(StoreError(String::from("the toml document should be a map"))),
+    }
+}
+
+fn from_toml(doc: ::toml::Value) -> Value {
+    match doc {
+        ::toml::Value::String(s) => Value::String(s),
+        ::toml::Value::Integer(i) => Value::Int(i),
+        ::toml::Value::Float(f) => Value::Float(f),
+        ::toml::Value::Boolean(b) => Value::Bool(b),
+        ::toml::Value::Datetime(d) => Value::String(d.to_string()),
+        ::toml::Value::Array(a) => Value::List(a.into_iter().map(from_toml).collect()),
+        ::toml::Value::Table(t) => Value::Map(t.into_iter().map(|(k, v)| (k, from_toml(v))).collect()),
+    }
+}
+
+fn to_toml(val: &Value) -> StoreResult<::toml::Value> {
+    Ok(match val {
+        Value::Bool(b) => ::toml::Value::Boolean(*b),
+        Value::Int(i) => ::toml::Value::Integer(*i),
+        Value::Float(f) => ::toml::Value::Float(*f),
+        Value::String(s) => ::toml::Value::String(s.clone()),
+        Value::List(l) => ::toml::Value::Array(l.iter().map(to_toml).collect::<StoreResult<_>>()?),
+        Value::Map(m) => {
+            let mut table = ::toml::Table::new();
+            for (k, v) in m.iter() {
+                table.insert(k.clone(), to_toml(v)?);
+            }
+            ::toml::Value::Table(table)
+        }
+        Value::Null | Value::Bytes(_) => return Err(unsupported("toml", val)),
+    })
+}
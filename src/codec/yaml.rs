@@ -0,0 +1,71 @@
+//! The yaml codec. Only the first document of the stream is decoded,
+//! the scalar keys of the mappings are turned into strings
+use yaml_rust::{Yaml, YamlLoader, YamlEmitter};
+use yaml_rust::yaml::Hash;
+use crate::codec::{Value, text, unsupported};
+use crate::store::{StoreResult, StoreError};
+
+pub fn decode(bytes: &[u8]) -> StoreResult<Value> {
+    let docs = YamlLoader::load_from_str(text(bytes)?).map_err(|e| StoreError(format!("the value is not a yaml: {}", e)))?;
+    match docs.into_iter().next() {
+        Some(doc) => from_yaml(doc),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn encode(val: &Value) -> StoreResult<Vec<u8>> {
+    let doc = to_yaml(val)?;
+    let mut out = String::new();
+    YamlEmitter::new(&mut out).dump(&doc).map_err(|e| StoreError(format!("{:?}", e)))?;
+    out.push('\n');
+    Ok(out.into_bytes())
+}
+
+fn from_yaml(doc: Yaml) -> StoreResult<Value> {
+    Ok(match doc {
+        Yaml::Null => Value::Null,
+        Yaml::Boolean(b) => Value::Bool(b),
+        Yaml::Integer(i) => Value::Int(i),
+        Yaml::Real(_) => Value::Float(doc.as_f64().ok_or_else(|| StoreError(format!("the yaml real {:?} is broken", doc)))?),
+        Yaml::String(s) => Value::String(s),
+        Yaml::Array(a) => Value::List(a.into_iter().map(from_yaml).collect::<StoreResult<_>>()?),
+        Yaml::Hash(h) => {
+            let mut map = std::collections::BTreeMap::new();
+            for (k, v) in h.into_iter() {
+                map.insert(key(k)?, from_yaml(v)?);
+            }
+            Value::Map(map)
+        }
+        Yaml::Alias(_) | Yaml::BadValue => return Err(StoreError(format!("the yaml value {:?} is not supported", doc))),
+    })
+}
+
+fn key(k: Yaml) -> StoreResult<String> {
+    match k {
+        Yaml::String(s) | Yaml::Real(s) => Ok(s),
+        Yaml::Integer(i) => Ok(i.to_string()),
+        Yaml::Boolean(b) => Ok(b.to_string()),
+        k => Err(StoreError(format!("the yaml key {:?} is not a scalar", k))),
+    }
+}
+
+fn to_yaml(val: &Value) -> StoreResult<Yaml> {
+    Ok(match val {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(*b),
+        Value::Int(i) => Yaml::Integer(*i),
+        Value::Float(f) if f.is_nan() => Yaml::Real(String::from(".nan")),
+        Value::Float(f) if f.is_infinite() => Yaml::Real(String::from(if *f > 0.0 { ".inf" } else { "-.inf" })),
+        Value::Float(f) => Yaml::Real(format!("{:?}", f)),
+        Value::String(s) => Yaml::String(s.clone()),
+        Value::Bytes(_) => return Err(unsupported("yaml", val)),
+        Value::List(l) => Yaml::Array(l.iter().map(to_yaml).collect::<StoreResult<_>>()?),
+        Value::Map(m) => {
+            let mut hash = Hash::new();
+            for (k, v) in m.iter() {
+                hash.insert(Yaml::String(k.clone()), to_yaml(v)?);
+            }
+            Yaml::Hash(hash)
+        }
+    })
+}
@@ -1,7 +1,42 @@
 #![allow(dead_code)]
 mod store;
+mod codec;
+
+use std::env;
+use std::path::Path;
+use std::process;
+use crate::store::log::format;
+use crate::store::diff::diff_stores;
 
 fn main() {
+    match env::args().nth(1).as_deref() {
+        Some("format") => {
+            for layout in format::describe() {
+                println!("{}", layout);
+            }
+        }
+        Some("diff") => {
+            let (old, new) = match (env::args().nth(2), env::args().nth(3)) {
+                (Some(old), Some(new)) => (old, new),
+                _ => {
+                    eprintln!("usage: diff <dirA> <dirB>");
+                    process::exit(2)
+                }
+            };
+            match diff_stores(Path::new(&old), Path::new(&new)) {
+                Ok(diff) => {
+                    for d in diff.iter() {
+                        println!("{}", d);
+                    }
+                    println!("{} keys differ", diff.len());
+                }
+                Err(e) => {
+                    eprintln!("{}", e.0);
+                    process::exit(1)
+                }
+            }
+        }
+        Some(cmd) => eprintln!("unknown command {}. Available commands: format, diff", cmd),
+        None => (),
+    }
 }
-
-
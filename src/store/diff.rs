@@ -0,0 +1,131 @@
+//! Comparison of two stores.
+//! The keys of both stores are streamed in order and merged like in a merge join,
+//! only the differing keys are kept.
+//! The fingerprints of the key ranges would not save anything here since they need every key
+//! and value to be read and hashed anyway, they make sense only when they are persisted.
+//! # Examples
+//! ```
+//!  for d in diff_stores(Path::new("data/a"), Path::new("data/b"))? {
+//!     println!("{}", d);
+//!  }
+//! ```
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::Path;
+use crate::store::StoreResult;
+use crate::store::log::transaction_log::TransactionLog;
+
+/// the difference of a key between the old and the new store
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyDiff {
+    /// the key is only in the new store
+    Added(Vec<u8>, Vec<u8>),
+    /// the key is only in the old store
+    Removed(Vec<u8>, Vec<u8>),
+    Changed { key: Vec<u8>, old: Vec<u8>, new: Vec<u8> },
+}
+
+impl KeyDiff {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            KeyDiff::Added(k, _) | KeyDiff::Removed(k, _) => k,
+            KeyDiff::Changed { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for KeyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = String::from_utf8_lossy;
+        match self {
+            KeyDiff::Added(k, v) => write!(f, "+ {} = {}", text(k), text(v)),
+            KeyDiff::Removed(k, v) => write!(f, "- {} = {}", text(k), text(v)),
+            KeyDiff::Changed { key, old, new } => write!(f, "~ {}: {} -> {}", text(key), text(old), text(new)),
+        }
+    }
+}
+
+/// compares two stores (directories of the transaction logs) without opening them for writing
+/// # Returns
+/// the differing keys in order
+pub fn diff_stores(old: &Path, new: &Path) -> StoreResult<Vec<KeyDiff>> {
+    let old = TransactionLog::open_view(&old.to_string_lossy())?;
+    let new = TransactionLog::open_view(&new.to_string_lossy())?;
+    Ok(diff_sorted(old.iter(), new.iter()))
+}
+
+/// merges two sequences of keys and values sorted by keys
+/// # Returns
+/// the differing keys in order
+pub fn diff_sorted<A, B>(old: A, new: B) -> Vec<KeyDiff>
+    where A: IntoIterator<Item=(Vec<u8>, Vec<u8>)>, B: IntoIterator<Item=(Vec<u8>, Vec<u8>)> {
+    let mut old = old.into_iter().peekable();
+    let mut new = new.into_iter().peekable();
+    let mut diff = vec![];
+    loop {
+        let ord = match (old.peek(), new.peek()) {
+            (None, None) => return diff,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((o, _)), Some((n, _))) => o.cmp(n),
+        };
+        match ord {
+            Ordering::Less => diff.extend(old.next().map(|(k, v)| KeyDiff::Removed(k, v))),
+            Ordering::Greater => diff.extend(new.next().map(|(k, v)| KeyDiff::Added(k, v))),
+            Ordering::Equal => {
+                if let (Some((key, o)), Some((_, n))) = (old.next(), new.next()) {
+                    if o != n {
+                        diff.push(KeyDiff::Changed { key, old: o, new: n })
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::diff::{diff_sorted, diff_stores, KeyDiff};
+    use crate::store::log::transaction_log::{TransactionLog, Record, LogOptions};
+    use std::path::Path;
+    use std::fs;
+
+    #[test]
+    fn diff_sorted_test() {
+        let old = vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec()), (b"c".to_vec(), b"3".to_vec())];
+        let new = vec![(b"b".to_vec(), b"2".to_vec()), (b"c".to_vec(), b"4".to_vec()), (b"d".to_vec(), b"5".to_vec())];
+        assert_eq!(diff_sorted(old.clone(), new), vec![
+            KeyDiff::Removed(b"a".to_vec(), b"1".to_vec()),
+            KeyDiff::Changed { key: b"c".to_vec(), old: b"3".to_vec(), new: b"4".to_vec() },
+            KeyDiff::Added(b"d".to_vec(), b"5".to_vec()),
+        ]);
+        assert!(diff_sorted(old.clone(), old).is_empty());
+        assert_eq!(KeyDiff::Added(b"d".to_vec(), b"5".to_vec()).to_string(), "+ d = 5");
+    }
+
+    #[test]
+    fn diff_stores_test() {
+        let (a, b) = (r"test_data\diff_stores_a", r"test_data\diff_stores_b");
+        let opts = LogOptions { segment_size: Some(200), ..LogOptions::default() };
+        {
+            let log_a = TransactionLog::create_with(a, opts.clone()).unwrap();
+            let log_b = TransactionLog::create_with(b, opts).unwrap();
+            for i in 0..20u8 {
+                log_a.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+                log_b.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+            }
+            log_a.push(&Record::delete_record(vec![3], vec![])).unwrap();
+            log_b.push(&Record::insert_record(vec![4], vec![40])).unwrap();
+            assert!(!log_a.segments().is_empty());
+        }
+
+        let diff = diff_stores(Path::new(a), Path::new(b)).unwrap();
+        assert_eq!(diff, vec![
+            KeyDiff::Added(vec![3], vec![3]),
+            KeyDiff::Changed { key: vec![4], old: vec![4], new: vec![40] },
+        ]);
+        assert!(diff_stores(Path::new(a), Path::new(r"test_data\diff_stores_absent")).is_err());
+        let _ = fs::remove_dir_all(a);
+        let _ = fs::remove_dir_all(b);
+    }
+}
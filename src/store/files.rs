@@ -21,7 +21,7 @@
 
 use std::path::Path;
 use std::fs::{OpenOptions, File};
-use std::io::{Write, Read, BufReader};
+use std::io::{Write, Read, Seek, SeekFrom};
 use std::{io, fs};
 use crate::store::{FromBytes, ToBytes, StoreError};
 
@@ -37,74 +37,132 @@ fn append_bytes(p: &Path, bytes: &[u8]) -> io::Result<usize> {
         .write(bytes)
 }
 
-pub fn copy_file(src: &Path, dst: &Path) -> Result<(), StoreError> {
-    fs::copy(src, dst)?;
-    Ok(())
+/// flushes the content and metadata of the file to the disk
+pub fn sync_file(p: &Path) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(p)?
+        .sync_all()
 }
 
-pub fn read_slice<T: FromBytes>(p: &Path, from: u64, number: u64) -> Result<T, StoreError> {
-    let f = File::open(p)?;
-    let file_size = f.metadata()?.len();
-    let to = from + number;
-    read_slice_bytes_internally(from, to, file_size, f)
-        .and_then(|bs| FromBytes::from_bytes(bs.as_slice()))
+/// makes the entries of the directory (created or renamed files) durable.
+/// On unix the directory itself should be synced after a file is created or renamed
+#[cfg(unix)]
+pub fn sync_dir(p: &Path) -> io::Result<()> {
+    File::open(p)?.sync_all()
 }
 
-pub fn read_from_end<T: FromBytes>(p: &Path, number: u64) -> Result<T, StoreError> {
-    let f = File::open(p)?;
-    let file_size = f.metadata()?.len();
-    let start_pos = file_size - number;
-    read_slice_bytes_internally(start_pos, file_size, file_size, f)
-        .and_then(|bs| FromBytes::from_bytes(bs.as_slice()))
+/// makes the entries of the directory (created or renamed files) durable.
+/// On windows directories can not be synced, the metadata is flushed
+/// together with the file by `FlushFileBuffers` (see `sync_file`)
+#[cfg(windows)]
+pub fn sync_dir(p: &Path) -> io::Result<()> {
+    if p.is_dir() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{:?} is not a directory", p)))
+    }
 }
 
-pub fn read_slice_from_end<T: FromBytes>(p: &Path, from: u64, number: u64) -> Result<T, StoreError> {
-    let f = File::open(p)?;
-    let file_size = f.metadata()?.len();
-    let start_pos = file_size - from;
-    let fin_pos = start_pos + number;
-    read_slice_bytes_internally(start_pos, fin_pos, file_size, f)
-        .and_then(|bs| FromBytes::from_bytes(bs.as_slice()))
+/// syncs the file and the directory containing it
+/// so that the file survives a crash right after it has been created
+pub fn sync_new_file(p: &Path) -> io::Result<()> {
+    sync_file(p)?;
+    match p.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
 }
 
+pub fn copy_file(src: &Path, dst: &Path) -> Result<(), StoreError> {
+    fs::copy(src, dst)?;
+    sync_new_file(dst)?;
+    Ok(())
+}
 
-pub fn read_all_file_bytes(p: &Path) -> Result<Vec<u8>, StoreError> {
-    let f = File::open(p)?;
-    let file_size = f.metadata()?.len();
-    read_slice_bytes_internally(0, file_size, file_size, f)
+/// keeps the file open and reads the slices at the given offsets
+/// seeking to them, so reading an entry does not depend on its position in the file.
+/// The size is taken when the file is opened, `refresh` picks up the appended bytes
+pub struct FileReader {
+    file: File,
+    size: u64,
 }
 
+impl FileReader {
+    pub fn open(p: &Path) -> Result<FileReader, StoreError> {
+        let file = File::open(p)?;
+        let size = file.metadata()?.len();
+        Ok(FileReader { file, size })
+    }
 
+    pub fn len(&self) -> u64 {
+        self.size
+    }
 
-fn read_slice_bytes_internally(from: u64, to: u64, file_size: u64, f: File) -> Result<Vec<u8>, StoreError> {
-    if from >= file_size || to > file_size || from >= to {
-        return Err(
-            StoreError(String::from(
-                format!("from:{f} >= file_size:{fs} || to:{t} > file_size:{fs} || from:{f} >= to:{t}",
-                        f = from, fs = file_size, t = to)))
-        );
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
     }
 
-    let range = (to - from) as usize;
-    let vec: Vec<u8> =
-        BufReader::with_capacity(8*1024, f)
-            .bytes()
-            .skip(from as usize)
-            .take(range)
-            .filter_map(Result::ok)
-            .collect();
+    /// rereads the size of the file which could grow since it has been opened
+    pub fn refresh(&mut self) -> Result<u64, StoreError> {
+        self.size = self.file.metadata()?.len();
+        Ok(self.size)
+    }
 
-    if vec.len() == range {
-        Ok(vec)
-    } else {
-        Err(StoreError(String::from("some of bytes are broken")))
+    /// reads `number` bytes starting from the offset `from`
+    pub fn read_at(&mut self, from: u64, number: u64) -> Result<Vec<u8>, StoreError> {
+        let to = from + number;
+        if from >= self.size || to > self.size || from >= to {
+            return Err(
+                StoreError(format!("from:{f} >= file_size:{fs} || to:{t} > file_size:{fs} || from:{f} >= to:{t}",
+                                   f = from, fs = self.size, t = to))
+            );
+        }
+        let mut buf = vec![0; number as usize];
+        self.file.seek(SeekFrom::Start(from))?;
+        self.file.read_exact(&mut buf).map_err(|_| StoreError(String::from("some of bytes are broken")))?;
+        Ok(buf)
+    }
+
+    /// reads `number` bytes starting `from` bytes before the end of the file
+    pub fn read_at_end(&mut self, from: u64, number: u64) -> Result<Vec<u8>, StoreError> {
+        match self.size.checked_sub(from) {
+            Some(start_pos) => self.read_at(start_pos, number),
+            None => Err(StoreError(format!("from:{} > file_size:{}", from, self.size))),
+        }
+    }
+
+    pub fn read_item<T: FromBytes>(&mut self, from: u64, number: u64) -> Result<T, StoreError> {
+        self.read_at(from, number).and_then(|bs| FromBytes::from_bytes(bs.as_slice()))
+    }
+
+    pub fn read_item_from_end<T: FromBytes>(&mut self, from: u64, number: u64) -> Result<T, StoreError> {
+        self.read_at_end(from, number).and_then(|bs| FromBytes::from_bytes(bs.as_slice()))
     }
 }
 
+pub fn read_slice<T: FromBytes>(p: &Path, from: u64, number: u64) -> Result<T, StoreError> {
+    FileReader::open(p)?.read_item(from, number)
+}
+
+pub fn read_from_end<T: FromBytes>(p: &Path, number: u64) -> Result<T, StoreError> {
+    FileReader::open(p)?.read_item_from_end(number, number)
+}
+
+pub fn read_slice_from_end<T: FromBytes>(p: &Path, from: u64, number: u64) -> Result<T, StoreError> {
+    FileReader::open(p)?.read_item_from_end(from, number)
+}
+
+
+pub fn read_all_file_bytes(p: &Path) -> Result<Vec<u8>, StoreError> {
+    let mut reader = FileReader::open(p)?;
+    reader.read_at(0, reader.len())
+}
+
 
 #[cfg(test)]
 mod tests {
-    use crate::store::files::{read_from_end, read_slice, read_slice_from_end, read_all_file_bytes, append_item};
+    use crate::store::files::{read_from_end, read_slice, read_slice_from_end, read_all_file_bytes, append_item, sync_dir, sync_new_file, FileReader};
     use std::path::Path;
     use crate::store::log::transaction_log::{Index, Record};
     use std::fs::{File, remove_file};
@@ -220,4 +278,51 @@ mod tests {
         let _ = remove_file(idx_file);
         let _ = remove_file(log_file);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn file_reader_test() {
+        let p = Path::new("file_reader.data");
+        let _ = File::create(p).unwrap();
+        for i in 1..=1000 {
+            append_item(p, &Index::create(i)).unwrap();
+        }
+
+        let mut reader = FileReader::open(p).unwrap();
+        assert_eq!(reader.len(), 4000);
+        assert_eq!(reader.read_item::<Index>(3996, 4).unwrap(), Index::create(1000));
+        assert_eq!(reader.read_item::<Index>(0, 4).unwrap(), Index::create(1));
+        assert_eq!(reader.read_item_from_end::<Index>(8, 4).unwrap(), Index::create(999));
+        assert!(reader.read_at(3998, 4).is_err());
+        assert!(reader.read_at_end(4004, 4).is_err());
+
+        append_item(p, &Index::create(1001)).unwrap();
+        assert!(reader.read_at(4000, 4).is_err());
+        assert_eq!(reader.refresh().unwrap(), 4004);
+        assert_eq!(reader.read_item::<Index>(4000, 4).unwrap(), Index::create(1001));
+        assert_eq!(read_all_file_bytes(p).unwrap().len(), 4004);
+        let _ = remove_file(p);
+    }
+
+    #[test]
+    fn sync_new_file_test() {
+        let p = Path::new("sync_new_file.data");
+        let _ = File::create(p).unwrap();
+        assert!(sync_new_file(p).is_ok());
+        let _ = remove_file(p);
+        assert!(sync_new_file(p).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sync_dir_unix_test() {
+        assert!(sync_dir(Path::new(".")).is_ok());
+        assert!(sync_dir(Path::new("not_existing_dir")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn sync_dir_windows_test() {
+        assert!(sync_dir(Path::new(".")).is_ok());
+        assert!(sync_dir(Path::new("not_existing_dir")).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! K-way merge of the sorted sources of entries (the memtable, the log segments read by `LogView`).
+//! Every source goes in order of keys and has one version of a key at most.
+//! The seqs should be comparable across the sources (the log segments in v2 or v3 keep them).
+//! The sources are merged by a heap of their heads, the version with the highest seq wins
+//! and the older versions of the key in the other sources are dropped.
+//! The tombstones are kept for the compaction (they shadow the older values down the line)
+//! and skipped by `MergeIterator::live` for the scans.
+//! # Examples
+//! ```
+//!  let mut merge = MergeIterator::new();
+//!  merge.add(table.flush_iter());
+//!  for s in segments.iter() { merge.add(s.entries()) }
+//!  for (k, v) in merge.live() { println!("{:?} {:?}", k, v) }
+//! ```
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use crate::store::memory::memtable::{MemEntry, FlushEntry};
+use crate::store::log::transaction_log::time_now_millis;
+
+type Source<'a, K, V> = Box<dyn Iterator<Item=FlushEntry<K, V>> + 'a>;
+
+pub struct MergeIterator<'a, K: Ord, V> {
+    sources: Vec<Source<'a, K, V>>,
+    heads: BinaryHeap<Head<K, V>>,
+}
+
+/// the current entry of the source
+struct Head<K, V> {
+    key: K,
+    entry: MemEntry<V>,
+    source: usize,
+}
+
+impl<'a, K: Ord, V> MergeIterator<'a, K, V> {
+    pub fn new() -> Self {
+        MergeIterator { sources: vec![], heads: BinaryHeap::new() }
+    }
+
+    /// adds the source sorted by keys. The versions of the same seq are taken from the source added first
+    pub fn add<I>(&mut self, source: I) where I: IntoIterator<Item=FlushEntry<K, V>>, I::IntoIter: 'a {
+        self.sources.push(Box::new(source.into_iter()));
+        self.advance(self.sources.len() - 1);
+    }
+
+    /// the keys and values skipping the tombstones and the expired values
+    pub fn live(self) -> impl Iterator<Item=(K, V)> + 'a where K: 'a, V: 'a {
+        let now = time_now_millis();
+        self.filter(move |(_, e)| !e.is_expired(now)).filter_map(|(k, e)| e.val.map(|v| (k, v)))
+    }
+
+    fn advance(&mut self, source: usize) {
+        if let Some((key, entry)) = self.sources[source].next() {
+            self.heads.push(Head { key, entry, source })
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Default for MergeIterator<'a, K, V> {
+    fn default() -> Self {
+        MergeIterator::new()
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for MergeIterator<'a, K, V> {
+    type Item = FlushEntry<K, V>;
+
+    /// the newest version of the next key including tombstones
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heads.pop()?;
+        self.advance(head.source);
+        while self.heads.peek().map(|h| h.key == head.key).unwrap_or(false) {
+            if let Some(old) = self.heads.pop() {
+                self.advance(old.source);
+            }
+        }
+        Some((head.key, head.entry))
+    }
+}
+
+/// the heap is the max heap so the smallest key goes first,
+/// then the highest seq and then the source added first
+impl<K: Ord, V> Ord for Head<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.cmp(&self.key)
+            .then(self.entry.seq.cmp(&other.entry.seq))
+            .then(other.source.cmp(&self.source))
+    }
+}
+
+impl<K: Ord, V> PartialOrd for Head<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> PartialEq for Head<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord, V> Eq for Head<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::iter::MergeIterator;
+    use crate::store::memory::memtable::{MemEntry, BaseMemTable, MemOp};
+    use crate::store::log::transaction_log::{TransactionLog, Record, LogOptions, RecordFormat, TimestampPrecision};
+    use crate::store::log::log_view::LogView;
+    use crate::store::log::segment::sealed_files;
+    use std::path::Path;
+    use std::fs;
+
+    #[test]
+    fn merge_test() {
+        let mut merge = MergeIterator::new();
+        merge.add(vec![(1, MemEntry::put(5, "a5")), (3, MemEntry::tombstone(7)), (6, MemEntry::put(1, "f1"))]);
+        merge.add(vec![(1, MemEntry::put(2, "a2")), (2, MemEntry::put(3, "b3")), (3, MemEntry::put(4, "c4"))]);
+        merge.add(vec![]);
+        merge.add(vec![(2, MemEntry::put(9, "b9")), (4, MemEntry::tombstone(1)), (5, MemEntry::put(1, "e1"))]);
+
+        let all: Vec<_> = merge.map(|(k, e)| (k, e.seq, e.op)).collect();
+        assert_eq!(all, vec![(1, 5, MemOp::Put), (2, 9, MemOp::Put), (3, 7, MemOp::Delete), (4, 1, MemOp::Delete), (5, 1, MemOp::Put), (6, 1, MemOp::Put)]);
+
+        let mut merge = MergeIterator::new();
+        merge.add(vec![(1, MemEntry::put(5, "a5")), (3, MemEntry::tombstone(7))]);
+        merge.add(vec![(1, MemEntry::put(5, "a5 older")), (3, MemEntry::put(4, "c4")), (4, MemEntry { expires_at: Some(1), ..MemEntry::put(1, "d1") })]);
+        assert_eq!(merge.live().collect::<Vec<_>>(), vec![(1, "a5")]);
+        assert_eq!(MergeIterator::<u8, u8>::new().next(), None);
+    }
+
+    #[test]
+    fn merge_segments_test() {
+        let dir = r"test_data\merge_segments";
+        let opts = LogOptions { segment_size: Some(40), format: RecordFormat::V2(TimestampPrecision::Millis), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(dir, opts).unwrap();
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        for i in 0..10u8 {
+            let seq = t_log.push(&Record::insert_record(vec![i % 4], vec![i])).unwrap();
+            if i >= 8 {
+                table.put_at(vec![i % 4], vec![i], seq);
+            }
+        }
+        let seq = t_log.push(&Record::delete_record(vec![2], vec![])).unwrap();
+        table.delete_at(vec![2], seq);
+
+        let mut files = sealed_files(Path::new(dir), "log_idx.cfgdb", "log_data.cfgdb").unwrap();
+        assert!(files.len() > 1);
+        files.push((Path::new(dir).join("log_idx.cfgdb"), Path::new(dir).join("log_data.cfgdb")));
+        let views: Vec<LogView> = files.into_iter().map(|f| LogView::open(&[f], None).unwrap()).collect();
+        let mut merge = MergeIterator::new();
+        merge.add(table.flush_iter());
+        for v in views.iter() {
+            merge.add(v.entries());
+        }
+        assert_eq!(merge.live().collect::<Vec<_>>(), vec![(vec![0], vec![8]), (vec![1], vec![9]), (vec![3], vec![7])]);
+        t_log.remove_files().unwrap();
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,234 @@
+//! Encryption for the backup files.
+//! The file is encrypted with AES-256-GCM by the key taken from `KeyProvider`.
+//! The id of the key is written in the header so the backup can be decrypted
+//! after the key has been rotated as long as the provider still knows the old key.
+//!
+//! # Structure of encrypted file
+//! | field         | size in bytes |
+//! | :------------ | -------------:|
+//! | magic         | 8             |
+//! | version       | 1             |
+//! | key id length | 1             |
+//! | key id        | ~             |
+//! | nonce         | 12            |
+//! | ciphertext    | ~             |
+//!
+//! The header (everything before the ciphertext) is authenticated as well.
+use std::collections::HashMap;
+use std::path::Path;
+use std::fs;
+use aes_gcm::{Aes256Gcm, Key, Nonce, KeyInit};
+use aes_gcm::aead::{Aead, Payload};
+use rand::Rng;
+use crate::store::{StoreResult, StoreError};
+use crate::store::files::{sync_file, sync_dir};
+
+pub(crate) static MAGIC: &[u8; 8] = b"cfgdbenc";
+pub(crate) const VERSION: u8 = 1;
+pub(crate) const NONCE_LEN: usize = 12;
+
+/// source of the keys for encrypting and decrypting backups
+pub trait KeyProvider {
+    /// the id of the key used to encrypt new backups
+    fn current_key_id(&self) -> String;
+    /// the key by id or none if the key is unknown
+    fn key(&self, id: &str) -> Option<[u8; 32]>;
+}
+
+/// in memory set of keys where the last added one is current
+pub struct StaticKeys {
+    current: String,
+    keys: HashMap<String, [u8; 32]>,
+}
+
+impl StaticKeys {
+    pub fn new(id: &str, key: [u8; 32]) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(String::from(id), key);
+        StaticKeys { current: String::from(id), keys }
+    }
+
+    /// adds a new key and makes it current. The old keys are kept to decrypt old backups
+    pub fn rotate(&mut self, id: &str, key: [u8; 32]) {
+        self.keys.insert(String::from(id), key);
+        self.current = String::from(id);
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn current_key_id(&self) -> String {
+        self.current.clone()
+    }
+
+    fn key(&self, id: &str) -> Option<[u8; 32]> {
+        self.keys.get(id).copied()
+    }
+}
+
+pub fn encrypt_bytes(plain: &[u8], keys: &dyn KeyProvider) -> StoreResult<Vec<u8>> {
+    let id = keys.current_key_id();
+    if id.len() > u8::MAX as usize {
+        return Err(StoreError(format!("the key id {} is longer than 255 bytes", id)));
+    }
+    let key = find_key(&id, keys)?;
+    let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.push(id.len() as u8);
+    bytes.extend_from_slice(id.as_bytes());
+    bytes.extend_from_slice(&nonce);
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain, aad: &bytes })
+        .map_err(|_| StoreError(String::from("impossible to encrypt the backup")))?;
+    bytes.extend_from_slice(&encrypted);
+    Ok(bytes)
+}
+
+pub fn decrypt_bytes(bytes: &[u8], keys: &dyn KeyProvider) -> StoreResult<Vec<u8>> {
+    let (id, header_len) = parse_header(bytes)?
+        .ok_or_else(|| StoreError(String::from("the backup is not encrypted")))?;
+    let key = find_key(&id, keys)?;
+    let nonce = &bytes[header_len - NONCE_LEN..header_len];
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: &bytes[header_len..], aad: &bytes[..header_len] })
+        .map_err(|_| StoreError(format!("the backup can not be decrypted with the key {}", id)))
+}
+
+pub fn encrypt_file(src: &Path, dst: &Path, keys: &dyn KeyProvider) -> StoreResult<()> {
+    let plain = fs::read(src)?;
+    fs::write(dst, encrypt_bytes(&plain, keys)?)?;
+    Ok(())
+}
+
+pub fn decrypt_file(src: &Path, dst: &Path, keys: &dyn KeyProvider) -> StoreResult<()> {
+    let bytes = fs::read(src)?;
+    fs::write(dst, decrypt_bytes(&bytes, keys)?)?;
+    Ok(())
+}
+
+/// the id of the key the file is encrypted with or none if the file is not encrypted
+pub fn key_id(p: &Path) -> StoreResult<Option<String>> {
+    let bytes = fs::read(p)?;
+    Ok(parse_header(&bytes)?.map(|(id, _)| id))
+}
+
+/// re-encrypts the file with the current key if it has been encrypted with another one
+/// # Returns
+/// true if the file has been rewritten
+pub fn reencrypt_file(p: &Path, keys: &dyn KeyProvider) -> StoreResult<bool> {
+    let bytes = fs::read(p)?;
+    match parse_header(&bytes)? {
+        Some((id, _)) if id == keys.current_key_id() => Ok(false),
+        Some(_) => {
+            let plain = decrypt_bytes(&bytes, keys)?;
+            replace_file(p, &encrypt_bytes(&plain, keys)?)?;
+            Ok(true)
+        }
+        None => Err(StoreError(format!("the file {:?} is not encrypted", p))),
+    }
+}
+
+/// writes the bytes to the sibling temp file and renames it over the file
+/// so a crash leaves either the old or the new content
+fn replace_file(p: &Path, bytes: &[u8]) -> StoreResult<()> {
+    let mut tmp_name = p.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    tmp_name.push(".tmp");
+    let tmp = p.with_file_name(tmp_name);
+    let res = fs::write(&tmp, bytes)
+        .and_then(|_| sync_file(&tmp))
+        .and_then(|_| fs::rename(&tmp, p));
+    if let Err(e) = res {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    let dir = p.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    sync_dir(dir)?;
+    Ok(())
+}
+
+fn find_key(id: &str, keys: &dyn KeyProvider) -> StoreResult<[u8; 32]> {
+    keys.key(id).ok_or_else(|| StoreError(format!("the key {} is not found", id)))
+}
+
+/// # Returns
+/// the key id and the length of header or none if there is no magic
+fn parse_header(bytes: &[u8]) -> StoreResult<Option<(String, usize)>> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Ok(None);
+    }
+    let broken = || StoreError(String::from("the header of encrypted backup is broken"));
+    let version = *bytes.get(MAGIC.len()).ok_or_else(broken)?;
+    if version != VERSION {
+        return Err(StoreError(format!("the version {} of encrypted backup is not supported", version)));
+    }
+    let id_len = *bytes.get(MAGIC.len() + 1).ok_or_else(broken)? as usize;
+    let id_start = MAGIC.len() + 2;
+    let header_len = id_start + id_len + NONCE_LEN;
+    if bytes.len() < header_len {
+        return Err(broken());
+    }
+    let id = String::from_utf8(bytes[id_start..id_start + id_len].to_vec()).map_err(|_| broken())?;
+    Ok(Some((id, header_len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::backup::{StaticKeys, encrypt_bytes, decrypt_bytes, encrypt_file, reencrypt_file, key_id, decrypt_file};
+    use std::path::Path;
+    use std::fs;
+
+    #[test]
+    fn encrypt_test() {
+        let keys = StaticKeys::new("k1", [7; 32]);
+        let plain = vec![1, 2, 3, 4, 5];
+        let enc = encrypt_bytes(&plain, &keys).unwrap();
+        assert_ne!(enc[enc.len() - plain.len()..].to_vec(), plain);
+        assert_eq!(decrypt_bytes(&enc, &keys).unwrap(), plain);
+
+        let other = StaticKeys::new("k1", [8; 32]);
+        assert!(decrypt_bytes(&enc, &other).is_err());
+        let unknown = StaticKeys::new("k2", [7; 32]);
+        assert!(decrypt_bytes(&enc, &unknown).is_err());
+    }
+
+    #[test]
+    fn tampered_test() {
+        let keys = StaticKeys::new("k1", [7; 32]);
+        let mut enc = encrypt_bytes(&[1, 2, 3], &keys).unwrap();
+        let last = enc.len() - 1;
+        enc[last] ^= 1;
+        assert!(decrypt_bytes(&enc, &keys).is_err());
+        assert!(decrypt_bytes(&[1, 2, 3], &keys).is_err());
+    }
+
+    #[test]
+    fn rotate_test() {
+        let src = Path::new("backup_rotate_src.data");
+        let dst = Path::new("backup_rotate_dst.data");
+        let restored = Path::new("backup_rotate_restored.data");
+        fs::write(src, vec![1; 100]).unwrap();
+
+        let mut keys = StaticKeys::new("k1", [1; 32]);
+        encrypt_file(src, dst, &keys).unwrap();
+        assert_eq!(key_id(dst).unwrap(), Some(String::from("k1")));
+        assert!(!reencrypt_file(dst, &keys).unwrap());
+
+        keys.rotate("k2", [2; 32]);
+        assert!(reencrypt_file(dst, &keys).unwrap());
+        assert_eq!(key_id(dst).unwrap(), Some(String::from("k2")));
+        assert!(!Path::new("backup_rotate_dst.data.tmp").exists());
+
+        decrypt_file(dst, restored, &StaticKeys::new("k2", [2; 32])).unwrap();
+        assert_eq!(fs::read(restored).unwrap(), vec![1; 100]);
+        assert_eq!(key_id(src).unwrap(), None);
+
+        let _ = fs::remove_file(src);
+        let _ = fs::remove_file(dst);
+        let _ = fs::remove_file(restored);
+    }
+}
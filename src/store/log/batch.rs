@@ -0,0 +1,445 @@
+//! Batch of records written to the log as one entry.
+//! The batch is the unit of group commit and replication:
+//! either all records of the batch are read back or none of them.
+//!
+//! # Structure of batch
+//! | field         | description                | size in bytes |
+//! | :------------ |:--------------------------:| -------------:|
+//! | marker        | always 0                   | 1             |
+//! | kind          | 16                         | 1             |
+//! | count         | number of records          | 4             |
+//! | length        | length of payload          | 4             |
+//! | crc32         | checksum of header, payload| 4             |
+//! | first seq     | seq of the first record    | 8             |
+//! | last seq      | seq of the last record     | 8             |
+//! | payload       | (record length(4), record)*| ~             |
+//!
+//! The batches checksummed by another algorithm than crc32 have the kind 17,
+//! the code of `ChecksumKind` after the kind and the checksum of 8 bytes.
+//! The checksum covers all bytes of the batch except the checksum itself.
+use crate::store::{ToBytes, FromBytes, StoreResult, StoreError};
+use crate::store::log::transaction_log::{Record, VERSIONED_MARKER};
+use crate::store::structures::checksum::ChecksumKind;
+
+pub const BATCH_KIND: u8 = 16;
+/// the batch with the checksum kind
+pub const CHECKSUMMED_BATCH_KIND: u8 = 17;
+pub(crate) const HEADER_LEN: usize = 30;
+pub(crate) const CHECKSUMMED_HEADER_LEN: usize = 35;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct RecordBatch {
+    first_seq: u64,
+    records: Vec<Record>,
+    checksum: ChecksumKind,
+}
+
+/// limits of the batch written as one entry of the log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchLimits {
+    /// the max size of the batch in bytes including the header
+    pub max_bytes: u32,
+    pub max_records: usize,
+    /// the batch exceeding the limits is split into several batches
+    /// each of them is atomic but the whole one is not.
+    /// Otherwise the batch is rejected
+    pub split: bool,
+}
+
+impl Default for BatchLimits {
+    /// the only limit is the length of the index entry (u32)
+    fn default() -> Self {
+        BatchLimits { max_bytes: u32::MAX, max_records: usize::MAX, split: false }
+    }
+}
+
+/// an entry of the log: either a single record or a batch
+#[derive(PartialEq, Debug, Clone)]
+pub enum LogEntry {
+    Single(Record),
+    Batch(RecordBatch),
+}
+
+impl RecordBatch {
+    pub fn new(records: Vec<Record>) -> Self {
+        RecordBatch { first_seq: 0, records, checksum: ChecksumKind::Crc32 }
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// the records with the seqs restored from the batch header
+    pub fn into_records(self) -> Vec<Record> {
+        let first_seq = self.first_seq;
+        let mut records = self.records;
+        for (i, r) in records.iter_mut().enumerate() {
+            r.set_seq(first_seq + i as u64);
+        }
+        records
+    }
+
+    pub fn count(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// the seq of the first record. It is assigned by the log while pushing
+    pub fn first_seq(&self) -> u64 {
+        self.first_seq
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.first_seq.saturating_add(self.records.len().saturating_sub(1) as u64)
+    }
+
+    pub fn with_first_seq(self, first_seq: u64) -> Self {
+        RecordBatch { first_seq, ..self }
+    }
+
+    pub fn checksum(&self) -> ChecksumKind {
+        self.checksum
+    }
+
+    pub fn with_checksum(self, checksum: ChecksumKind) -> Self {
+        RecordBatch { checksum, ..self }
+    }
+
+    pub fn size_in_bytes(&self) -> u32 {
+        self.size_in_bytes_u64() as u32
+    }
+
+    /// splits the batch into the batches fitting the limits keeping the order of records.
+    /// Can return `StoreError` if the batch exceeds the limits and splitting is not allowed
+    /// or a single record does not fit `max_bytes`
+    pub fn split(self, limits: &BatchLimits) -> StoreResult<Vec<RecordBatch>> {
+        let fits = |records: usize, bytes: u64| records <= limits.max_records && bytes <= limits.max_bytes as u64;
+        if fits(self.count(), self.size_in_bytes_u64()) {
+            return Ok(vec![self]);
+        }
+        if !limits.split {
+            return Err(StoreError(format!("the batch of {} records and {} bytes exceeds the limits {:?}",
+                                          self.count(), self.size_in_bytes_u64(), limits)));
+        }
+
+        let header = self.header_len() as u64;
+        let checksum = self.checksum;
+        let mut batches = vec![];
+        let mut curr: Vec<Record> = vec![];
+        let mut curr_bytes = header;
+        for r in self.records.into_iter() {
+            let r_bytes = r.size_in_bytes() as u64 + 4;
+            if !fits(1, header + r_bytes) {
+                return Err(StoreError(format!("the record of {} bytes does not fit the batch limits {:?}", r_bytes, limits)));
+            }
+            if !fits(curr.len() + 1, curr_bytes + r_bytes) {
+                batches.push(RecordBatch::new(curr).with_checksum(checksum));
+                curr = vec![];
+                curr_bytes = header;
+            }
+            curr.push(r);
+            curr_bytes += r_bytes;
+        }
+        batches.push(RecordBatch::new(curr).with_checksum(checksum));
+        Ok(batches)
+    }
+
+    fn size_in_bytes_u64(&self) -> u64 {
+        let payload: u64 = self.records.iter().map(|r| r.size_in_bytes() as u64 + 4).sum();
+        payload + self.header_len() as u64
+    }
+
+    fn header_len(&self) -> usize {
+        match self.checksum {
+            ChecksumKind::Crc32 => HEADER_LEN,
+            _ => CHECKSUMMED_HEADER_LEN,
+        }
+    }
+}
+
+impl ToBytes for RecordBatch {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        for r in self.records.iter() {
+            payload.extend_from_slice(&r.size_in_bytes().to_be_bytes());
+            payload.extend_from_slice(&r.to_bytes());
+        }
+
+        let mut bytes = match self.checksum {
+            ChecksumKind::Crc32 => vec![VERSIONED_MARKER, BATCH_KIND],
+            kind => vec![VERSIONED_MARKER, CHECKSUMMED_BATCH_KIND, kind.code()],
+        };
+        bytes.extend_from_slice(&(self.records.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        let sum_at = bytes.len();
+        let sum_len = sum_len(self.checksum);
+        bytes.extend_from_slice(&[0; 8][..sum_len]);
+        bytes.extend_from_slice(&self.first_seq.to_be_bytes());
+        bytes.extend_from_slice(&self.last_seq().to_be_bytes());
+        bytes.extend_from_slice(&payload);
+
+        let sum = self.checksum.checksum(&covered(&bytes, sum_at, sum_len));
+        bytes[sum_at..sum_at + sum_len].copy_from_slice(&sum.to_be_bytes()[8 - sum_len..]);
+        bytes
+    }
+}
+
+impl FromBytes for RecordBatch {
+    /// deserializes the batch checking the length, the checksum and the count of records
+    fn from_bytes(bytes: &[u8]) -> StoreResult<Self> {
+        let no_header = || StoreError(String::from("the bytes do not contain the batch header"));
+        if !is_batch(bytes) || bytes.len() < HEADER_LEN {
+            return Err(no_header());
+        }
+        let (checksum, pos) = if bytes[1] == BATCH_KIND {
+            (ChecksumKind::Crc32, 2)
+        } else {
+            (ChecksumKind::from_code(bytes[2])?, 3)
+        };
+        let sum_len = sum_len(checksum);
+        let header_len = pos + 8 + sum_len + 16;
+        if bytes.len() < header_len {
+            return Err(no_header());
+        }
+        let count = u32_at(bytes, pos) as usize;
+        let len = u32_at(bytes, pos + 4) as usize;
+        let sum = if sum_len == 4 { u32_at(bytes, pos + 8) as u64 } else { u64_at(bytes, pos + 8) };
+        let first_seq = u64_at(bytes, pos + 8 + sum_len);
+        let last_seq = u64_at(bytes, pos + 16 + sum_len);
+
+        let payload = &bytes[header_len..];
+        if payload.len() != len {
+            return Err(StoreError(format!("the batch is torn: expected {} bytes, got {}", len, payload.len())));
+        }
+        if !checksum.checksummer().verify(&covered(bytes, pos + 8, sum_len), sum) {
+            return Err(StoreError::corruption("the checksum of the batch does not match"));
+        }
+        // every record has 4 bytes of the length at least
+        if count == 0 || count > payload.len() / 4 {
+            return Err(StoreError(format!("the batch header says {} records for {} bytes", count, payload.len())));
+        }
+        if first_seq.checked_add(count as u64 - 1) != Some(last_seq) {
+            return Err(StoreError(format!("the batch seqs {}..={} do not match {} records", first_seq, last_seq, count)));
+        }
+
+        let mut records = Vec::with_capacity(count);
+        let mut pos = 0;
+        while pos < payload.len() {
+            if pos + 4 > payload.len() {
+                return Err(StoreError(String::from("the batch payload is broken")));
+            }
+            let r_len = u32_at(payload, pos) as usize;
+            pos += 4;
+            if pos + r_len > payload.len() {
+                return Err(StoreError(String::from("the batch payload is broken")));
+            }
+            records.push(Record::from_bytes(&payload[pos..pos + r_len])?);
+            pos += r_len;
+        }
+
+        if records.len() != count {
+            return Err(StoreError(format!("the batch header says {} records but has {}", count, records.len())));
+        }
+        Ok(RecordBatch { first_seq, records, checksum })
+    }
+}
+
+impl LogEntry {
+    pub fn size_in_bytes(&self) -> u32 {
+        match self {
+            LogEntry::Single(r) => r.size_in_bytes(),
+            LogEntry::Batch(b) => b.size_in_bytes(),
+        }
+    }
+
+    /// the number of records in the entry
+    pub fn count(&self) -> usize {
+        match self {
+            LogEntry::Single(_) => 1,
+            LogEntry::Batch(b) => b.count(),
+        }
+    }
+
+    /// sets the seq of the record or the first seq of the batch
+    pub(crate) fn set_first_seq(&mut self, seq: u64) {
+        match self {
+            LogEntry::Single(r) => r.set_seq(seq),
+            LogEntry::Batch(b) => b.first_seq = seq,
+        }
+    }
+
+    /// the timestamps of the oldest and the newest record in millis
+    pub fn time_range(&self) -> (u128, u128) {
+        match self {
+            LogEntry::Single(r) => (r.timestamp_millis(), r.timestamp_millis()),
+            LogEntry::Batch(b) => b.records().iter()
+                .map(|r| r.timestamp_millis())
+                .fold((u128::MAX, 0), |(min, max), ts| (min.min(ts), max.max(ts))),
+        }
+    }
+
+    pub fn into_records(self) -> Vec<Record> {
+        match self {
+            LogEntry::Single(r) => vec![r],
+            LogEntry::Batch(b) => b.into_records(),
+        }
+    }
+}
+
+impl ToBytes for LogEntry {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            LogEntry::Single(r) => r.to_bytes(),
+            LogEntry::Batch(b) => b.to_bytes(),
+        }
+    }
+}
+
+impl FromBytes for LogEntry {
+    fn from_bytes(bytes: &[u8]) -> StoreResult<Self> {
+        if is_batch(bytes) {
+            RecordBatch::from_bytes(bytes).map(LogEntry::Batch)
+        } else {
+            Record::from_bytes(bytes).map(LogEntry::Single)
+        }
+    }
+}
+
+/// checks the bytes start with the batch marker
+pub fn is_batch(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && bytes[0] == VERSIONED_MARKER && (bytes[1] == BATCH_KIND || bytes[1] == CHECKSUMMED_BATCH_KIND)
+}
+
+fn sum_len(checksum: ChecksumKind) -> usize {
+    if checksum == ChecksumKind::Crc32 { 4 } else { 8 }
+}
+
+/// the bytes of the batch without the checksum, the header is checked with the payload
+fn covered(bytes: &[u8], sum_at: usize, sum_len: usize) -> Vec<u8> {
+    let mut covered = Vec::with_capacity(bytes.len() - sum_len);
+    covered.extend_from_slice(&bytes[..sum_at]);
+    covered.extend_from_slice(&bytes[sum_at + sum_len..]);
+    covered
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    let mut arr = [0; 4];
+    arr.copy_from_slice(&bytes[pos..pos + 4]);
+    u32::from_be_bytes(arr)
+}
+
+fn u64_at(bytes: &[u8], pos: usize) -> u64 {
+    let mut arr = [0; 8];
+    arr.copy_from_slice(&bytes[pos..pos + 8]);
+    u64::from_be_bytes(arr)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::batch::{RecordBatch, LogEntry, BatchLimits};
+    use crate::store::log::transaction_log::Record;
+    use crate::store::{ToBytes, FromBytes};
+    use crate::store::structures::checksum::ChecksumKind;
+
+    fn batch() -> RecordBatch {
+        RecordBatch::new(vec![
+            Record::insert_record(vec![1], vec![1, 1]),
+            Record::delete_record(vec![2, 2], vec![]),
+            Record::lock_record(vec![3], vec![3]),
+        ]).with_first_seq(10)
+    }
+
+    #[test]
+    fn batch_test() {
+        let b = batch();
+        assert_eq!(b.count(), 3);
+        assert_eq!(b.last_seq(), 12);
+
+        let bytes = b.to_bytes();
+        assert_eq!(bytes.len(), b.size_in_bytes() as usize);
+        assert_eq!(RecordBatch::from_bytes(&bytes).unwrap(), b);
+        assert_eq!(LogEntry::from_bytes(&bytes).unwrap(), LogEntry::Batch(b));
+
+        let rec = Record::insert_record(vec![1], vec![2]);
+        assert_eq!(LogEntry::from_bytes(&rec.to_bytes()).unwrap(), LogEntry::Single(rec));
+    }
+
+    #[test]
+    fn torn_batch_test() {
+        let mut bytes = batch().to_bytes();
+        bytes.truncate(bytes.len() - 3);
+        assert!(RecordBatch::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn corrupted_batch_test() {
+        let mut bytes = batch().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert!(RecordBatch::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn corrupted_header_test() {
+        for k in [ChecksumKind::Crc32, ChecksumKind::XxHash64].iter() {
+            let bytes = batch().with_checksum(*k).to_bytes();
+            let pos = if *k == ChecksumKind::Crc32 { 2 } else { 3 };
+            let sum_len = if *k == ChecksumKind::Crc32 { 4 } else { 8 };
+            // count, first seq and last seq
+            for at in [pos + 3, pos + 8 + sum_len + 7, pos + 16 + sum_len + 7].iter() {
+                let mut broken = bytes.clone();
+                broken[*at] ^= 0x01;
+                assert!(RecordBatch::from_bytes(&broken).unwrap_err().is_corruption(), "{:?} {}", k, at);
+            }
+        }
+
+        let overflow = batch().with_first_seq(u64::MAX - 1);
+        assert!(RecordBatch::from_bytes(&overflow.to_bytes()).is_err());
+        let empty = RecordBatch::new(vec![]).with_first_seq(0);
+        assert!(RecordBatch::from_bytes(&empty.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn split_test() {
+        let limits = BatchLimits { max_bytes: u32::MAX, max_records: 2, split: true };
+        let batches = batch().split(&limits).unwrap();
+        assert_eq!(batches.iter().map(|b| b.count()).collect::<Vec<usize>>(), vec![2, 1]);
+        assert_eq!(batches[1].records()[0], batch().records()[2]);
+
+        let one = batch().records()[0].size_in_bytes() + 4;
+        let limits = BatchLimits { max_bytes: 30 + one * 2 + 1, max_records: usize::MAX, split: true };
+        let batches = batch().split(&limits).unwrap();
+        assert_eq!(batches.iter().map(|b| b.count()).collect::<Vec<usize>>(), vec![2, 1]);
+        assert!(batches.iter().all(|b| b.size_in_bytes() <= limits.max_bytes));
+
+        assert_eq!(batch().split(&BatchLimits::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn split_rejected_test() {
+        let strict = BatchLimits { max_bytes: u32::MAX, max_records: 2, split: false };
+        assert!(batch().split(&strict).is_err());
+
+        let tiny = BatchLimits { max_bytes: 40, max_records: usize::MAX, split: true };
+        assert!(batch().split(&tiny).is_err());
+    }
+
+    #[test]
+    fn checksum_kinds_test() {
+        let kinds = [ChecksumKind::Crc32, ChecksumKind::Crc32c, ChecksumKind::XxHash64, ChecksumKind::Rabin];
+        for k in kinds.iter() {
+            let b = batch().with_checksum(*k);
+            let mut bytes = b.to_bytes();
+            assert_eq!(bytes.len(), b.size_in_bytes() as usize);
+            assert_eq!(RecordBatch::from_bytes(&bytes).unwrap(), b);
+
+            let last = bytes.len() - 1;
+            bytes[last] ^= 0xFF;
+            assert!(RecordBatch::from_bytes(&bytes).is_err());
+        }
+        assert_eq!(batch().with_checksum(ChecksumKind::XxHash64).to_bytes()[1..3], [17, 3]);
+    }
+}
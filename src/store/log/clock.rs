@@ -0,0 +1,171 @@
+//! Monotonic guard for record timestamps.
+//! The system clock can be adjusted backwards (ntp, manual changes),
+//! so the timestamps coming from records are passed through `MonotonicClock`
+//! which never issues a value less than the last issued one.
+//! The last issued value (high watermark) is persisted next to the log
+//! to keep the guarantee between restarts. The file is rewritten from the push path
+//! when the watermark moves past the stored value (before the record is written)
+//! and synced together with the log files.
+use std::path::Path;
+use std::sync::Mutex;
+use std::fs::OpenOptions;
+use std::io::Write;
+use crate::store::{StoreResult, StoreError};
+use crate::store::files::read_all_file_bytes;
+
+/// what to do when the clock goes backwards
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkewPolicy {
+    /// replace the timestamp with the last issued one
+    Clamp,
+    /// reject the record
+    Error,
+}
+
+#[derive(Debug)]
+pub struct MonotonicClock {
+    state: Mutex<Watermark>,
+    policy: SkewPolicy,
+}
+
+#[derive(Debug)]
+struct Watermark {
+    /// the last issued timestamp
+    last: u128,
+    /// the value written to the file
+    stored: u128,
+}
+
+impl MonotonicClock {
+    pub fn new(policy: SkewPolicy) -> Self {
+        MonotonicClock::with_watermark(0, policy)
+    }
+
+    pub fn with_watermark(watermark: u128, policy: SkewPolicy) -> Self {
+        MonotonicClock { state: Mutex::new(Watermark { last: watermark, stored: watermark }), policy }
+    }
+
+    /// restores the clock from the watermark file if it exists
+    pub fn load(p: &Path, policy: SkewPolicy) -> StoreResult<Self> {
+        if !p.exists() {
+            return Ok(MonotonicClock::new(policy));
+        }
+        let bytes = read_all_file_bytes(p)?;
+        if bytes.len() != 16 {
+            return Err(StoreError(format!("watermark file should have 16 bytes but has {}", bytes.len())));
+        }
+        let mut ts = [0; 16];
+        ts.copy_from_slice(&bytes);
+        Ok(MonotonicClock::with_watermark(u128::from_be_bytes(ts), policy))
+    }
+
+    /// writes the current watermark to the file replacing the old one
+    pub fn persist(&self, p: &Path) -> StoreResult<()> {
+        let mut state = self.state.lock().expect("the clock lock is poisoned");
+        write_watermark(p, state.last)?;
+        state.stored = state.last;
+        Ok(())
+    }
+
+    /// checks the timestamp against the last issued one
+    /// # Returns
+    /// the timestamp itself if it is not less than the watermark,
+    /// otherwise the watermark or `StoreError` according to `SkewPolicy`
+    pub fn stamp(&self, ts: u128) -> StoreResult<u128> {
+        self.stamp_with(ts, |_| Ok(()))
+    }
+
+    /// the same as `MonotonicClock::stamp` but the watermark is written to the file
+    /// before it is issued if it moves past the stored value,
+    /// so the file is never behind the timestamps written after it
+    pub fn stamp_persisted(&self, ts: u128, p: &Path) -> StoreResult<u128> {
+        self.stamp_with(ts, |ts| write_watermark(p, ts))
+    }
+
+    fn stamp_with<F>(&self, ts: u128, store: F) -> StoreResult<u128> where F: FnOnce(u128) -> StoreResult<()> {
+        let mut state = self.state.lock().expect("the clock lock is poisoned");
+        if ts >= state.last {
+            if ts > state.stored {
+                store(ts)?;
+                state.stored = ts;
+            }
+            state.last = ts;
+            return Ok(ts);
+        }
+        match self.policy {
+            SkewPolicy::Clamp => Ok(state.last),
+            SkewPolicy::Error =>
+                Err(StoreError(format!("clock went backwards: {} < last issued {}", ts, state.last))),
+        }
+    }
+
+    pub fn watermark(&self) -> u128 {
+        self.state.lock().expect("the clock lock is poisoned").last
+    }
+
+    pub fn policy(&self) -> SkewPolicy {
+        self.policy
+    }
+}
+
+/// the file always has 16 bytes so it is overwritten in place
+fn write_watermark(p: &Path, ts: u128) -> StoreResult<()> {
+    OpenOptions::new().write(true).create(true).truncate(false).open(p)?.write_all(&ts.to_be_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::clock::{MonotonicClock, SkewPolicy};
+    use std::path::Path;
+    use std::fs::remove_file;
+
+    #[test]
+    fn clamp_test() {
+        let clock = MonotonicClock::new(SkewPolicy::Clamp);
+        assert_eq!(clock.stamp(10).unwrap(), 10);
+        assert_eq!(clock.stamp(5).unwrap(), 10);
+        assert_eq!(clock.stamp(10).unwrap(), 10);
+        assert_eq!(clock.stamp(11).unwrap(), 11);
+        assert_eq!(clock.watermark(), 11);
+    }
+
+    #[test]
+    fn error_test() {
+        let clock = MonotonicClock::new(SkewPolicy::Error);
+        assert_eq!(clock.stamp(10).unwrap(), 10);
+        assert!(clock.stamp(9).is_err());
+        assert_eq!(clock.watermark(), 10);
+    }
+
+    #[test]
+    fn persist_test() {
+        let p = Path::new("clock_watermark.data");
+        let clock = MonotonicClock::new(SkewPolicy::Clamp);
+        clock.stamp(1_000_000).unwrap();
+        clock.persist(p).unwrap();
+
+        let restored = MonotonicClock::load(p, SkewPolicy::Clamp).unwrap();
+        assert_eq!(restored.watermark(), 1_000_000);
+        assert_eq!(restored.stamp(1).unwrap(), 1_000_000);
+
+        let _ = remove_file(p);
+    }
+
+    #[test]
+    fn stamp_persisted_test() {
+        let p = Path::new("clock_stamp_persisted.data");
+        let clock = MonotonicClock::new(SkewPolicy::Clamp);
+        assert_eq!(clock.stamp_persisted(1_000, p).unwrap(), 1_000);
+        assert_eq!(MonotonicClock::load(p, SkewPolicy::Clamp).unwrap().watermark(), 1_000);
+        assert_eq!(clock.stamp_persisted(10, p).unwrap(), 1_000);
+        assert_eq!(clock.stamp_persisted(2_000, p).unwrap(), 2_000);
+        assert_eq!(MonotonicClock::load(p, SkewPolicy::Clamp).unwrap().watermark(), 2_000);
+
+        let restored = MonotonicClock::load(p, SkewPolicy::Error).unwrap();
+        assert!(restored.stamp_persisted(1_500, p).is_err());
+        assert_eq!(MonotonicClock::load(p, SkewPolicy::Clamp).unwrap().watermark(), 2_000);
+
+        let _ = remove_file(p);
+    }
+}
@@ -0,0 +1,249 @@
+//! Description of the on-disk formats of the log.
+//! `describe` returns the layouts built from the same constants the encoders use
+//! so the tooling and external parsers can follow the format as it evolves.
+//! # Examples
+//! ```
+//!  for f in describe() {
+//!     println!("{}", f);
+//!  }
+//! ```
+use std::fmt;
+use crate::store::log::transaction_log::{VERSIONED_MARKER, V2, V3, SECONDS_FLAG, EXPIRES_FLAG, CONTENT_TYPE_FLAG, SEQ_FLAG};
+use crate::store::log::batch::{BATCH_KIND, CHECKSUMMED_BATCH_KIND};
+use crate::store::log::backup;
+
+/// the size of a field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldSize {
+    Fixed(usize),
+    /// LEB128 varint taking from 1 to `max` bytes
+    Varint { max: usize },
+    /// the size is defined by another field
+    Variable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    /// the offset from the start of the structure or none if it follows a variable sized field
+    pub offset: Option<usize>,
+    pub size: FieldSize,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub name: &'static str,
+    /// the version (or the kind) written into the structure if it has one
+    pub version: Option<u8>,
+    pub fields: Vec<Field>,
+}
+
+impl Layout {
+    /// the length of the leading fixed sized fields
+    pub fn fixed_len(&self) -> usize {
+        self.fields.iter()
+            .take_while(|f| f.offset.is_some())
+            .map(|f| match f.size {
+                FieldSize::Fixed(s) => s,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+struct LayoutBuilder {
+    layout: Layout,
+    offset: Option<usize>,
+}
+
+impl LayoutBuilder {
+    fn new(name: &'static str, version: Option<u8>) -> Self {
+        LayoutBuilder { layout: Layout { name, version, fields: vec![] }, offset: Some(0) }
+    }
+
+    fn field(mut self, name: &'static str, size: FieldSize, description: &str) -> Self {
+        self.layout.fields.push(Field { name, offset: self.offset, size, description: String::from(description) });
+        self.offset = match size {
+            FieldSize::Fixed(s) => self.offset.map(|o| o + s),
+            _ => None,
+        };
+        self
+    }
+
+    fn build(self) -> Layout {
+        self.layout
+    }
+}
+
+/// the layouts of all structures the log writes
+pub fn describe() -> Vec<Layout> {
+    vec![index(), record_v1(), record_v2(), record_v3(), batch(), checksummed_batch(), encrypted_backup()]
+}
+
+fn index() -> Layout {
+    LayoutBuilder::new("index entry", None)
+        .field("length", FieldSize::Fixed(4), "the length of the entry in the data file, u32 be")
+        .build()
+}
+
+fn record_v1() -> Layout {
+    LayoutBuilder::new("record v1", None)
+        .field("op type", FieldSize::Fixed(1), "1 insert, 2 delete, 3 lock, 4..127 skippable, 128..255 mandatory")
+        .field("timestamp", FieldSize::Fixed(16), "millis, u128 be")
+        .field("key length", FieldSize::Fixed(4), "u32 be")
+        .field("value length", FieldSize::Fixed(4), "u32 be")
+        .field("key", FieldSize::Variable, "key bytes")
+        .field("value", FieldSize::Variable, "value bytes")
+        .build()
+}
+
+fn record_v2() -> Layout {
+    LayoutBuilder::new("record v2", Some(V2))
+        .field("marker", FieldSize::Fixed(1), &format!("always {}", VERSIONED_MARKER))
+        .field("version", FieldSize::Fixed(1), &format!("{}", V2))
+        .field("op type", FieldSize::Fixed(1), "the same as in v1")
+        .field("flags", FieldSize::Fixed(1), &flags())
+        .field("timestamp", FieldSize::Varint { max: 19 }, "millis or seconds according to the flags")
+        .field("expiration", FieldSize::Varint { max: 19 }, "millis, only if the flag is set")
+        .field("content type", FieldSize::Variable, "1 byte (1 json, 2 toml, 3 text, 4 binary, 5 yaml), only if the flag is set")
+        .field("seq", FieldSize::Varint { max: 10 }, "the seq assigned by the log, only if the flag is set")
+        .field("key length", FieldSize::Varint { max: 5 }, "")
+        .field("value length", FieldSize::Varint { max: 5 }, "")
+        .field("key", FieldSize::Variable, "key bytes")
+        .field("value", FieldSize::Variable, "value bytes")
+        .build()
+}
+
+fn record_v3() -> Layout {
+    LayoutBuilder::new("record v3", Some(V3))
+        .field("marker", FieldSize::Fixed(1), &format!("always {}", VERSIONED_MARKER))
+        .field("version", FieldSize::Fixed(1), &format!("{}", V3))
+        .field("op type", FieldSize::Fixed(1), "the same as in v1")
+        .field("flags", FieldSize::Fixed(1), &flags())
+        .field("crc32", FieldSize::Fixed(4), "checksum of all other bytes of the record, u32 be")
+        .field("timestamp", FieldSize::Varint { max: 19 }, "millis or seconds according to the flags")
+        .field("expiration", FieldSize::Varint { max: 19 }, "millis, only if the flag is set")
+        .field("content type", FieldSize::Variable, "1 byte (1 json, 2 toml, 3 text, 4 binary, 5 yaml), only if the flag is set")
+        .field("seq", FieldSize::Varint { max: 10 }, "the seq assigned by the log, only if the flag is set")
+        .field("key length", FieldSize::Varint { max: 5 }, "")
+        .field("value length", FieldSize::Varint { max: 5 }, "")
+        .field("key", FieldSize::Variable, "key bytes")
+        .field("value", FieldSize::Variable, "value bytes")
+        .build()
+}
+
+fn flags() -> String {
+    format!("{} - timestamp in seconds, {} - expiration follows the timestamp, {} - content type follows the expiration, \
+             {} - seq follows the content type",
+            SECONDS_FLAG, EXPIRES_FLAG, CONTENT_TYPE_FLAG, SEQ_FLAG)
+}
+
+fn batch() -> Layout {
+    LayoutBuilder::new("batch", Some(BATCH_KIND))
+        .field("marker", FieldSize::Fixed(1), &format!("always {}", VERSIONED_MARKER))
+        .field("kind", FieldSize::Fixed(1), &format!("{}", BATCH_KIND))
+        .field("count", FieldSize::Fixed(4), "number of records, u32 be")
+        .field("length", FieldSize::Fixed(4), "length of payload, u32 be")
+        .field("crc32", FieldSize::Fixed(4), "checksum of header and payload, u32 be")
+        .field("first seq", FieldSize::Fixed(8), "u64 be")
+        .field("last seq", FieldSize::Fixed(8), "u64 be")
+        .field("payload", FieldSize::Variable, "(record length u32 be, record)*")
+        .build()
+}
+
+fn checksummed_batch() -> Layout {
+    LayoutBuilder::new("checksummed batch", Some(CHECKSUMMED_BATCH_KIND))
+        .field("marker", FieldSize::Fixed(1), &format!("always {}", VERSIONED_MARKER))
+        .field("kind", FieldSize::Fixed(1), &format!("{}", CHECKSUMMED_BATCH_KIND))
+        .field("checksum kind", FieldSize::Fixed(1), "1 crc32, 2 crc32c, 3 xxhash64, 4 rabin")
+        .field("count", FieldSize::Fixed(4), "number of records, u32 be")
+        .field("length", FieldSize::Fixed(4), "length of payload, u32 be")
+        .field("checksum", FieldSize::Fixed(8), "checksum of header and payload, u64 be")
+        .field("first seq", FieldSize::Fixed(8), "u64 be")
+        .field("last seq", FieldSize::Fixed(8), "u64 be")
+        .field("payload", FieldSize::Variable, "(record length u32 be, record)*")
+        .build()
+}
+
+fn encrypted_backup() -> Layout {
+    LayoutBuilder::new("encrypted backup", Some(backup::VERSION))
+        .field("magic", FieldSize::Fixed(backup::MAGIC.len()), &String::from_utf8_lossy(backup::MAGIC))
+        .field("version", FieldSize::Fixed(1), &format!("{}", backup::VERSION))
+        .field("key id length", FieldSize::Fixed(1), "")
+        .field("key id", FieldSize::Variable, "utf8")
+        .field("nonce", FieldSize::Fixed(backup::NONCE_LEN), "")
+        .field("ciphertext", FieldSize::Variable, "AES-256-GCM, the header is authenticated")
+        .build()
+}
+
+impl fmt::Display for FieldSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldSize::Fixed(s) => write!(f, "{}", s),
+            FieldSize::Varint { max } => write!(f, "1..{}", max),
+            FieldSize::Variable => write!(f, "~"),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some(v) => writeln!(f, "{} ({})", self.name, v)?,
+            None => writeln!(f, "{}", self.name)?,
+        }
+        for field in self.fields.iter() {
+            let offset = field.offset.map(|o| o.to_string()).unwrap_or_else(|| String::from("~"));
+            let line = format!("  {:>4} {:>6}  {:<14} {}", offset, field.size.to_string(), field.name, field.description);
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::format::{describe, FieldSize};
+    use crate::store::log::transaction_log::{Record, RecordFormat, TimestampPrecision};
+    use crate::store::log::batch::RecordBatch;
+    use crate::store::log::backup::{encrypt_bytes, StaticKeys};
+    use crate::store::ToBytes;
+    use crate::store::structures::checksum::ChecksumKind;
+
+    #[test]
+    fn describe_test() {
+        let layouts = describe();
+        let layout = |name: &str| layouts.iter().find(|l| l.name == name).unwrap();
+        let len = |name: &str| layout(name).fixed_len();
+
+        let rec = Record::insert_record(vec![1, 2], vec![3]);
+        assert_eq!(len("record v1"), rec.to_bytes().len() - 3);
+        assert_eq!(len("record v2"), 4);
+        assert_eq!(len("record v3"), 8);
+        assert_eq!(rec.with_format(RecordFormat::V3(TimestampPrecision::Millis)).to_bytes()[1], layout("record v3").version.unwrap());
+        assert_eq!(rec.with_format(RecordFormat::V2(TimestampPrecision::Millis)).to_bytes()[1], layout("record v2").version.unwrap());
+
+        let batch = RecordBatch::new(vec![]);
+        assert_eq!(len("batch"), batch.to_bytes().len());
+        let batch = batch.with_checksum(ChecksumKind::Rabin);
+        assert_eq!(len("checksummed batch"), batch.to_bytes().len());
+        assert_eq!(batch.to_bytes()[2], ChecksumKind::Rabin.code());
+        assert_eq!(len("index entry"), 4);
+
+        let keys = StaticKeys::new("k", [1; 32]);
+        let enc = encrypt_bytes(&[], &keys).unwrap();
+        let backup = layout("encrypted backup");
+        assert_eq!(backup.fixed_len(), 10);
+        assert_eq!(backup.fields[3].offset, Some(10));
+        assert_eq!(backup.fields[4].size, FieldSize::Fixed(12));
+        assert_eq!(enc.len(), 10 + 1 + 12 + 16);
+    }
+
+    #[test]
+    fn display_test() {
+        let text = describe()[0].to_string();
+        assert!(text.starts_with("index entry\n"));
+        assert!(text.contains("length"));
+    }
+}
@@ -0,0 +1,72 @@
+//! Hooks of the transaction log write path.
+//! - pre-write hooks get the batch before it is written and can change it (enrichment)
+//!   or veto it returning an error (validation). A pushed record comes as a batch of one record.
+//! - post-commit hooks observe the written batches with their seqs.
+//!
+//! The hooks run in order of registration on the thread pushing the records.
+//! # Examples
+//! ```
+//!  t_log.add_pre_write_hook(|b: RecordBatch| {
+//!     if b.records().iter().any(|r| r.key().starts_with(b"ro/")) {
+//!         return Err(StoreError(String::from("read only")));
+//!     }
+//!     Ok(b)
+//!  });
+//!  t_log.add_post_commit_hook(|b: &RecordBatch| println!("{}..={}", b.first_seq(), b.last_seq()));
+//! ```
+use std::fmt;
+use std::sync::{RwLock, RwLockReadGuard};
+use crate::store::log::batch::RecordBatch;
+use crate::store::StoreResult;
+
+pub type PreWriteHook = Box<dyn Fn(RecordBatch) -> StoreResult<RecordBatch> + Send + Sync>;
+pub type PostCommitHook = Box<dyn Fn(&RecordBatch) + Send + Sync>;
+
+#[derive(Default)]
+pub struct LogHooks {
+    pre_write: RwLock<Vec<PreWriteHook>>,
+    post_commit: RwLock<Vec<PostCommitHook>>,
+}
+
+impl fmt::Debug for LogHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogHooks")
+            .field("pre_write", &read(&self.pre_write).len())
+            .field("post_commit", &read(&self.post_commit).len())
+            .finish()
+    }
+}
+
+impl LogHooks {
+    pub fn add_pre_write(&self, hook: PreWriteHook) {
+        self.pre_write.write().expect("the hooks lock is poisoned").push(hook)
+    }
+
+    pub fn add_post_commit(&self, hook: PostCommitHook) {
+        self.post_commit.write().expect("the hooks lock is poisoned").push(hook)
+    }
+
+    pub fn has_pre_write(&self) -> bool {
+        !read(&self.pre_write).is_empty()
+    }
+
+    pub fn has_post_commit(&self) -> bool {
+        !read(&self.post_commit).is_empty()
+    }
+
+    /// passes the batch through all pre-write hooks.
+    /// Can return `StoreError` if a hook vetoes the batch
+    pub fn pre_write(&self, batch: RecordBatch) -> StoreResult<RecordBatch> {
+        read(&self.pre_write).iter().try_fold(batch, |b, hook| hook(b))
+    }
+
+    pub fn post_commit(&self, batch: &RecordBatch) {
+        for hook in read(&self.post_commit).iter() {
+            hook(batch)
+        }
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().expect("the hooks lock is poisoned")
+}
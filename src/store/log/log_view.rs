@@ -0,0 +1,165 @@
+//! Read-only view of a log or a log backup.
+//! The files (plain or encrypted backups, see `backup`, the sealed segments and the active files)
+//! are read as they are without restoring them over the live log and without taking the lock,
+//! the records are applied to a detached memtable so the state can be inspected
+//! or compared with the live one (see also `store::diff`).
+//! # Examples
+//! ```
+//!  let view = TransactionLog::open_backup(r"c:\projects\configdb\data", Some(&keys))?;
+//!  let old_host = view.get(b"db/host");
+//!  for d in view.diff(table.iter()) {
+//!     println!("{:?}", d);
+//!  }
+//! ```
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::store::{FromBytes, StoreResult, StoreError};
+use crate::store::log::backup::{KeyProvider, decrypt_bytes, key_id};
+use crate::store::log::batch::LogEntry;
+use crate::store::log::transaction_log::{Index, Record};
+use crate::store::memory::memtable::{BaseMemTable, FlushEntry};
+use crate::store::memory::MemTable;
+use crate::store::diff::{KeyDiff, diff_sorted};
+
+pub struct LogView {
+    records: Vec<Record>,
+    table: BaseMemTable<Vec<u8>, Vec<u8>>,
+}
+
+impl LogView {
+    /// reads the pairs of the index and the log files from the oldest to the newest.
+    /// The encrypted files need the provider knowing the key they have been encrypted with
+    pub fn open(files: &[(PathBuf, PathBuf)], keys: Option<&dyn KeyProvider>) -> StoreResult<LogView> {
+        let mut records = vec![];
+        for (idx, log) in files.iter() {
+            records.extend(read_records(idx, log, keys)?);
+        }
+
+        let table = BaseMemTable::new(u64::MAX);
+        for r in records.iter() {
+            table.apply(r);
+        }
+        Ok(LogView { records, table })
+    }
+
+    /// the records of the files in the order they have been written
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// the value of the key after the last record
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.table.find(key.to_vec())
+    }
+
+    /// the keys and values after the last record in order
+    pub fn iter(&self) -> impl Iterator<Item=(Vec<u8>, Vec<u8>)> {
+        self.table.iter()
+    }
+
+    pub fn scan_prefix(&self, prefix: &[u8]) -> impl Iterator<Item=(Vec<u8>, Vec<u8>)> {
+        self.table.scan_prefix(prefix)
+    }
+
+    /// the last versions of the keys in order with seqs and tombstones, see `store::iter`.
+    /// The records of v1 keep no seqs so they are numbered from 0 in order of the view
+    pub fn entries(&self) -> impl Iterator<Item=FlushEntry<Vec<u8>, Vec<u8>>> {
+        self.table.flush_iter()
+    }
+
+    /// compares the view (the old state) with the keys and values of the live store
+    /// # Returns
+    /// the differing keys in order
+    pub fn diff<I>(&self, live: I) -> Vec<KeyDiff> where I: IntoIterator<Item=(Vec<u8>, Vec<u8>)> {
+        let live: BTreeMap<Vec<u8>, Vec<u8>> = live.into_iter().collect();
+        diff_sorted(self.iter(), live)
+    }
+}
+
+fn read_records(idx: &Path, log: &Path, keys: Option<&dyn KeyProvider>) -> StoreResult<Vec<Record>> {
+    let idx_bytes = read_backup_file(idx, keys)?;
+    let log_bytes = read_backup_file(log, keys)?;
+
+    let mut records = vec![];
+    let mut pos = 0;
+    for i in Index::from_bytes_array(&idx_bytes)? {
+        let end = pos + i.get_value() as usize;
+        if end > log_bytes.len() {
+            return Err(StoreError(format!("the log {:?} is shorter than the index: {} < {}", log, log_bytes.len(), end)));
+        }
+        records.extend(LogEntry::from_bytes(&log_bytes[pos..end])?.into_records());
+        pos = end;
+    }
+    if pos != log_bytes.len() {
+        return Err(StoreError(format!("the log {:?} has {} bytes out of the index", log, log_bytes.len() - pos)));
+    }
+    Ok(records)
+}
+
+fn read_backup_file(p: &Path, keys: Option<&dyn KeyProvider>) -> StoreResult<Vec<u8>> {
+    match (key_id(p)?, keys) {
+        (None, _) => Ok(fs::read(p)?),
+        (Some(_), Some(keys)) => decrypt_bytes(&fs::read(p)?, keys),
+        (Some(id), None) => Err(StoreError(format!("the backup {:?} is encrypted with the key {}", p, id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::transaction_log::{TransactionLog, Record};
+    use crate::store::log::backup::StaticKeys;
+    use crate::store::diff::KeyDiff;
+    use crate::store::log::batch::RecordBatch;
+    use crate::store::memory::memtable::BaseMemTable;
+    use crate::store::memory::MemTable;
+    use std::fs;
+
+    #[test]
+    fn open_backup_test() {
+        let dir = r"test_data\open_backup";
+        let t_log = TransactionLog::create(dir).unwrap();
+        t_log.push(&Record::insert_record(b"host".to_vec(), b"a".to_vec())).unwrap();
+        t_log.push_batch(&RecordBatch::new(vec![
+            Record::insert_record(b"port".to_vec(), b"80".to_vec()),
+            Record::insert_record(b"user".to_vec(), b"root".to_vec()),
+        ])).unwrap();
+        t_log.push(&Record::delete_record(b"user".to_vec(), vec![])).unwrap();
+        t_log.backup().unwrap();
+        t_log.push(&Record::insert_record(b"host".to_vec(), b"b".to_vec())).unwrap();
+
+        let view = TransactionLog::open_backup(dir, None).unwrap();
+        assert_eq!(view.records().len(), 4);
+        assert_eq!(view.get(b"host"), Some(b"a".to_vec()));
+        assert_eq!(view.get(b"user"), None);
+        assert_eq!(view.iter().count(), 2);
+
+        let live: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        live.put(b"host".to_vec(), b"b".to_vec()).unwrap();
+        live.put(b"port".to_vec(), b"80".to_vec()).unwrap();
+        live.put(b"user".to_vec(), b"admin".to_vec()).unwrap();
+        assert_eq!(view.diff(live.iter()), vec![
+            KeyDiff::Changed { key: b"host".to_vec(), old: b"a".to_vec(), new: b"b".to_vec() },
+            KeyDiff::Added(b"user".to_vec(), b"admin".to_vec()),
+        ]);
+        assert_eq!(view.diff(vec![]).len(), 2);
+        t_log.remove_files().unwrap();
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn open_encrypted_backup_test() {
+        let dir = r"test_data\open_encrypted_backup";
+        let keys = StaticKeys::new("k1", [7; 32]);
+        let t_log = TransactionLog::create(dir).unwrap();
+        t_log.push(&Record::insert_record(b"host".to_vec(), b"a".to_vec())).unwrap();
+        t_log.backup_encrypted(&keys).unwrap();
+
+        assert!(TransactionLog::open_backup(dir, None).is_err());
+        assert!(TransactionLog::open_backup(dir, Some(&StaticKeys::new("k2", [7; 32]))).is_err());
+        let view = TransactionLog::open_backup(dir, Some(&keys)).unwrap();
+        assert_eq!(view.get(b"host"), Some(b"a".to_vec()));
+        t_log.remove_files().unwrap();
+        let _ = fs::remove_dir_all(dir);
+    }
+}
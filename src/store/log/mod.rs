@@ -1 +1,11 @@
-pub mod transaction_log;
\ No newline at end of file
+pub mod transaction_log;
+pub mod clock;
+pub mod tail;
+pub mod sync;
+pub mod backup;
+pub mod log_view;
+pub mod varint;
+pub mod batch;
+pub mod format;
+pub mod segment;
+pub mod hooks;
\ No newline at end of file
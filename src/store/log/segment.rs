@@ -0,0 +1,320 @@
+//! Size based rotation of the transaction log.
+//! The entries are appended to the active files (`log_idx.cfgdb`, `log_data.cfgdb`).
+//! When the active log exceeds `LogOptions::segment_size` the files get sealed
+//! by renaming them to `log_idx.N.cfgdb`, `log_data.N.cfgdb` and the new active files are created.
+//! The sealed segments can be listed and deleted when their records are not needed anymore
+//! (e.g. they have been flushed to the tables).
+//! Every segment keeps the range of timestamps of its records
+//! so the reading of the records since some time skips the older segments.
+//! # Examples
+//! ```
+//!  let opts = LogOptions { segment_size: Some(64 * 1024 * 1024), ..LogOptions::default() };
+//!  let t_log = TransactionLog::create_with(dir, opts)?;
+//!  for s in t_log.segments() { println!("{} {}..{}", s.number, s.first_seq, s.next_seq) }
+//!  t_log.delete_segments_before(flushed_seq)?;
+//! ```
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::fs::{File, rename, remove_file, read_dir};
+use crate::store::files::sync_dir;
+use crate::store::{StoreResult, StoreError};
+
+/// the sealed part of the log
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// the segments are numbered from 1 in order of sealing
+    pub number: u64,
+    pub idx: PathBuf,
+    pub log: PathBuf,
+    /// the seq of the first record
+    pub first_seq: u64,
+    /// the seq following the last record
+    pub next_seq: u64,
+    /// the number of the first entry from the start of the log
+    pub first_entry: u64,
+    pub entries: u64,
+    /// the timestamps of the oldest and the newest record in millis
+    pub min_ts: u128,
+    pub max_ts: u128,
+}
+
+/// the sealed segments and the active files
+#[derive(Debug)]
+pub(crate) struct Segments {
+    dir: PathBuf,
+    idx: PathBuf,
+    log: PathBuf,
+    size: Option<u64>,
+    state: Mutex<SegmentsState>,
+}
+
+#[derive(Debug)]
+struct SegmentsState {
+    sealed: Vec<Segment>,
+    /// the active segment
+    first_seq: u64,
+    first_entry: u64,
+    entries: u64,
+    bytes: u64,
+    number: u64,
+    /// the seq following the last appended record
+    next_seq: u64,
+    min_ts: Option<u128>,
+    max_ts: u128,
+}
+
+impl Segments {
+    /// # Arguments
+    /// * `size` the size of the active log (entries and index) after which it is sealed
+    pub fn new(dir: PathBuf, idx: PathBuf, log: PathBuf, size: Option<u64>) -> Self {
+        let state = SegmentsState {
+            sealed: vec![],
+            first_seq: 0,
+            first_entry: 0,
+            entries: 0,
+            bytes: 0,
+            number: 1,
+            next_seq: 0,
+            min_ts: None,
+            max_ts: 0,
+        };
+        Segments { dir, idx, log, size, state: Mutex::new(state) }
+    }
+
+    /// appends the entry by `write` keeping the active files in place until it is written.
+    /// The appends are serialized so `write` can allocate the seqs of the entry
+    /// # Arguments
+    /// * `ts` the timestamps of the oldest and the newest record of the entry
+    /// * `write` returns the size of the entry and the seq following its last record
+    pub fn append<F>(&self, ts: (u128, u128), write: F) -> StoreResult<usize>
+        where F: FnOnce(&Path, &Path) -> StoreResult<(usize, u64)> {
+        let mut state = self.lock();
+        let (r, next_seq) = write(&self.idx, &self.log)?;
+        state.next_seq = next_seq;
+        state.entries += 1;
+        state.bytes += r as u64 + 4;
+        state.min_ts = Some(state.min_ts.map(|m| m.min(ts.0)).unwrap_or(ts.0));
+        state.max_ts = state.max_ts.max(ts.1);
+        Ok(r)
+    }
+
+    /// seals the active files if they exceed the size.
+    /// The segment ends with the last appended record
+    /// # Arguments
+    /// * `sync` flushes the active files before they are renamed
+    /// # Returns
+    /// true if the segment has been sealed
+    pub fn rotate_if_full<F>(&self, sync: F) -> StoreResult<bool>
+        where F: FnOnce() -> StoreResult<()> {
+        let mut state = self.lock();
+        let next_seq = state.next_seq;
+        match self.size {
+            Some(size) if state.bytes >= size && state.entries > 0 => (),
+            _ => return Ok(false),
+        }
+        sync()?;
+        let number = state.number;
+        let segment = Segment {
+            number,
+            idx: self.sealed_path(&self.idx, number)?,
+            log: self.sealed_path(&self.log, number)?,
+            first_seq: state.first_seq,
+            next_seq,
+            first_entry: state.first_entry,
+            entries: state.entries,
+            min_ts: state.min_ts.unwrap_or(0),
+            max_ts: state.max_ts,
+        };
+        rename(&self.idx, &segment.idx)?;
+        rename(&self.log, &segment.log)?;
+        File::create(&self.log)?;
+        File::create(&self.idx)?;
+        sync_dir(&self.dir)?;
+
+        state.first_seq = next_seq;
+        state.first_entry += state.entries;
+        state.entries = 0;
+        state.bytes = 0;
+        state.number += 1;
+        state.min_ts = None;
+        state.max_ts = 0;
+        state.sealed.push(segment);
+        Ok(true)
+    }
+
+    /// the number of the first entry which has not been deleted
+    pub fn first_entry(&self) -> u64 {
+        let state = self.lock();
+        state.sealed.first().map(|s| s.first_entry).unwrap_or(state.first_entry)
+    }
+
+    /// the first entry and the first seq of the segment containing the record with the seq
+    pub fn locate_seq(&self, seq: u64) -> StoreResult<(u64, u64)> {
+        let state = self.lock();
+        if seq >= state.first_seq {
+            return Ok((state.first_entry, state.first_seq));
+        }
+        match state.sealed.iter().find(|s| seq >= s.first_seq && seq < s.next_seq) {
+            Some(s) => Ok((s.first_entry, s.first_seq)),
+            None => Err(StoreError(format!("the segment of the seq {} has been deleted", seq))),
+        }
+    }
+
+    /// the first entry of the oldest segment having the records newer or equal to the timestamp.
+    /// The older segments are skipped
+    pub fn locate_time(&self, ts: u128) -> u64 {
+        let state = self.lock();
+        state.sealed.iter()
+            .find(|s| s.max_ts >= ts)
+            .map(|s| s.first_entry)
+            .unwrap_or(state.first_entry)
+    }
+
+    pub fn sealed(&self) -> Vec<Segment> {
+        self.lock().sealed.clone()
+    }
+
+    /// deletes the sealed segments having only the records with seqs less than `seq`
+    /// # Returns
+    /// the deleted segments
+    pub fn delete_before(&self, seq: u64) -> StoreResult<Vec<Segment>> {
+        let mut state = self.lock();
+        let n = state.sealed.iter().take_while(|s| s.next_seq <= seq).count();
+        let deleted: Vec<Segment> = state.sealed.drain(..n).collect();
+        for s in deleted.iter() {
+            remove_file(&s.idx)?;
+            remove_file(&s.log)?;
+        }
+        if !deleted.is_empty() {
+            sync_dir(&self.dir)?;
+        }
+        Ok(deleted)
+    }
+
+    /// reads the entry (the number from the start of the log) from the segment containing it.
+    /// The segments are not rotated while `read` works
+    /// # Arguments
+    /// * `read` gets the files, the number of the entry in the segment and the number of the segment
+    pub fn read_entry<F, R>(&self, entry: u64, read: F) -> StoreResult<R>
+        where F: FnOnce(&Path, &Path, u64, u64) -> StoreResult<R> {
+        let state = self.lock();
+        if entry >= state.first_entry {
+            return read(&self.idx, &self.log, entry - state.first_entry, state.number);
+        }
+        match state.sealed.iter().find(|s| entry >= s.first_entry && entry < s.first_entry + s.entries) {
+            Some(s) => read(&s.idx, &s.log, entry - s.first_entry, s.number),
+            None => Err(StoreError(format!("the segment of the entry {} has been deleted", entry))),
+        }
+    }
+
+    /// the files of the active and then sealed segments (newest first) kept in place while `read` works
+    pub fn read_files<F, R>(&self, read: F) -> StoreResult<R>
+        where F: FnOnce(Vec<(&Path, &Path)>) -> StoreResult<R> {
+        let state = self.lock();
+        let mut files = vec![(self.idx.as_path(), self.log.as_path())];
+        files.extend(state.sealed.iter().rev().map(|s| (s.idx.as_path(), s.log.as_path())));
+        read(files)
+    }
+
+    pub fn remove_files(&self) -> std::io::Result<()> {
+        for s in self.lock().sealed.drain(..) {
+            remove_file(&s.idx)?;
+            remove_file(&s.log)?;
+        }
+        Ok(())
+    }
+
+    /// removes the sealed segments left by the previous log in the directory
+    pub fn remove_stale(&self) -> StoreResult<()> {
+        let names = [self.idx.file_name(), self.log.file_name()];
+        for entry in read_dir(&self.dir)? {
+            let path = entry?.path();
+            if names.iter().any(|n| is_sealed_of(&path, n.and_then(|n| n.to_str()))) {
+                remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sealed_path(&self, active: &Path, number: u64) -> StoreResult<PathBuf> {
+        match (active.file_stem().and_then(|s| s.to_str()), active.extension().and_then(|e| e.to_str())) {
+            (Some(stem), Some(ext)) => Ok(self.dir.join(format!("{}.{}.{}", stem, number, ext))),
+            _ => Err(StoreError(format!("the log file {:?} has no extension", active))),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SegmentsState> {
+        self.state.lock().expect("the segments lock is poisoned")
+    }
+}
+
+/// `log_data.12.cfgdb` is a sealed segment of `log_data.cfgdb`
+fn is_sealed_of(path: &Path, active: Option<&str>) -> bool {
+    sealed_number(path, active).is_some()
+}
+
+/// the number of the sealed segment (12 for `log_data.12.cfgdb` of `log_data.cfgdb`)
+fn sealed_number(path: &Path, active: Option<&str>) -> Option<u64> {
+    let (name, active) = match (path.file_name().and_then(|n| n.to_str()), active) {
+        (Some(n), Some(a)) => (n, a),
+        _ => return None,
+    };
+    let (stem, ext) = active.split_once('.')?;
+    name.strip_prefix(stem)
+        .and_then(|r| r.strip_prefix('.'))
+        .and_then(|r| r.strip_suffix(ext))
+        .and_then(|r| r.strip_suffix('.'))
+        .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|n| n.parse().ok())
+}
+
+/// the index and log files of the sealed segments left in the directory from the oldest to the newest.
+/// The files are found by names so the log does not need to be opened
+pub(crate) fn sealed_files(dir: &Path, idx: &str, log: &str) -> StoreResult<Vec<(PathBuf, PathBuf)>> {
+    let mut numbers = vec![];
+    for entry in read_dir(dir)? {
+        if let Some(n) = sealed_number(&entry?.path(), Some(log)) {
+            numbers.push(n);
+        }
+    }
+    numbers.sort_unstable();
+    let name = |active: &str, n: u64| match active.split_once('.') {
+        Some((stem, ext)) => dir.join(format!("{}.{}.{}", stem, n, ext)),
+        None => dir.join(active),
+    };
+    Ok(numbers.into_iter().map(|n| (name(idx, n), name(log, n))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::segment::{is_sealed_of, sealed_files};
+    use std::path::Path;
+    use std::fs;
+
+    #[test]
+    fn sealed_name_test() {
+        let active = Some("log_data.cfgdb");
+        assert!(is_sealed_of(Path::new("dir/log_data.1.cfgdb"), active));
+        assert!(is_sealed_of(Path::new("log_data.120.cfgdb"), active));
+        assert!(!is_sealed_of(Path::new("log_data.cfgdb"), active));
+        assert!(!is_sealed_of(Path::new("log_data..cfgdb"), active));
+        assert!(!is_sealed_of(Path::new("log_data.1a.cfgdb"), active));
+        assert!(!is_sealed_of(Path::new("log_idx.1.cfgdb"), active));
+        assert!(!is_sealed_of(Path::new("log_data.1.cfgdb.bck"), active));
+    }
+
+    #[test]
+    fn sealed_files_test() {
+        let dir = r"test_data\sealed_files";
+        fs::create_dir_all(dir).unwrap();
+        for name in ["log_data.10.cfgdb", "log_idx.10.cfgdb", "log_data.2.cfgdb", "log_idx.2.cfgdb", "log_data.cfgdb", "log_data.x.cfgdb"].iter() {
+            fs::write(Path::new(dir).join(name), b"").unwrap();
+        }
+        let files = sealed_files(Path::new(dir), "log_idx.cfgdb", "log_data.cfgdb").unwrap();
+        assert_eq!(files, vec![
+            (Path::new(dir).join("log_idx.2.cfgdb"), Path::new(dir).join("log_data.2.cfgdb")),
+            (Path::new(dir).join("log_idx.10.cfgdb"), Path::new(dir).join("log_data.10.cfgdb")),
+        ]);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
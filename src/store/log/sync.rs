@@ -0,0 +1,355 @@
+//! Durability control for the transaction log.
+//! `Durability` defines when the log files are flushed to the disk:
+//! - never (the os decides)
+//! - after every write
+//! - periodically by a background ticker every N millis or after M written bytes
+//! - in groups: the writers wait until their records are synced and one sync covers the concurrent writes
+//!
+//! In the periodic mode the writes are acknowledged before they are synced,
+//! the size of this window can be checked through `SyncStats`.
+//! In the group mode the first waiting writer (the leader) waits for other writes up to the delay
+//! or until the group is full and then syncs the files for all of them.
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use std::thread;
+use log::error;
+use crate::store::files::sync_file;
+use crate::store::StoreResult;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Durability {
+    /// the files are not synced explicitly
+    None,
+    /// the files are synced after every write
+    PerWrite,
+    /// the files are synced by the background ticker
+    /// every `interval` or when `bytes` have been written since the last sync
+    Periodic { interval: Duration, bytes: u64 },
+    /// the writes are acknowledged after they are synced, the concurrent writes share one sync.
+    /// The sync waits up to `max_delay` for `max_records` records to gather
+    Group { max_delay: Duration, max_records: u64 },
+}
+
+/// the writes which have been acknowledged but not synced yet
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SyncStats {
+    pub unsynced_bytes: u64,
+    pub unsynced_records: u64,
+    pub syncs: u64,
+}
+
+#[derive(Debug)]
+struct SyncState {
+    stats: SyncStats,
+    stopped: bool,
+    /// the number of records written and covered by the finished syncs
+    written: u64,
+    synced: u64,
+    /// the leader of the group is gathering or syncing the records
+    syncing: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    files: Vec<PathBuf>,
+    state: Mutex<SyncState>,
+    cond: Condvar,
+    /// one sync runs at a time so the same pending writes are not subtracted twice
+    sync_lock: Mutex<()>,
+}
+
+/// syncs the files according to `Durability`
+#[derive(Debug)]
+pub struct LogSyncer {
+    durability: Durability,
+    shared: Arc<Shared>,
+    ticker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Shared {
+    fn sync(&self) -> StoreResult<()> {
+        let _sync = self.sync_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (pending, upto) = {
+            let state = self.state.lock().expect("the sync lock is poisoned");
+            (state.stats, state.written)
+        };
+        if pending.unsynced_records == 0 {
+            return Ok(());
+        }
+        for f in self.files.iter() {
+            sync_file(f.as_path())?;
+        }
+        let mut state = self.state.lock().expect("the sync lock is poisoned");
+        state.stats.unsynced_bytes = state.stats.unsynced_bytes.saturating_sub(pending.unsynced_bytes);
+        state.stats.unsynced_records = state.stats.unsynced_records.saturating_sub(pending.unsynced_records);
+        state.stats.syncs += 1;
+        state.synced = state.synced.max(upto);
+        Ok(())
+    }
+
+    /// waits until the record `ticket` is synced leading the group if nobody does
+    fn sync_group(&self, ticket: u64, max_delay: Duration, max_records: u64) -> StoreResult<()> {
+        let mut state = self.state.lock().expect("the sync lock is poisoned");
+        loop {
+            if state.synced >= ticket {
+                return Ok(());
+            }
+            if !state.syncing {
+                state.syncing = true;
+                let deadline = Instant::now() + max_delay;
+                while state.stats.unsynced_records < max_records {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    state = self.cond.wait_timeout(state, deadline - now).expect("the sync lock is poisoned").0;
+                }
+                drop(state);
+                let res = self.sync();
+                self.state.lock().expect("the sync lock is poisoned").syncing = false;
+                self.cond.notify_all();
+                return res;
+            }
+            state = self.cond.wait(state).expect("the sync lock is poisoned");
+        }
+    }
+
+    fn tick(&self, interval: Duration, bytes: u64) {
+        loop {
+            {
+                let state = self.state.lock().expect("the sync lock is poisoned");
+                let (state, _) = self.cond
+                    .wait_timeout_while(state, interval, |s| !s.stopped && s.stats.unsynced_bytes < bytes)
+                    .expect("the sync lock is poisoned");
+                if state.stopped {
+                    return;
+                }
+            }
+            if let Err(e) = self.sync() {
+                error!("the background sync of the log failed: {}", e.0);
+            }
+        }
+    }
+}
+
+impl LogSyncer {
+    /// creates the syncer for the files.
+    /// For `Durability::Periodic` it starts the background ticker
+    pub fn new(files: Vec<PathBuf>, durability: Durability) -> Self {
+        let shared = Arc::new(Shared {
+            files,
+            state: Mutex::new(SyncState { stats: SyncStats::default(), stopped: false, written: 0, synced: 0, syncing: false }),
+            cond: Condvar::new(),
+            sync_lock: Mutex::new(()),
+        });
+        let ticker = match durability {
+            Durability::Periodic { interval, bytes } => {
+                let sh = shared.clone();
+                Some(thread::spawn(move || sh.tick(interval, bytes)))
+            }
+            _ => None,
+        };
+        LogSyncer { durability, shared, ticker: Mutex::new(ticker) }
+    }
+
+    /// registers the written record and syncs the files if the durability requires that.
+    /// For `Durability::Group` it returns when the record is synced
+    pub fn written(&self, bytes: u64) -> StoreResult<()> {
+        let ticket = {
+            let mut state = self.shared.state.lock().expect("the sync lock is poisoned");
+            state.stats.unsynced_bytes += bytes;
+            state.stats.unsynced_records += 1;
+            state.written += 1;
+            state.written
+        };
+        match self.durability {
+            Durability::None => Ok(()),
+            Durability::PerWrite => self.shared.sync(),
+            Durability::Periodic { .. } => {
+                self.shared.cond.notify_all();
+                Ok(())
+            }
+            Durability::Group { max_delay, max_records } => {
+                self.shared.cond.notify_all();
+                self.shared.sync_group(ticket, max_delay, max_records)
+            }
+        }
+    }
+
+    /// syncs all pending writes now
+    pub fn sync(&self) -> StoreResult<()> {
+        self.shared.sync()
+    }
+
+    pub fn stats(&self) -> SyncStats {
+        self.shared.state.lock().expect("the sync lock is poisoned").stats
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// stops the background ticker if it exists.
+    /// It is called from drop so the poisoned locks are not a reason to panic again
+    pub fn stop(&self) {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner()).stopped = true;
+        self.shared.cond.notify_all();
+        if let Some(t) = self.ticker.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = t.join();
+        }
+    }
+}
+
+impl Drop for LogSyncer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::sync::{LogSyncer, Durability};
+    use std::path::PathBuf;
+    use std::fs::{File, remove_file};
+    use std::time::{Duration, Instant};
+    use std::thread;
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn none_test() {
+        let syncer = LogSyncer::new(vec![], Durability::None);
+        syncer.written(10).unwrap();
+        syncer.written(10).unwrap();
+        assert_eq!(syncer.stats().unsynced_bytes, 20);
+        assert_eq!(syncer.stats().unsynced_records, 2);
+        assert_eq!(syncer.stats().syncs, 0);
+    }
+
+    #[test]
+    fn per_write_test() {
+        let p = PathBuf::from("sync_per_write.data");
+        File::create(p.as_path()).unwrap();
+        let syncer = LogSyncer::new(vec![p.clone()], Durability::PerWrite);
+        syncer.written(10).unwrap();
+        assert_eq!(syncer.stats().unsynced_bytes, 0);
+        assert_eq!(syncer.stats().syncs, 1);
+        let _ = remove_file(p);
+    }
+
+    #[test]
+    fn per_write_concurrent_test() {
+        let p = PathBuf::from("sync_per_write_concurrent.data");
+        File::create(p.as_path()).unwrap();
+        let syncer = Arc::new(LogSyncer::new(vec![p.clone()], Durability::PerWrite));
+        let writers: Vec<_> = (0..8).map(|_| {
+            let syncer = syncer.clone();
+            thread::spawn(move || {
+                for _ in 0..300 {
+                    syncer.written(10).unwrap();
+                }
+            })
+        }).collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        let stats = syncer.stats();
+        assert_eq!((stats.unsynced_bytes, stats.unsynced_records), (0, 0));
+        assert!(stats.syncs > 0 && stats.syncs <= 2400, "{}", stats.syncs);
+        let _ = remove_file(p);
+    }
+
+    #[test]
+    fn periodic_explicit_sync_test() {
+        let p = PathBuf::from("sync_periodic_explicit.data");
+        File::create(p.as_path()).unwrap();
+        let durability = Durability::Periodic { interval: Duration::from_millis(1), bytes: 1 };
+        let syncer = Arc::new(LogSyncer::new(vec![p.clone()], durability));
+        let writers: Vec<_> = (0..4).map(|i| {
+            let syncer = syncer.clone();
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    syncer.written(10).unwrap();
+                    if i % 2 == 0 {
+                        syncer.sync().unwrap();
+                    }
+                }
+            })
+        }).collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        syncer.sync().unwrap();
+        let stats = syncer.stats();
+        assert_eq!((stats.unsynced_bytes, stats.unsynced_records), (0, 0));
+        syncer.stop();
+        let _ = remove_file(p);
+    }
+
+    #[test]
+    fn periodic_bytes_test() {
+        let p = PathBuf::from("sync_periodic_bytes.data");
+        File::create(p.as_path()).unwrap();
+        let durability = Durability::Periodic { interval: Duration::from_secs(60), bytes: 100 };
+        let syncer = LogSyncer::new(vec![p.clone()], durability);
+        syncer.written(50).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(syncer.stats().unsynced_bytes, 50);
+
+        syncer.written(50).unwrap();
+        for _ in 0..100 {
+            if syncer.stats().syncs > 0 { break; }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(syncer.stats().unsynced_bytes, 0);
+        syncer.stop();
+        let _ = remove_file(p);
+    }
+
+    #[test]
+    fn group_test() {
+        let p = PathBuf::from("sync_group.data");
+        File::create(p.as_path()).unwrap();
+        let durability = Durability::Group { max_delay: Duration::from_secs(5), max_records: 8 };
+        let syncer = Arc::new(LogSyncer::new(vec![p.clone()], durability));
+        let barrier = Arc::new(Barrier::new(8));
+        let start = Instant::now();
+        let writers: Vec<_> = (0..8).map(|_| {
+            let (syncer, barrier) = (syncer.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                syncer.written(10).unwrap()
+            })
+        }).collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(syncer.stats().syncs, 1);
+        assert_eq!(syncer.stats().unsynced_records, 0);
+
+        let single = LogSyncer::new(vec![p.clone()], Durability::Group { max_delay: Duration::from_millis(20), max_records: 8 });
+        let start = Instant::now();
+        single.written(10).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(single.stats().syncs, 1);
+        let _ = remove_file(p);
+    }
+
+    #[test]
+    fn periodic_interval_test() {
+        let p = PathBuf::from("sync_periodic_interval.data");
+        File::create(p.as_path()).unwrap();
+        let durability = Durability::Periodic { interval: Duration::from_millis(10), bytes: u64::MAX };
+        let syncer = LogSyncer::new(vec![p.clone()], durability);
+        syncer.written(1).unwrap();
+        for _ in 0..100 {
+            if syncer.stats().syncs > 0 { break; }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(syncer.stats().unsynced_records, 0);
+        drop(syncer);
+        let _ = remove_file(p);
+    }
+}
@@ -0,0 +1,322 @@
+//! Tailing of the transaction log.
+//! `TailIterator` follows the log from a given record and waits for the new ones
+//! getting notified by `TransactionLog::push` through `LogProgress`
+//! so the consumers (replication, cdc) do not need to poll file sizes.
+//! The iterator goes over records expanding batches,
+//! `next_entry_timeout` reads whole entries to keep the batch boundaries.
+//! The positions go through the segments of the log, the sealed segments are read
+//! until they are deleted.
+//! # Examples
+//! ```
+//!  let mut tail = t_log.tail(0);
+//!  while let Some(rec) = tail.next_timeout(Duration::from_millis(100))? {
+//!     // handle record
+//!  }
+//! ```
+use std::sync::{Mutex, Condvar, Arc};
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::path::Path;
+use crate::store::files::{read_slice, read_all_file_bytes};
+use crate::store::log::transaction_log::{Index, Record};
+use crate::store::log::batch::{LogEntry, RecordBatch};
+use crate::store::log::segment::Segments;
+use crate::store::{StoreResult, StoreError};
+
+/// the number of pushed entries shared between the log and the tailing iterators
+#[derive(Debug, Default)]
+pub struct LogProgress {
+    state: Mutex<ProgressState>,
+    cond: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    entries: u64,
+    closed: bool,
+}
+
+impl LogProgress {
+    pub fn advance(&self) {
+        self.state.lock().expect("the progress lock is poisoned").entries += 1;
+        self.cond.notify_all();
+    }
+
+    pub fn close(&self) {
+        self.state.lock().expect("the progress lock is poisoned").closed = true;
+        self.cond.notify_all();
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.state.lock().expect("the progress lock is poisoned").entries
+    }
+
+    /// waits until the log has more than `pos` entries or gets closed
+    /// # Returns
+    /// true if the entry `pos` is available
+    fn wait_for(&self, pos: u64, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.state.lock().expect("the progress lock is poisoned");
+        while state.entries <= pos && !state.closed {
+            state = match deadline {
+                None => self.cond.wait(state).expect("the progress lock is poisoned"),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return false;
+                    }
+                    self.cond.wait_timeout(state, d - now).expect("the progress lock is poisoned").0
+                }
+            }
+        }
+        state.entries > pos
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().expect("the progress lock is poisoned").closed
+    }
+}
+
+/// iterator following the log from the given entry.
+/// The position is the number of the entry (a record or a batch) from the start of the log
+pub struct TailIterator {
+    segments: Arc<Segments>,
+    progress: Arc<LogProgress>,
+    next: u64,
+    /// the number of the segment and the offset of the next entry in it
+    offset: Option<(u64, u64)>,
+    pending: VecDeque<Record>,
+    /// the read has failed so the blocking iteration is over
+    failed: bool,
+}
+
+impl TailIterator {
+    pub(crate) fn new(segments: Arc<Segments>, progress: Arc<LogProgress>, from: u64) -> Self {
+        TailIterator { segments, progress, next: from, offset: None, pending: VecDeque::new(), failed: false }
+    }
+
+    /// the position of the next entry to read.
+    /// The records of a partially read batch are not counted
+    pub fn position(&self) -> u64 {
+        self.next
+    }
+
+    /// reads the next record without blocking
+    /// # Returns
+    /// `None` if there is no new record yet (would block)
+    pub fn try_next(&mut self) -> StoreResult<Option<Record>> {
+        if let Some(r) = self.pending.pop_front() {
+            return Ok(Some(r));
+        }
+        if self.progress.entries() <= self.next {
+            return Ok(None);
+        }
+        self.read_next().map(Some)
+    }
+
+    /// waits for the next record not longer than `timeout`
+    /// # Returns
+    /// `None` if the timeout elapsed.
+    /// `StoreError` if the log has been closed and all records have been read
+    pub fn next_timeout(&mut self, timeout: Duration) -> StoreResult<Option<Record>> {
+        if let Some(r) = self.pending.pop_front() {
+            return Ok(Some(r));
+        }
+        match self.next_entry_timeout(timeout)? {
+            Some(e) => {
+                self.pending.extend(e.into_records());
+                Ok(self.pending.pop_front())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// waits for the next entry not longer than `timeout`.
+    /// The records of a partially read batch are returned as a batch first
+    /// # Returns
+    /// `None` if the timeout elapsed.
+    /// `StoreError` if the log has been closed and all entries have been read
+    pub fn next_entry_timeout(&mut self, timeout: Duration) -> StoreResult<Option<LogEntry>> {
+        if !self.pending.is_empty() {
+            let rest: Vec<Record> = self.pending.drain(..).collect();
+            return Ok(Some(LogEntry::Batch(RecordBatch::new(rest))));
+        }
+        if self.progress.wait_for(self.next, Some(timeout)) {
+            return self.read_next_entry().map(Some);
+        }
+        if self.progress.is_closed() {
+            return Err(StoreError(String::from("the log is closed")));
+        }
+        Ok(None)
+    }
+
+    fn read_next(&mut self) -> StoreResult<Record> {
+        let entry = self.read_next_entry()?;
+        self.pending.extend(entry.into_records());
+        self.pending.pop_front().ok_or_else(|| StoreError(String::from("the batch is empty")))
+    }
+
+    fn read_next_entry(&mut self) -> StoreResult<LogEntry> {
+        let cached = self.offset;
+        let (segment, offset, entry) = self.segments.read_entry(self.next, |idx, log, local, segment| {
+            let offset = match cached {
+                Some((s, o)) if s == segment => o,
+                _ => find_offset(idx, local)?,
+            };
+            let len = read_slice::<Index>(idx, local * 4, 4)?.get_value() as u64;
+            let entry = read_slice::<LogEntry>(log, offset, len)?;
+            Ok((segment, offset + len, entry))
+        })?;
+        self.next += 1;
+        self.offset = Some((segment, offset));
+        Ok(entry)
+    }
+}
+
+/// the offset of the entry `pos` in the segment
+fn find_offset(idx: &Path, pos: u64) -> StoreResult<u64> {
+    if pos == 0 {
+        return Ok(0);
+    }
+    let bytes = read_all_file_bytes(idx)?;
+    let end = (pos * 4) as usize;
+    if bytes.len() < end {
+        return Err(StoreError(format!("the index has less than {} entries", pos)));
+    }
+    Ok(Index::from_bytes_array(&bytes[..end])?
+        .iter()
+        .map(|i| i.get_value() as u64)
+        .sum())
+}
+
+/// blocks until the next record is pushed.
+/// Ends when the log is closed and all records have been read
+/// or after the first error (the broken entry would fail again)
+impl Iterator for TailIterator {
+    type Item = StoreResult<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(r) = self.pending.pop_front() {
+            return Some(Ok(r));
+        }
+        if self.failed || !self.progress.wait_for(self.next, None) {
+            return None;
+        }
+        let next = self.read_next();
+        self.failed = next.is_err();
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::transaction_log::{TransactionLog, Record};
+    use crate::store::log::batch::{RecordBatch, LogEntry};
+    use std::time::Duration;
+    use std::thread;
+    use std::path::Path;
+    use std::fs;
+
+    #[test]
+    fn try_next_test() {
+        let t_log = TransactionLog::create(r"test_data\tail_try_next").unwrap();
+        let mut tail = t_log.tail(0);
+        assert!(tail.try_next().unwrap().is_none());
+
+        let rec = Record::insert_record(vec![1, 2], vec![3]);
+        t_log.push(&rec).unwrap();
+        assert_eq!(tail.try_next().unwrap(), Some(rec));
+        assert!(tail.try_next().unwrap().is_none());
+        assert_eq!(tail.position(), 1);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn tail_from_middle_test() {
+        let t_log = TransactionLog::create(r"test_data\tail_middle").unwrap();
+        for i in 1..10 {
+            t_log.push(&Record::insert_record(vec![i; i as usize], vec![i])).unwrap();
+        }
+        let mut tail = t_log.tail(5);
+        let rec = tail.next_timeout(Duration::from_millis(10)).unwrap().unwrap();
+        assert_eq!(rec, Record::insert_record(vec![6; 6], vec![6]).with_timestamp_millis(rec.timestamp_millis()));
+        assert_eq!(tail.position(), 6);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn follow_test() {
+        let t_log = TransactionLog::create(r"test_data\tail_follow").unwrap();
+        let tail = t_log.tail(0);
+        let reader = thread::spawn(move || {
+            tail.map(|r| r.unwrap().size_in_bytes()).collect::<Vec<u32>>()
+        });
+
+        for i in 1..=10 {
+            t_log.push(&Record::insert_record(vec![1; i], vec![])).unwrap();
+        }
+        t_log.close().unwrap();
+
+        let sizes = reader.join().unwrap();
+        assert_eq!(sizes, (1..=10).map(|i| 25 + i).collect::<Vec<u32>>());
+        let _ = t_log.remove_files();
+    }
+
+    #[test]
+    fn corrupted_test() {
+        let dir = r"test_data\tail_corrupted";
+        let t_log = TransactionLog::create(dir).unwrap();
+        let mut tail = t_log.tail(0);
+        for i in 0..3 {
+            t_log.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+        }
+        let idx = Path::new(dir).join("log_idx.cfgdb");
+        let len = fs::metadata(&idx).unwrap().len() as usize;
+        fs::write(&idx, vec![0xff; len]).unwrap();
+
+        assert!(matches!(tail.next(), Some(Err(_))));
+        assert!(tail.next().is_none());
+        assert_eq!(tail.position(), 0);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn timeout_test() {
+        let t_log = TransactionLog::create(r"test_data\tail_timeout").unwrap();
+        let mut tail = t_log.tail(0);
+        assert!(tail.next_timeout(Duration::from_millis(10)).unwrap().is_none());
+        t_log.close().unwrap();
+        assert!(tail.next_timeout(Duration::from_millis(10)).is_err());
+        let _ = t_log.remove_files();
+    }
+
+    #[test]
+    fn batch_test() {
+        let t_log = TransactionLog::create(r"test_data\tail_batch").unwrap();
+        let mut tail = t_log.tail(0);
+        t_log.push(&Record::insert_record(vec![1], vec![])).unwrap();
+        t_log.push_batch(&RecordBatch::new(vec![
+            Record::insert_record(vec![2], vec![]),
+            Record::insert_record(vec![3], vec![]),
+        ])).unwrap();
+
+        let keys: Vec<Vec<u8>> = (0..3)
+            .map(|_| tail.try_next().unwrap().unwrap().key().to_vec())
+            .collect();
+        assert_eq!(keys, vec![vec![1], vec![2], vec![3]]);
+        assert!(tail.try_next().unwrap().is_none());
+        assert_eq!(tail.position(), 2);
+
+        let mut entries = t_log.tail(0);
+        let timeout = Duration::from_millis(10);
+        assert!(matches!(entries.next_entry_timeout(timeout).unwrap(), Some(LogEntry::Single(_))));
+        match entries.next_entry_timeout(timeout).unwrap() {
+            Some(LogEntry::Batch(b)) => {
+                assert_eq!(b.count(), 2);
+                assert_eq!(b.first_seq(), 1);
+            }
+            e => panic!("expected the batch, got {:?}", e),
+        }
+        t_log.remove_files().unwrap();
+    }
+}
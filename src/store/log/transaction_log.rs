@@ -1,18 +1,66 @@
 use std::convert::TryInto;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::ops::RangeInclusive;
+use log::warn;
+use std::time::{SystemTime, UNIX_EPOCH, Duration};
 use std::io::Error;
 use std::path::PathBuf;
 use crate::store::files::*;
 use std::io;
-use std::fs::{File, remove_file};
+use std::fs::{File, OpenOptions, remove_file};
 use crate::store::{ToBytes, FromBytes, StoreResult, StoreError};
+use crate::store::log::clock::{MonotonicClock, SkewPolicy};
+use crate::store::log::tail::{LogProgress, TailIterator};
+use crate::store::log::sync::{LogSyncer, Durability, SyncStats};
+use crate::store::log::backup::{KeyProvider, encrypt_file, reencrypt_file};
+use crate::store::log::log_view::LogView;
+use crate::store::log::varint::{write_varint, read_varint, varint_len};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::store::log::batch::{RecordBatch, LogEntry, BatchLimits, is_batch};
+use crate::store::structures::checksum::ChecksumKind;
+use crate::store::log::segment::{Segments, Segment, sealed_files};
+use crate::store::log::hooks::LogHooks;
+use std::path::Path;
+use std::collections::HashSet;
+use crate::store::memory::content_type::ContentType;
 
 
 static LOCK_FILE: &str = "log.lock";
 static IDX_FILE_NAME: &str = "log_idx.cfgdb";
 static LOG_FILE_NAME: &str = "log_data.cfgdb";
 static BACKUP_EXT: &str = "cfgdb.bck";
+static CLOCK_FILE_NAME: &str = "log_ts.cfgdb";
+
+/// options for creating `TransactionLog`
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// behaviour when a record comes with a timestamp less than the last pushed one
+    pub skew_policy: SkewPolicy,
+    /// when the files are synced to the disk
+    pub durability: Durability,
+    /// the format the records are written in
+    pub format: RecordFormat,
+    /// the limits of batches pushed by `TransactionLog::push_batch`
+    pub batch_limits: BatchLimits,
+    /// the checksum of batches
+    pub checksum: ChecksumKind,
+    /// the size of the log in bytes after which it is sealed and a new segment is started.
+    /// None means the log is never rotated
+    pub segment_size: Option<u64>,
+}
 
+impl Default for LogOptions {
+    fn default() -> Self {
+        LogOptions {
+            skew_policy: SkewPolicy::Clamp,
+            durability: Durability::None,
+            format: RecordFormat::V1,
+            batch_limits: BatchLimits::default(),
+            checksum: ChecksumKind::default(),
+            segment_size: None,
+        }
+    }
+}
 
 /// default struct including into itself index and log
 #[derive(Debug)]
@@ -20,6 +68,16 @@ pub struct TransactionLog {
     idx: PathBuf,
     log: PathBuf,
     lock: PathBuf,
+    watermark: PathBuf,
+    clock: MonotonicClock,
+    progress: Arc<LogProgress>,
+    syncer: LogSyncer,
+    format: RecordFormat,
+    batch_limits: BatchLimits,
+    checksum: ChecksumKind,
+    next_seq: AtomicU64,
+    segments: Arc<Segments>,
+    hooks: LogHooks,
 }
 
 impl Drop for TransactionLog {
@@ -29,13 +87,25 @@ impl Drop for TransactionLog {
 }
 
 impl TransactionLog {
-    pub fn close(&self) -> io::Result<()> {
-        remove_file(&self.lock)
+    /// syncs the log and the timestamp watermark and releases the lock file.
+    /// The tailing iterators get finished
+    pub fn close(&self) -> StoreResult<()> {
+        self.progress.close();
+        self.syncer.stop();
+        if self.log.exists() {
+            self.syncer.sync()?;
+        }
+        remove_file(&self.lock)?;
+        Ok(())
     }
 
     pub fn remove_files(&self) -> io::Result<()> {
+        self.segments.remove_files()?;
         remove_file(&self.idx)?;
         remove_file(&self.log)?;
+        if self.watermark.exists() {
+            remove_file(&self.watermark)?;
+        }
         remove_file(&self.lock)?;
         Ok(())
     }
@@ -67,6 +137,12 @@ impl TransactionLog {
     /// ```
     ///
     pub fn create(dir_str: &str) -> StoreResult<Self> {
+        TransactionLog::create_with(dir_str, LogOptions::default())
+    }
+
+    /// create a new commit log with the given options
+    /// see `TransactionLog::create`
+    pub fn create_with(dir_str: &str, opts: LogOptions) -> StoreResult<Self> {
         let dir = {
             let dir = PathBuf::from(dir_str);
             if dir.is_file() {
@@ -78,32 +154,117 @@ impl TransactionLog {
             dir
         };
 
-        Ok(TransactionLog {
-            lock: {
-                let mut lock = PathBuf::from(dir.clone());
-                lock.push(LOCK_FILE);
-                if lock.exists() {
-                    return Err(StoreError(String::from(format!("lock file for {} exists", dir_str))));
-                }
+        // the lock is taken before anything in the directory is touched
+        // so the log which is in use is left as it is
+        let mut lock = dir.clone();
+        lock.push(LOCK_FILE);
+        match OpenOptions::new().write(true).create_new(true).open(lock.as_path()) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(StoreError(format!("lock file for {} exists", dir_str)));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        TransactionLog::create_locked(dir, lock.clone(), opts).inspect_err(|_| {
+            let _ = remove_file(&lock);
+        })
+    }
+
+    fn create_locked(dir: PathBuf, lock: PathBuf, opts: LogOptions) -> StoreResult<Self> {
+        let mut watermark = dir.clone();
+        watermark.push(CLOCK_FILE_NAME);
+        let clock = MonotonicClock::load(watermark.as_path(), opts.skew_policy)?;
+        clock.persist(watermark.as_path())?;
+
+        let mut log = dir.clone();
+        log.push(LOG_FILE_NAME);
+        let mut idx = dir.clone();
+        idx.push(IDX_FILE_NAME);
+        let segments = Segments::new(dir.clone(), idx.clone(), log.clone(), opts.segment_size);
+        segments.remove_stale()?;
+        File::create(log.as_path())?;
+        File::create(idx.as_path())?;
+        sync_dir(dir.as_path())?;
+        let syncer = LogSyncer::new(vec![idx.clone(), log.clone(), watermark.clone()], opts.durability);
 
-                File::create(lock.as_path())?;
-                lock
-            },
-            log: {
-                let mut log = PathBuf::from(dir.clone());
-                log.push(LOG_FILE_NAME);
-                File::create(log.as_path())?;
-                log
-            },
-            idx: {
-                let mut idx = PathBuf::from(dir.clone());
-                idx.push(IDX_FILE_NAME);
-                File::create(idx.as_path())?;
-                idx
-            },
+        Ok(TransactionLog {
+            idx,
+            log,
+            lock,
+            watermark,
+            clock,
+            progress: Arc::new(LogProgress::default()),
+            syncer,
+            format: opts.format,
+            batch_limits: opts.batch_limits,
+            checksum: opts.checksum,
+            next_seq: AtomicU64::new(0),
+            hooks: LogHooks::default(),
+            segments: Arc::new(segments),
         })
     }
+
     pub fn backup(&self) -> StoreResult<()> {
+        let (idx_bk, log_bk) = self.backup_paths()?;
+        copy_file(self.log.as_path(), log_bk.as_path())?;
+        copy_file(self.idx.as_path(), idx_bk.as_path())
+    }
+
+    /// the same as `TransactionLog::backup` but the backup files are encrypted
+    /// with the current key of the provider. see `backup::encrypt_file`
+    pub fn backup_encrypted(&self, keys: &dyn KeyProvider) -> StoreResult<()> {
+        let (idx_bk, log_bk) = self.backup_paths()?;
+        encrypt_file(self.log.as_path(), log_bk.as_path(), keys)?;
+        encrypt_file(self.idx.as_path(), idx_bk.as_path(), keys)?;
+        sync_new_file(log_bk.as_path())?;
+        sync_new_file(idx_bk.as_path())?;
+        Ok(())
+    }
+
+    /// re-encrypts the existing backup files with the current key of the provider.
+    /// The provider should know the key the backup has been encrypted with
+    pub fn rotate_backup_key(&self, keys: &dyn KeyProvider) -> StoreResult<()> {
+        let (idx_bk, log_bk) = self.backup_paths()?;
+        if reencrypt_file(log_bk.as_path(), keys)? {
+            sync_file(log_bk.as_path())?;
+        }
+        if reencrypt_file(idx_bk.as_path(), keys)? {
+            sync_file(idx_bk.as_path())?;
+        }
+        Ok(())
+    }
+
+    /// opens the backup of the log in the directory read-only, see `LogView`.
+    /// The live log in the directory is not touched and can be opened at the same time
+    /// # Arguments
+    /// * `keys` the provider for the encrypted backups, not needed for the plain ones
+    pub fn open_backup(dir_str: &str, keys: Option<&dyn KeyProvider>) -> StoreResult<LogView> {
+        let mut idx_bk = PathBuf::from(dir_str);
+        idx_bk.push(IDX_FILE_NAME);
+        idx_bk.set_extension(BACKUP_EXT);
+        let mut log_bk = PathBuf::from(dir_str);
+        log_bk.push(LOG_FILE_NAME);
+        log_bk.set_extension(BACKUP_EXT);
+        if !idx_bk.exists() || !log_bk.exists() {
+            return Err(StoreError(format!("the backup in {} does not exist", dir_str)));
+        }
+        LogView::open(&[(idx_bk, log_bk)], keys)
+    }
+
+    /// opens the log in the directory read-only (the sealed segments and the active files), see `LogView`.
+    /// The lock is not taken so the log can be written by another process at the same time
+    pub fn open_view(dir_str: &str) -> StoreResult<LogView> {
+        let dir = PathBuf::from(dir_str);
+        let (idx, log) = (dir.join(IDX_FILE_NAME), dir.join(LOG_FILE_NAME));
+        if !idx.exists() || !log.exists() {
+            return Err(StoreError(format!("the log in {} does not exist", dir_str)));
+        }
+        let mut files = sealed_files(dir.as_path(), IDX_FILE_NAME, LOG_FILE_NAME)?;
+        files.push((idx, log));
+        LogView::open(&files, None)
+    }
+
+    fn backup_paths(&self) -> StoreResult<(PathBuf, PathBuf)> {
         let idx = &self.idx;
         let log = &self.log;
         if !idx.exists() || !log.exists() {
@@ -115,15 +276,203 @@ impl TransactionLog {
 
         idx_bk.set_extension(BACKUP_EXT);
         log_bk.set_extension(BACKUP_EXT);
+        Ok((idx_bk, log_bk))
+    }
 
-        copy_file(log.as_path(), log_bk.as_path())?;
-        copy_file(idx.as_path(), idx_bk.as_path())
+    /// appends the record to the log.
+    /// The timestamp of the record is checked against the last pushed one
+    /// and either clamped or rejected according to `SkewPolicy`.
+    /// The record is written in the format of the log.
+    /// The record gets the next seq of the log, the versioned formats (v2, v3) keep it in the header.
+    /// The pre-write hooks get the record as a batch of one record and should keep it single
+    /// # Returns
+    /// the seq of the record
+    pub fn push(&self, record: &Record) -> StoreResult<u64> {
+        let record = if self.hooks.has_pre_write() {
+            let mut records = self.hooks.pre_write(RecordBatch::new(vec![record.clone()]))?.into_records();
+            match (records.pop(), records.is_empty()) {
+                (Some(r), true) => self.prepare(&r)?,
+                _ => return Err(StoreError(String::from("the pre-write hooks should keep the pushed record single"))),
+            }
+        } else {
+            self.prepare(record)?
+        };
+        let mut entry = LogEntry::Single(record);
+        let seq = self.append(&mut entry)?;
+        self.rotate_if_full()?;
+        if self.hooks.has_post_commit() {
+            self.hooks.post_commit(&RecordBatch::new(entry.into_records()).with_first_seq(seq));
+        }
+        Ok(seq)
+    }
+
+    /// appends the records of the batch to the log as one entry.
+    /// The records get stamped and converted like in `TransactionLog::push`
+    /// and the batch gets the seq of its first record.
+    /// The records of the batch do not keep the seqs, they are restored from the batch header.
+    /// The batch exceeding `BatchLimits` is split into several entries if it is allowed.
+    /// The pre-write hooks get the batch before the split,
+    /// the post-commit hooks get every written entry
+    /// # Returns
+    /// the batches which have been written
+    pub fn push_batch(&self, batch: &RecordBatch) -> StoreResult<Vec<RecordBatch>> {
+        let batch = if self.hooks.has_pre_write() { self.hooks.pre_write(batch.clone())? } else { batch.clone() };
+        if batch.is_empty() {
+            return Err(StoreError(String::from("the batch is empty")));
+        }
+        let mut records = Vec::with_capacity(batch.count());
+        for r in batch.records() {
+            records.push(self.prepare(r)?);
+        }
+        let mut written = vec![];
+        for b in RecordBatch::new(records).with_checksum(self.checksum).split(&self.batch_limits)? {
+            let mut entry = LogEntry::Batch(b);
+            self.append(&mut entry)?;
+            self.rotate_if_full()?;
+            if let LogEntry::Batch(b) = entry {
+                self.hooks.post_commit(&b);
+                written.push(b);
+            }
+        }
+        Ok(written)
+    }
+
+    /// registers the hook which can change or veto the records before they are written.
+    /// see `hooks::LogHooks`
+    pub fn add_pre_write_hook<F>(&self, hook: F)
+        where F: Fn(RecordBatch) -> StoreResult<RecordBatch> + Send + Sync + 'static {
+        self.hooks.add_pre_write(Box::new(hook))
+    }
+
+    /// registers the hook observing the written batches with their seqs
+    pub fn add_post_commit_hook<F>(&self, hook: F)
+        where F: Fn(&RecordBatch) + Send + Sync + 'static {
+        self.hooks.add_post_commit(Box::new(hook))
+    }
+
+    /// the seq the next pushed record gets
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq.load(Ordering::SeqCst)
+    }
+
+    fn prepare(&self, record: &Record) -> StoreResult<Record> {
+        if self.format == RecordFormat::V1 {
+            if record.expires_at.is_some() {
+                return Err(StoreError(String::from("the format v1 can not keep the expiration time")));
+            }
+            if record.content_type.is_some() {
+                return Err(StoreError(String::from("the format v1 can not keep the content type")));
+            }
+        }
+        let ts = self.clock.stamp_persisted(record.timestamp, &self.watermark)?;
+        let mut record = if ts != record.timestamp || self.format != record.format {
+            record.with_timestamp_millis(ts).with_format(self.format)
+        } else {
+            record.clone()
+        };
+        record.seq = None;
+        Ok(record)
+    }
+
+    /// the sealed segments from the oldest to the newest.
+    /// see `LogOptions::segment_size`
+    pub fn segments(&self) -> Vec<Segment> {
+        self.segments.sealed()
+    }
+
+    /// deletes the sealed segments which have only the records with seqs less than `seq`
+    /// (e.g. the records flushed to the tables)
+    /// # Returns
+    /// the deleted segments
+    pub fn delete_segments_before(&self, seq: u64) -> StoreResult<Vec<Segment>> {
+        self.segments.delete_before(seq)
+    }
+
+    fn rotate_if_full(&self) -> StoreResult<bool> {
+        self.segments.rotate_if_full(|| self.syncer.sync())
+    }
+
+    /// appends the entry with the next seqs of the log.
+    /// The seqs are taken under the lock of the segments so the entries are written in order of seqs
+    /// # Returns
+    /// the seq of the (first) record
+    fn append(&self, entry: &mut LogEntry) -> StoreResult<u64> {
+        let count = entry.count() as u64;
+        let mut first_seq = 0;
+        let r = self.segments.append(entry.time_range(), |idx, log| {
+            first_seq = self.next_seq.load(Ordering::SeqCst);
+            entry.set_first_seq(first_seq);
+            append_item(idx, &Index::create(entry.size_in_bytes()))?;
+            let r = append_item(log, &*entry)?;
+            self.next_seq.store(first_seq + count, Ordering::SeqCst);
+            Ok((r, first_seq + count))
+        })?;
+        self.syncer.written(r as u64 + 4)?;
+        self.progress.advance();
+        Ok(first_seq)
+    }
+
+    /// syncs all pushed records to the disk regardless `Durability`
+    pub fn sync(&self) -> StoreResult<()> {
+        self.syncer.sync()
+    }
+
+    /// the records which have been pushed but not synced yet
+    pub fn sync_stats(&self) -> SyncStats {
+        self.syncer.stats()
+    }
+
+    /// follows the log starting from the record `from` (the number from the start of the log)
+    /// see `TailIterator`
+    pub fn tail(&self, from: u64) -> TailIterator {
+        TailIterator::new(self.segments.clone(), self.progress.clone(), from)
+    }
+
+    /// the records from the start of the log (the oldest segment which has not been deleted)
+    /// to the last pushed one. The batches are expanded into records.
+    /// The iteration stops after the first error
+    pub fn iter(&self) -> impl Iterator<Item=StoreResult<Record>> {
+        self.iter_from(self.segments.first_entry())
+    }
+
+    /// the records with the timestamp newer or equal to `ts` (millis) in order.
+    /// The sealed segments having only older records are not read.
+    /// The iteration stops after the first error
+    pub fn iter_since(&self, ts: u128) -> impl Iterator<Item=StoreResult<Record>> {
+        self.iter_from(self.segments.locate_time(ts))
+            .filter(move |r| r.as_ref().map(|r| r.timestamp_millis() >= ts).unwrap_or(true))
+    }
+
+    fn iter_from(&self, entry: u64) -> impl Iterator<Item=StoreResult<Record>> {
+        let mut tail = self.tail(entry);
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let next = tail.try_next().transpose();
+            failed = matches!(next, Some(Err(_)));
+            next
+        })
     }
-    pub fn push(&self, record: &Record) -> StoreResult<usize> {
-        let index = &Index::create(record.size_in_bytes());
-        append_item(&self.idx, index)?;
-        let r = append_item(&self.log, record)?;
-        Ok(r)
+
+    /// follows the log starting from the record with the seq.
+    /// The records which have been written are read from the segments first
+    /// then the iterator waits for the new ones. see `TailIterator`
+    /// Can return `StoreError` if the segment of the seq has been deleted
+    /// or the seq has not been given to a record yet
+    pub fn tail_from_seq(&self, seq: u64) -> StoreResult<TailIterator> {
+        if seq > self.next_seq() {
+            return Err(StoreError(format!("the seq {} is ahead of the log {}", seq, self.next_seq())));
+        }
+        let (entry, first_seq) = self.segments.locate_seq(seq)?;
+        let mut tail = self.tail(entry);
+        for s in first_seq..seq {
+            if tail.try_next()?.is_none() {
+                return Err(StoreError(format!("the record {} has not been written yet", s)));
+            }
+        }
+        Ok(tail)
     }
 
     /// read list of records from the end according a position
@@ -131,38 +480,48 @@ impl TransactionLog {
     ///* `number_from_end` the position relative to the end. Should be more or equal 1
     /// Can return `StoreError` if number less 1
     pub fn read_all_from_end(&self, number_from_end: usize) -> StoreResult<Vec<Record>> {
-        let mut r_start_pos = 0;
-        let mut r_number: u64;
-        let mut records: Vec<Record> = Vec::new();
+        self.replay_from_end(number_from_end).map(|r| r.records)
+    }
 
-        for i in 1..=number_from_end {
-            let pos: u64 = i as u64 * 4;
-            match read_slice_from_end::<Index>(self.idx.as_path(), pos, 4) {
-                Ok(idx) => {
-                    let vl = idx.get_value() as u64;
-                    r_start_pos += vl;
-                    r_number = vl;
-                    match read_slice_from_end::<Record>(self.log.as_path(), r_start_pos, r_number) {
-                        Ok(r) => records.push(r),
-                        Err(e) => return Err(e),
-                    }
+    /// read list of records from the end according a position
+    /// skipping the records with unknown types written by newer versions.
+    /// The batches are expanded into records, the newest record goes first.
+    /// The entries are read through the active and then the sealed segments.
+    /// The final entry which has not been written completely (torn) is skipped.
+    /// The records with a seq which has been read already (e.g. written twice by a retry) are skipped.
+    /// The skipped records and the torn entry are reported in `ReplayReport`.
+    /// Can return `StoreError` if a record has a type which can not be skipped
+    /// # Arguments
+    /// * `number_from_end` the number of entries relative to the end. Should be more or equal 1
+    pub fn replay_from_end(&self, number_from_end: usize) -> StoreResult<ReplayReport> {
+        self.segments.read_files(|files| {
+            let mut report = ReplayReport { records: vec![], skipped: vec![], duplicates: 0, torn_tail: false };
+            let mut read = 0;
+            let mut seen = HashSet::new();
+            for (i, (idx, log)) in files.into_iter().enumerate() {
+                if read == number_from_end {
+                    break;
                 }
-                Err(e) => return Err(e),
+                read += replay_segment(idx, log, number_from_end - read, read, i == 0, &mut seen, &mut report)?;
             }
-        }
-        Ok(records)
+            if read < number_from_end {
+                return Err(StoreError(format!("the log has only {} entries", read)));
+            }
+            Ok(report)
+        })
     }
 
-    /// read record from the end according a position
+    /// read record from the end of the active segment according a position
+    /// Can return `StoreError` if number less 1 or the entry is a batch
     /// # Arguments
     ///* `number_from_end` the position relative to the end. Should be more or equal 1
-    /// Can return `StoreError` if number less 1
     pub fn read_from_end(&self, pos_from_end: usize) -> StoreResult<Record> {
         let mut r_start_pos = 0;
         let mut r_number: u64 = 0;
+        let mut idx_reader = FileReader::open(self.idx.as_path())?;
         for i in 1..=pos_from_end {
             let pos: u64 = i as u64 * 4;
-            match read_slice_from_end::<Index>(self.idx.as_path(), pos, 4) {
+            match idx_reader.read_item_from_end::<Index>(pos, 4) {
                 Ok(idx) => {
                     let vl = idx.get_value() as u64;
                     r_start_pos += vl;
@@ -179,6 +538,77 @@ impl TransactionLog {
     }
 }
 
+/// reads not more than `n` entries from the end of the segment into the report.
+/// Only the final entry of the active segment can be torn
+/// # Returns
+/// the number of entries which have been read
+fn replay_segment(idx: &Path, log: &Path, n: usize, read_before: usize, active: bool,
+                  seen: &mut HashSet<u64>, report: &mut ReplayReport) -> StoreResult<usize> {
+    let entries = Index::from_bytes_array(&std::fs::read(idx)?)?;
+    let number = n.min(entries.len());
+
+    let expected: u64 = entries.iter().map(|i| i.get_value() as u64).sum();
+    let mut reader = FileReader::open(log)?;
+    let actual = reader.len();
+    let mut r_start_pos = 0;
+    if actual < expected {
+        let last = entries.last().map(|i| i.get_value() as u64).unwrap_or(0);
+        if !active || actual + last < expected {
+            return Err(StoreError(format!("the log is shorter than the index by {} bytes", expected - actual)));
+        }
+        r_start_pos = actual + last - expected;
+    }
+
+    for i in 1..=number {
+        let idx = &entries[entries.len() - i];
+        let vl = idx.get_value() as u64;
+        let torn_allowed = active && i == 1;
+        if torn_allowed && actual < expected {
+            warn!("skipped the torn entry at the end of the log");
+            report.torn_tail = true;
+            continue;
+        }
+        r_start_pos += vl;
+        let raw = reader.read_item_from_end::<RawEntry>(r_start_pos, vl)?;
+        let records = if is_batch(&raw.0) {
+            match RecordBatch::from_bytes(&raw.0) {
+                Ok(b) => b.into_records(),
+                Err(e) if torn_allowed => {
+                    warn!("skipped the torn batch at the end of the log: {}", e.0);
+                    report.torn_tail = true;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            match Record::from_bytes(&raw.0) {
+                Ok(r) => vec![r],
+                Err(e) if torn_allowed && e.is_corruption() => {
+                    warn!("skipped the damaged record at the end of the log: {}", e.0);
+                    report.torn_tail = true;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        for r in records.into_iter().rev() {
+            match r.operation {
+                RecordType::Unknown(op) => {
+                    let pos_from_end = read_before + i;
+                    warn!("skipped a record with unknown type {} at position {} from the end", op, pos_from_end);
+                    report.skipped.push(SkippedRecord { pos_from_end, op, size: r.size_in_bytes() })
+                }
+                _ => match r.seq {
+                    Some(seq) if !seen.insert(seq) => report.duplicates += 1,
+                    _ => report.records.push(r),
+                },
+            }
+        }
+    }
+    Ok(number)
+}
+
 /// default record for index file for commit log.
 /// It consists of ints(u32) meaning the length of record in commit log
 #[derive(PartialEq, Debug)]
@@ -186,17 +616,102 @@ pub struct Index {
     val: u32
 }
 
+/// the op codes reserved for future operations (merge, range tombstone, checkpoint, namespace ops)
+/// which can be safely skipped by the versions not knowing them
+pub const SKIPPABLE_OPS: RangeInclusive<u8> = 4..=127;
+/// the op codes reserved for future operations which change the state in a way
+/// that can not be ignored. The versions not knowing them refuse to read the log
+pub const MANDATORY_OPS: RangeInclusive<u8> = 128..=255;
+
 /// commit log type
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum RecordType {
     Insert,
     Delete,
     Lock,
+    /// an op from `SKIPPABLE_OPS` unknown to this version
+    Unknown(u8),
 }
 
+impl RecordType {
+    pub fn code(&self) -> u8 {
+        match self {
+            RecordType::Insert => 1,
+            RecordType::Delete => 2,
+            RecordType::Lock => 3,
+            RecordType::Unknown(op) => *op,
+        }
+    }
+
+    pub fn from_code(op: u8) -> StoreResult<RecordType> {
+        match op {
+            1 => Ok(RecordType::Insert),
+            2 => Ok(RecordType::Delete),
+            3 => Ok(RecordType::Lock),
+            op if SKIPPABLE_OPS.contains(&op) => Ok(RecordType::Unknown(op)),
+            op => Err(StoreError(format!("the record type {} is not supported", op))),
+        }
+    }
+}
+
+/// the result of reading the log with records which are skipped
+#[derive(Debug)]
+pub struct ReplayReport {
+    pub records: Vec<Record>,
+    pub skipped: Vec<SkippedRecord>,
+    /// the number of records skipped since their seqs have been read already
+    pub duplicates: usize,
+    /// the final entry has not been written completely and has been skipped
+    pub torn_tail: bool,
+}
+
+/// the bytes of a log entry read as is
+struct RawEntry(Vec<u8>);
+
+impl FromBytes for RawEntry {
+    fn from_bytes(bytes: &[u8]) -> StoreResult<Self> {
+        Ok(RawEntry(bytes.to_vec()))
+    }
+}
+
+/// the record which has been skipped since the type is unknown
+#[derive(Debug, PartialEq)]
+pub struct SkippedRecord {
+    pub pos_from_end: usize,
+    pub op: u8,
+    pub size: u32,
+}
+
+/// precision of the timestamp in the v2 record format
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TimestampPrecision {
+    Millis,
+    Seconds,
+}
+
+/// binary format of the record
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RecordFormat {
+    /// the fixed header of 25 bytes
+    V1,
+    /// the header with varints, see `README.md`
+    V2(TimestampPrecision),
+    /// the v2 header with the crc32 of the record
+    V3(TimestampPrecision),
+}
+
+/// the first byte of versioned records. It is never used as an op code
+pub(crate) const VERSIONED_MARKER: u8 = 0;
+pub(crate) const V2: u8 = 2;
+pub(crate) const V3: u8 = 3;
+pub(crate) const SECONDS_FLAG: u8 = 1;
+pub(crate) const EXPIRES_FLAG: u8 = 2;
+pub(crate) const CONTENT_TYPE_FLAG: u8 = 4;
+pub(crate) const SEQ_FLAG: u8 = 8;
+
 /// commit log record. This record saves the information before other operation for preventing data loss
 /// the header consists of ts(current time), op type RecordType, key length and val length
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Record {
     timestamp: u128,
     operation: RecordType,
@@ -204,32 +719,84 @@ pub struct Record {
     val_len: u32,
     key: Vec<u8>,
     val: Vec<u8>,
+    format: RecordFormat,
+    /// the expiration time in millis, the v1 format does not keep it
+    expires_at: Option<u128>,
+    /// the content type of the value, the v1 format does not keep it
+    content_type: Option<ContentType>,
+    /// the seq assigned by the log, the v1 format does not keep it
+    seq: Option<u64>,
 }
 
 impl ToBytes for Record {
     /// serializing op
-    /// # Order
+    /// # Order (v1)
     /// - the first byte is operation see `RecordType`
-    /// - then 8 bytes is timestamp
+    /// - then 16 bytes is timestamp
     /// - then 4 bytes is key length
     /// - then 4 bytes is val length
     /// - then key array
     /// - then val array
+    ///
+    /// # Order (v2)
+    /// - the marker byte 0 and the version byte 2
+    /// - then the byte of operation
+    /// - then the byte of flags (1 - seconds precision, 2 - the expiration time follows the timestamp,
+    ///   4 - the content type byte follows the timestamp and the expiration time,
+    ///   8 - the seq follows the content type)
+    /// - then varints of timestamp, expiration time in millis (if the flag is set),
+    ///   the byte of content type (if the flag is set), varint of seq (if the flag is set),
+    ///   varints of key length and val length
+    /// - then key array
+    /// - then val array
+    ///
+    /// # Order (v3)
+    /// the same as v2 with the version byte 3 and the crc32 (4 bytes) after the flags
+    /// computed over all other bytes of the record
     fn to_bytes(&self) -> Vec<u8> {
-        let op: u8 =
-            match self.operation {
-                RecordType::Insert => 1,
-                RecordType::Delete => 2,
-                RecordType::Lock => 3,
-            };
-
-        let mut bytes = vec![op];
-        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
-        bytes.extend_from_slice(&self.key_len.to_be_bytes());
-        bytes.extend_from_slice(&self.val_len.to_be_bytes());
+        let mut bytes = match self.format {
+            RecordFormat::V1 => {
+                let mut bytes = vec![self.operation.code()];
+                bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+                bytes.extend_from_slice(&self.key_len.to_be_bytes());
+                bytes.extend_from_slice(&self.val_len.to_be_bytes());
+                bytes
+            }
+            RecordFormat::V2(precision) | RecordFormat::V3(precision) => {
+                let version = if let RecordFormat::V3(_) = self.format { V3 } else { V2 };
+                let mut flags = if precision == TimestampPrecision::Seconds { SECONDS_FLAG } else { 0 };
+                if self.expires_at.is_some() {
+                    flags |= EXPIRES_FLAG;
+                }
+                if self.content_type.is_some() {
+                    flags |= CONTENT_TYPE_FLAG;
+                }
+                if self.seq.is_some() {
+                    flags |= SEQ_FLAG;
+                }
+                let mut bytes = vec![VERSIONED_MARKER, version, self.operation.code(), flags];
+                write_varint(self.encoded_timestamp(), &mut bytes);
+                if let Some(e) = self.expires_at {
+                    write_varint(e, &mut bytes);
+                }
+                if let Some(ct) = self.content_type {
+                    bytes.push(ct.code());
+                }
+                if let Some(seq) = self.seq {
+                    write_varint(seq as u128, &mut bytes);
+                }
+                write_varint(self.key_len as u128, &mut bytes);
+                write_varint(self.val_len as u128, &mut bytes);
+                bytes
+            }
+        };
         bytes.extend_from_slice(&self.key);
         bytes.extend_from_slice(&self.val);
 
+        if let RecordFormat::V3(_) = self.format {
+            let crc = crc32fast::hash(&bytes);
+            bytes.splice(4..4, crc.to_be_bytes());
+        }
         bytes
     }
 }
@@ -246,12 +813,7 @@ impl FromBytes for Record {
     /// * `bytes` - bytes array to deserialize
     ///
     /// # Order
-    /// - the first byte is operation see `RecordType`
-    /// - then 8 bytes is timestamp
-    /// - then 4 bytes is key length
-    /// - then 4 bytes is val length
-    /// - then key array
-    /// - then val array
+    /// see `Record::to_bytes`. The v2 records start with the zero byte
     ///
     /// # Returns
     /// `Result` with Record or `StoreError`
@@ -259,21 +821,25 @@ impl FromBytes for Record {
         if bytes.is_empty() {
             return Err(StoreError(String::from(" bytes are empty")));
         }
+        if bytes[0] == VERSIONED_MARKER {
+            return Record::from_versioned_bytes(bytes);
+        }
 
-        let operation: RecordType = match bytes.get(0) {
-            Some(1) => RecordType::Insert,
-            Some(2) => RecordType::Delete,
-            Some(3) => RecordType::Lock,
-            _ => panic!("the first byte should be either 1 or 2 or 3")
-        };
+        if bytes.len() < 25 {
+            return Err(StoreError(String::from("the record is shorter than the header")));
+        }
+        let operation = RecordType::from_code(bytes[0])?;
 
         let timestamp = convert_128(&bytes[1..17]);
         let key_len = convert_32(&bytes[17..21]);
         let val_len = convert_32(&bytes[21..25]);
+        if bytes.len() as u64 != 25 + key_len as u64 + val_len as u64 {
+            return Err(StoreError(String::from("the record length does not match the header")));
+        }
         let key = bytes[25..25 + key_len as usize].to_vec();
         let val = bytes[25 + key_len as usize..].to_vec();
 
-        Ok(Record { timestamp, operation, key_len, val_len, key, val })
+        Ok(Record { timestamp, operation, key_len, val_len, key, val, format: RecordFormat::V1, expires_at: None, content_type: None, seq: None })
     }
 }
 
@@ -289,9 +855,22 @@ impl Record {
     /// size in bytes operation
     /// it counts size of record
     /// Generally it comes from header(16-ts,4 and 4 from key and value length , 1 op)
-    /// and bytes from key and val
+    /// and bytes from key and val.
+    /// For v2 the header takes 4 bytes, varints of ts, expiration, key and val length
+    /// the byte of content type and varint of seq, v3 adds 4 bytes of crc32
     pub fn size_in_bytes(&self) -> u32 {
-        self.val_len + self.key_len + 16 + 4 + 4 + 1
+        let varints = varint_len(self.encoded_timestamp())
+            + self.expires_at.map(varint_len).unwrap_or(0)
+            + self.content_type.map(|_| 1).unwrap_or(0)
+            + self.seq.map(|s| varint_len(s as u128)).unwrap_or(0)
+            + varint_len(self.key_len as u128)
+            + varint_len(self.val_len as u128);
+        let header = match self.format {
+            RecordFormat::V1 => 16 + 4 + 4 + 1,
+            RecordFormat::V2(_) => 4 + varints,
+            RecordFormat::V3(_) => 8 + varints,
+        };
+        self.val_len + self.key_len + header as u32
     }
 
     pub fn insert_record(key: Vec<u8>, val: Vec<u8>) -> Self {
@@ -304,6 +883,166 @@ impl Record {
         Record::op_from(RecordType::Lock, key, val)
     }
 
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp as u64)
+    }
+
+    pub fn timestamp_millis(&self) -> u128 {
+        self.timestamp
+    }
+
+    pub fn operation(&self) -> &RecordType {
+        &self.operation
+    }
+
+    /// the seq assigned by the log or none if the record has not been pushed
+    /// or has been read from the v1 format
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    /// the copy of the record with the seq.
+    /// The seq is kept only by the versioned formats (v2, v3)
+    pub fn with_seq(&self, seq: u64) -> Self {
+        Record { seq: Some(seq), ..self.clone() }
+    }
+
+    pub(crate) fn set_seq(&mut self, seq: u64) {
+        self.seq = Some(seq);
+    }
+
+    pub fn format(&self) -> RecordFormat {
+        self.format
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn val(&self) -> &[u8] {
+        &self.val
+    }
+
+    /// the expiration time in millis or none if the record does not expire
+    pub fn expires_at(&self) -> Option<u128> {
+        self.expires_at
+    }
+
+    /// the copy of the record expiring at the time in millis.
+    /// The expiration time is kept only by the versioned formats (v2, v3)
+    pub fn with_expiry(&self, expires_at: u128) -> Self {
+        Record { expires_at: Some(expires_at), ..self.clone() }
+    }
+
+    /// the content type of the value or none if it has not been set
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.content_type
+    }
+
+    /// the copy of the record with the content type of the value.
+    /// The content type is kept only by the versioned formats (v2, v3)
+    pub fn with_content_type(&self, content_type: ContentType) -> Self {
+        Record { content_type: Some(content_type), ..self.clone() }
+    }
+
+    /// the copy of the record with another timestamp in millis
+    pub fn with_timestamp_millis(&self, timestamp: u128) -> Self {
+        Record { timestamp, ..self.clone() }
+    }
+
+    /// the copy of the record in the given format.
+    /// The timestamp is truncated to the precision of the format
+    pub fn with_format(&self, format: RecordFormat) -> Self {
+        let timestamp = match format {
+            RecordFormat::V2(TimestampPrecision::Seconds) | RecordFormat::V3(TimestampPrecision::Seconds) =>
+                self.timestamp - self.timestamp % 1000,
+            _ => self.timestamp,
+        };
+        Record { timestamp, format, ..self.clone() }
+    }
+
+    fn encoded_timestamp(&self) -> u128 {
+        match self.format {
+            RecordFormat::V2(TimestampPrecision::Seconds) | RecordFormat::V3(TimestampPrecision::Seconds) =>
+                self.timestamp / 1000,
+            _ => self.timestamp,
+        }
+    }
+
+    fn from_versioned_bytes(bytes: &[u8]) -> StoreResult<Record> {
+        if bytes.len() < 4 || (bytes[1] != V2 && bytes[1] != V3) {
+            return Err(StoreError(format!("the record version {:?} is not supported", bytes.get(1))));
+        }
+        let mut pos = 4;
+        if bytes[1] == V3 {
+            if bytes.len() < 8 {
+                return Err(StoreError::corruption("the record is shorter than the header"));
+            }
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&bytes[..4]);
+            hasher.update(&bytes[8..]);
+            if hasher.finalize() != convert_32(&bytes[4..8]) {
+                return Err(StoreError::corruption("the checksum of the record does not match"));
+            }
+            pos = 8;
+        }
+        let operation = RecordType::from_code(bytes[2])?;
+        let precision =
+            if bytes[3] & SECONDS_FLAG != 0 { TimestampPrecision::Seconds } else { TimestampPrecision::Millis };
+
+        let (ts, len) = read_varint(&bytes[pos..])?;
+        pos += len;
+        let mut expires_at = None;
+        if bytes[3] & EXPIRES_FLAG != 0 {
+            let (e, len) = read_varint(&bytes[pos..])?;
+            pos += len;
+            expires_at = Some(e);
+        }
+        let mut content_type = None;
+        if bytes[3] & CONTENT_TYPE_FLAG != 0 {
+            let code = bytes.get(pos)
+                .ok_or_else(|| StoreError(String::from("the record is shorter than the header")))?;
+            content_type = Some(ContentType::from_code(*code)?);
+            pos += 1;
+        }
+        let mut seq = None;
+        if bytes[3] & SEQ_FLAG != 0 {
+            let (s, len) = read_varint(&bytes[pos..])?;
+            pos += len;
+            seq = Some(s as u64);
+        }
+        let (key_len, len) = read_varint(&bytes[pos..])?;
+        pos += len;
+        let (val_len, len) = read_varint(&bytes[pos..])?;
+        pos += len;
+
+        // the lengths come from the file so they are checked before slicing
+        let ends = TryInto::<usize>::try_into(key_len).ok()
+            .zip(TryInto::<usize>::try_into(val_len).ok())
+            .and_then(|(k, v)| pos.checked_add(k).and_then(|key_end| key_end.checked_add(v).map(|end| (key_end, end))));
+        let key_end = match ends {
+            Some((key_end, end)) if end == bytes.len() => key_end,
+            _ => return Err(StoreError(String::from("the record length does not match the header"))),
+        };
+        let timestamp = match precision {
+            TimestampPrecision::Seconds => ts.checked_mul(1000)
+                .ok_or_else(|| StoreError(String::from("the record timestamp is out of range")))?,
+            TimestampPrecision::Millis => ts,
+        };
+
+        Ok(Record {
+            timestamp,
+            operation,
+            key_len: key_len as u32,
+            val_len: val_len as u32,
+            key: bytes[pos..key_end].to_vec(),
+            val: bytes[key_end..].to_vec(),
+            format: if bytes[1] == V3 { RecordFormat::V3(precision) } else { RecordFormat::V2(precision) },
+            expires_at,
+            content_type,
+            seq,
+        })
+    }
 
     fn op_from(operation: RecordType, key: Vec<u8>, val: Vec<u8>) -> Self {
         Record {
@@ -313,6 +1052,10 @@ impl Record {
             val_len: val.len() as u32,
             key,
             val,
+            format: RecordFormat::V1,
+            expires_at: None,
+            content_type: None,
+            seq: None,
         }
     }
 }
@@ -352,11 +1095,14 @@ impl From<std::io::Error> for StoreError {
     }
 }
 
-fn time_now_millis() -> u128 {
+/// the current time in millis.
+/// If the system clock is set before the epoch it returns 0,
+/// the monotonic guard in `TransactionLog::push` takes care of that
+pub(crate) fn time_now_millis() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_millis()
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
 }
 
 fn convert_128(slice: &[u8]) -> u128 {
@@ -377,8 +1123,20 @@ fn convert_to_fixed(bytes: &[u8]) -> &[u8; 4] {
 
 #[cfg(test)]
 mod tests {
-    use crate::store::log::transaction_log::{Index, Record, RecordType, TransactionLog, time_now_millis};
-    use crate::store::{FromBytes, ToBytes};
+    use crate::store::log::transaction_log::{Index, Record, RecordType, TransactionLog, time_now_millis, LogOptions, SkippedRecord, RecordFormat, TimestampPrecision};
+    use std::time::{UNIX_EPOCH, Duration};
+    use crate::store::{FromBytes, ToBytes, StoreResult, StoreError};
+    use std::sync::{Arc, Mutex};
+    use crate::store::log::clock::SkewPolicy;
+    use crate::store::log::sync::Durability;
+    use crate::store::log::backup::{StaticKeys, key_id, decrypt_bytes};
+    use crate::store::log::transaction_log::BACKUP_EXT;
+    use crate::store::log::batch::{RecordBatch, BatchLimits, LogEntry};
+    use crate::store::structures::checksum::ChecksumKind;
+    use std::fs;
+    use crate::store::memory::content_type::ContentType;
+    use crate::store::files::append_item;
+    use crate::store::log::varint::write_varint;
 
 
     #[test]
@@ -471,8 +1229,9 @@ mod tests {
         if let Ok(t_log) = TransactionLog::create(r"test_data\simple") {
             let rec = Record::insert_record(vec![1 as u8; 10], vec![1 as u8; 20]);
 
-            if let Ok(size_res) = t_log.push(&rec) {
-                assert_eq!(size_res, 55);
+            if let Ok(seq) = t_log.push(&rec) {
+                assert_eq!(seq, 0);
+                assert_eq!(rec.size_in_bytes(), 55);
             } else {
                 panic!("panic")
             }
@@ -485,6 +1244,133 @@ mod tests {
         }
     }
 
+    #[test]
+    fn clock_skew_clamp_test() {
+        let t_log = TransactionLog::create(r"test_data\clock_clamp").unwrap();
+        let rec = Record::insert_record(vec![1], vec![1]);
+        t_log.push(&rec).unwrap();
+        t_log.push(&rec.with_timestamp_millis(rec.timestamp_millis() - 1000)).unwrap();
+
+        let last = t_log.read_from_end(1).unwrap();
+        assert_eq!(last.timestamp_millis(), rec.timestamp_millis());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn clock_skew_error_test() {
+        let opts = LogOptions { skew_policy: SkewPolicy::Error, ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\clock_error", opts).unwrap();
+        let rec = Record::insert_record(vec![1], vec![1]);
+        t_log.push(&rec).unwrap();
+        assert!(t_log.push(&rec.with_timestamp_millis(rec.timestamp_millis() - 1000)).is_err());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn clock_watermark_test() {
+        let rec = Record::insert_record(vec![1], vec![1]);
+        let future = rec.with_timestamp_millis(rec.timestamp_millis() + 1_000_000);
+        {
+            let t_log = TransactionLog::create(r"test_data\clock_watermark").unwrap();
+            t_log.push(&future).unwrap();
+        }
+        let t_log = TransactionLog::create(r"test_data\clock_watermark").unwrap();
+        t_log.push(&rec).unwrap();
+        assert_eq!(t_log.read_from_end(1).unwrap().timestamp_millis(), future.timestamp_millis());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn clock_watermark_crash_test() {
+        let rec = Record::insert_record(vec![1], vec![1]);
+        let future = rec.with_timestamp_millis(rec.timestamp_millis() + 1_000_000);
+        let t_log = TransactionLog::create(r"test_data\clock_watermark_crash").unwrap();
+        t_log.push(&future).unwrap();
+        // the log is not closed so the watermark is not written by close
+        std::mem::forget(t_log);
+
+        let t_log = TransactionLog::create_force(r"test_data\clock_watermark_crash").unwrap();
+        t_log.push(&rec).unwrap();
+        assert_eq!(t_log.read_from_end(1).unwrap().timestamp_millis(), future.timestamp_millis());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn durability_test() {
+        let t_log = TransactionLog::create(r"test_data\durability_none").unwrap();
+        let rec = Record::insert_record(vec![1; 10], vec![1; 20]);
+        t_log.push(&rec).unwrap();
+        assert_eq!(t_log.sync_stats().unsynced_records, 1);
+        assert_eq!(t_log.sync_stats().unsynced_bytes, 59);
+        t_log.sync().unwrap();
+        assert_eq!(t_log.sync_stats().unsynced_records, 0);
+        t_log.remove_files().unwrap();
+
+        let opts = LogOptions { durability: Durability::PerWrite, ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\durability_per_write", opts).unwrap();
+        t_log.push(&rec).unwrap();
+        t_log.push(&rec).unwrap();
+        assert_eq!(t_log.sync_stats().unsynced_records, 0);
+        assert_eq!(t_log.sync_stats().syncs, 2);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn encrypted_backup_test() {
+        let t_log = TransactionLog::create(r"test_data\encrypted_backup").unwrap();
+        t_log.push(&Record::insert_record(vec![1; 10], vec![2; 10])).unwrap();
+        let mut keys = StaticKeys::new("first", [1; 32]);
+        t_log.backup_encrypted(&keys).unwrap();
+
+        let mut log_bk = t_log.log.clone();
+        log_bk.set_extension(BACKUP_EXT);
+        assert_eq!(key_id(log_bk.as_path()).unwrap(), Some(String::from("first")));
+
+        keys.rotate("second", [2; 32]);
+        t_log.rotate_backup_key(&keys).unwrap();
+        assert_eq!(key_id(log_bk.as_path()).unwrap(), Some(String::from("second")));
+
+        let plain = decrypt_bytes(&fs::read(log_bk.as_path()).unwrap(), &keys).unwrap();
+        assert_eq!(plain, fs::read(t_log.log.as_path()).unwrap());
+
+        let mut idx_bk = t_log.idx.clone();
+        idx_bk.set_extension(BACKUP_EXT);
+        let _ = fs::remove_file(log_bk);
+        let _ = fs::remove_file(idx_bk);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn unknown_record_type_test() {
+        let t_log = TransactionLog::create(r"test_data\unknown_type").unwrap();
+        let merge = Record::op_from(RecordType::Unknown(4), vec![1], vec![2]);
+        t_log.push(&Record::insert_record(vec![1], vec![1])).unwrap();
+        t_log.push(&merge).unwrap();
+        t_log.push(&Record::delete_record(vec![1], vec![])).unwrap();
+
+        let report = t_log.replay_from_end(3).unwrap();
+        assert_eq!(report.records.len(), 2);
+        assert_eq!(report.records[0].operation, RecordType::Delete);
+        assert_eq!(report.records[1].operation, RecordType::Insert);
+        assert_eq!(report.skipped, vec![SkippedRecord { pos_from_end: 2, op: 4, size: merge.size_in_bytes() }]);
+
+        let mandatory = Record::op_from(RecordType::Unknown(200), vec![1], vec![2]);
+        t_log.push(&mandatory).unwrap();
+        assert!(t_log.replay_from_end(4).is_err());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn record_type_code_test() {
+        for op in 1..=255u8 {
+            match RecordType::from_code(op) {
+                Ok(t) => assert_eq!(t.code(), op),
+                Err(_) => assert!(op >= 128),
+            }
+        }
+        assert!(RecordType::from_code(0).is_err());
+    }
+
     #[test]
     fn record_test() {
         let k = [0; 10];
@@ -515,6 +1401,505 @@ mod tests {
         }
     }
 
+    #[test]
+    fn record_v2_test() {
+        let rec = Record::insert_record(vec![1; 10], vec![2; 300]);
+        for p in [TimestampPrecision::Millis, TimestampPrecision::Seconds] {
+            let v2 = rec.with_format(RecordFormat::V2(p));
+            let bytes = v2.to_bytes();
+            assert_eq!(bytes.len(), v2.size_in_bytes() as usize);
+            assert!(v2.size_in_bytes() < rec.size_in_bytes());
+            assert_eq!(Record::from_bytes(&bytes).unwrap(), v2);
+        }
+
+        let secs = rec.with_timestamp_millis(1_500_999).with_format(RecordFormat::V2(TimestampPrecision::Seconds));
+        assert_eq!(secs.timestamp_millis(), 1_500_000);
+        assert_eq!(secs.timestamp(), UNIX_EPOCH + Duration::from_secs(1500));
+
+        let empty = Record::delete_record(vec![], vec![]).with_format(RecordFormat::V2(TimestampPrecision::Millis));
+        assert_eq!(Record::from_bytes(&empty.to_bytes()).unwrap(), empty);
+
+        let mut broken = rec.with_format(RecordFormat::V2(TimestampPrecision::Millis)).to_bytes();
+        broken.pop();
+        assert!(Record::from_bytes(&broken).is_err());
+    }
+
+    #[test]
+    fn record_v2_corrupted_length_test() {
+        let header = Record::insert_record(vec![1], vec![2]).with_format(RecordFormat::V2(TimestampPrecision::Seconds)).to_bytes();
+        let corrupted = |ts: u128, key_len: u128, val_len: u128| {
+            let mut bytes = header[..4].to_vec();
+            write_varint(ts, &mut bytes);
+            write_varint(key_len, &mut bytes);
+            write_varint(val_len, &mut bytes);
+            bytes.extend_from_slice(&[1, 2]);
+            Record::from_bytes(&bytes)
+        };
+        assert_eq!(corrupted(1, 1, 1).unwrap().key(), &[1]);
+        assert!(corrupted(1, u64::MAX as u128 - 1, 1).is_err());
+        assert!(corrupted(1, 1, u64::MAX as u128).is_err());
+        assert!(corrupted(1, u128::MAX, u128::MAX).is_err());
+        assert!(corrupted(1, 3, 0).is_err());
+        assert!(corrupted(u128::MAX / 10, 1, 1).is_err());
+    }
+
+    #[test]
+    fn record_v3_test() {
+        let rec = Record::insert_record(vec![1; 10], vec![2; 300]);
+        for p in [TimestampPrecision::Millis, TimestampPrecision::Seconds] {
+            let v3 = rec.with_format(RecordFormat::V3(p));
+            let bytes = v3.to_bytes();
+            assert_eq!(bytes.len(), v3.size_in_bytes() as usize);
+            assert_eq!(v3.size_in_bytes(), rec.with_format(RecordFormat::V2(p)).size_in_bytes() + 4);
+            assert_eq!(Record::from_bytes(&bytes).unwrap(), v3);
+        }
+
+        let bytes = rec.with_format(RecordFormat::V3(TimestampPrecision::Millis)).to_bytes();
+        for pos in [2, 5, 9, bytes.len() - 1] {
+            let mut broken = bytes.clone();
+            broken[pos] ^= 0x10;
+            let err = Record::from_bytes(&broken).unwrap_err();
+            assert!(err.is_corruption(), "{}: {}", pos, err.0);
+        }
+        assert!(Record::from_bytes(&bytes[..6]).unwrap_err().is_corruption());
+        assert!(!Record::from_bytes(&[0, 9, 1, 0]).unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn record_expiry_test() {
+        let rec = Record::insert_record(vec![1; 10], vec![2; 30]).with_expiry(1_700_000_000_000);
+        assert_eq!(rec.expires_at(), Some(1_700_000_000_000));
+        for f in [RecordFormat::V2(TimestampPrecision::Millis), RecordFormat::V3(TimestampPrecision::Seconds)] {
+            let r = rec.with_format(f);
+            let bytes = r.to_bytes();
+            assert_eq!(bytes.len(), r.size_in_bytes() as usize);
+            let restored = Record::from_bytes(&bytes).unwrap();
+            assert_eq!(restored.expires_at(), Some(1_700_000_000_000));
+            assert_eq!(restored, r);
+        }
+
+        let typed = rec.with_content_type(ContentType::Toml);
+        let bytes = typed.with_format(RecordFormat::V3(TimestampPrecision::Millis)).to_bytes();
+        let restored = Record::from_bytes(&bytes).unwrap();
+        assert_eq!((restored.expires_at(), restored.content_type()), (Some(1_700_000_000_000), Some(ContentType::Toml)));
+        assert_eq!(bytes.len(), rec.with_format(RecordFormat::V3(TimestampPrecision::Millis)).size_in_bytes() as usize + 1);
+
+        let t_log = TransactionLog::create(r"test_data\record_expiry").unwrap();
+        assert!(t_log.push(&rec).is_err());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn corrupted_record_tail_test() {
+        let opts = LogOptions { format: RecordFormat::V3(TimestampPrecision::Millis), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\corrupted_record_tail", opts).unwrap();
+        for i in 1..=3 {
+            t_log.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+        }
+        let mut bytes = fs::read(&t_log.log).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&t_log.log, &bytes).unwrap();
+
+        let report = t_log.replay_from_end(3).unwrap();
+        assert_eq!(report.records.len(), 2);
+        assert!(report.torn_tail);
+
+        let keys: Vec<StoreResult<Record>> = t_log.iter().collect();
+        assert_eq!(keys.len(), 3);
+        assert!(keys[2].as_ref().unwrap_err().is_corruption());
+
+        bytes[last] ^= 0xFF;
+        bytes[10] ^= 0xFF;
+        fs::write(&t_log.log, &bytes).unwrap();
+        assert!(t_log.replay_from_end(3).unwrap_err().is_corruption());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn log_v2_test() {
+        let opts = LogOptions { format: RecordFormat::V2(TimestampPrecision::Seconds), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\log_v2", opts).unwrap();
+        for i in 1..10 {
+            t_log.push(&Record::insert_record(vec![i; i as usize], vec![i; 200])).unwrap();
+        }
+        let records = t_log.read_all_from_end(9).unwrap();
+        for (i, r) in records.iter().enumerate() {
+            assert_eq!(r.format(), RecordFormat::V2(TimestampPrecision::Seconds));
+            assert_eq!(r.timestamp_millis() % 1000, 0);
+            assert_eq!(r.size_in_bytes() as usize, 4 + 5 + 1 + 1 + 2 + (9 - i) + 200);
+            assert_eq!(r.seq(), Some(8 - i as u64));
+        }
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn record_edge_keys_test() {
+        let keys: Vec<Vec<u8>> = vec![vec![], vec![0], vec![0, 0], vec![1], vec![1, 0], vec![0xFF; 1024]];
+        let formats = [RecordFormat::V1, RecordFormat::V2(TimestampPrecision::Millis)];
+        for fmt in formats.iter() {
+            for k in keys.iter() {
+                let rec = Record::insert_record(k.clone(), vec![]).with_format(*fmt);
+                let bytes = rec.to_bytes();
+                assert_eq!(bytes.len(), rec.size_in_bytes() as usize);
+                let restored = Record::from_bytes(&bytes).unwrap();
+                assert_eq!(restored.key(), k.as_slice());
+                assert_eq!(restored, rec);
+
+                assert!(Record::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+                let mut longer = bytes.clone();
+                longer.push(0);
+                assert!(Record::from_bytes(&longer).is_err());
+            }
+        }
+        assert!(Record::from_bytes(&[1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn record_seq_test() {
+        let rec = Record::insert_record(vec![1, 2], vec![3]).with_expiry(10).with_content_type(ContentType::Json);
+        for f in [RecordFormat::V2(TimestampPrecision::Millis), RecordFormat::V3(TimestampPrecision::Seconds)] {
+            let r = rec.with_format(f).with_seq(300);
+            let bytes = r.to_bytes();
+            assert_eq!(bytes.len(), r.size_in_bytes() as usize);
+            assert_eq!(r.size_in_bytes(), rec.with_format(f).size_in_bytes() + 2);
+            assert_eq!(Record::from_bytes(&bytes).unwrap(), r);
+        }
+        let v1 = rec.with_seq(1);
+        assert_eq!(v1.to_bytes(), rec.to_bytes());
+        assert_eq!(Record::from_bytes(&v1.to_bytes()).unwrap().seq(), None);
+    }
+
+    #[test]
+    fn replay_duplicates_test() {
+        let opts = LogOptions { format: RecordFormat::V3(TimestampPrecision::Millis), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\replay_duplicates", opts).unwrap();
+        for i in 0..3 {
+            assert_eq!(t_log.push(&Record::insert_record(vec![i], vec![i])).unwrap(), i as u64);
+        }
+        t_log.push_batch(&batch_of(&[3, 4])).unwrap();
+
+        let last = t_log.read_from_end(2).unwrap();
+        assert_eq!(last.seq(), Some(2));
+        append_item(&t_log.idx, &Index::create(last.size_in_bytes())).unwrap();
+        append_item(&t_log.log, &last).unwrap();
+
+        let report = t_log.replay_from_end(5).unwrap();
+        assert_eq!(report.duplicates, 1);
+        let seqs: Vec<Option<u64>> = report.records.iter().map(|r| r.seq()).collect();
+        assert_eq!(seqs, vec![Some(2), Some(4), Some(3), Some(1), Some(0)]);
+        t_log.remove_files().unwrap();
+    }
+
+    fn batch_of(keys: &[u8]) -> RecordBatch {
+        RecordBatch::new(keys.iter().map(|k| Record::insert_record(vec![*k], vec![*k])).collect())
+    }
+
+    #[test]
+    fn batch_log_test() {
+        let t_log = TransactionLog::create(r"test_data\batch_log").unwrap();
+        t_log.push(&Record::insert_record(vec![1], vec![1])).unwrap();
+        let b = t_log.push_batch(&batch_of(&[2, 3, 4])).unwrap();
+        assert_eq!(b.len(), 1);
+        assert_eq!((b[0].first_seq(), b[0].last_seq()), (1, 3));
+        t_log.push(&Record::insert_record(vec![5], vec![5])).unwrap();
+        assert_eq!(t_log.next_seq(), 5);
+        assert!(t_log.push_batch(&RecordBatch::new(vec![])).is_err());
+
+        let report = t_log.replay_from_end(3).unwrap();
+        let keys: Vec<u8> = report.records.iter().map(|r| r.key()[0]).collect();
+        assert_eq!(keys, vec![5, 4, 3, 2, 1]);
+        assert!(!report.torn_tail);
+        assert!(t_log.replay_from_end(4).is_err());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn batch_limits_test() {
+        let limits = BatchLimits { max_bytes: u32::MAX, max_records: 2, split: true };
+        let opts = LogOptions { batch_limits: limits, ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\batch_limits", opts).unwrap();
+        let batches = t_log.push_batch(&batch_of(&[1, 2, 3, 4, 5])).unwrap();
+        let seqs: Vec<(u64, u64)> = batches.iter().map(|b| (b.first_seq(), b.last_seq())).collect();
+        assert_eq!(seqs, vec![(0, 1), (2, 3), (4, 4)]);
+        assert_eq!(t_log.replay_from_end(3).unwrap().records.len(), 5);
+        t_log.remove_files().unwrap();
+
+        let limits = BatchLimits { split: false, ..limits };
+        let opts = LogOptions { batch_limits: limits, ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\batch_limits_strict", opts).unwrap();
+        assert!(t_log.push_batch(&batch_of(&[1, 2, 3])).is_err());
+        assert_eq!(t_log.next_seq(), 0);
+        t_log.push_batch(&batch_of(&[1, 2])).unwrap();
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn batch_checksum_test() {
+        let opts = LogOptions { checksum: ChecksumKind::XxHash64, ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\batch_checksum", opts).unwrap();
+        let batches = t_log.push_batch(&batch_of(&[1, 2])).unwrap();
+        assert_eq!(batches[0].checksum(), ChecksumKind::XxHash64);
+
+        let mut tail = t_log.tail(0);
+        match tail.next_entry_timeout(Duration::from_millis(10)).unwrap() {
+            Some(LogEntry::Batch(b)) => assert_eq!(b.checksum(), ChecksumKind::XxHash64),
+            e => panic!("expected the batch, got {:?}", e),
+        }
+        assert_eq!(t_log.replay_from_end(1).unwrap().records.len(), 2);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn torn_batch_tail_test() {
+        let t_log = TransactionLog::create(r"test_data\batch_torn_tail").unwrap();
+        t_log.push_batch(&batch_of(&[1, 2])).unwrap();
+        t_log.push_batch(&batch_of(&[3, 4])).unwrap();
+
+        let len = fs::metadata(&t_log.log).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&t_log.log).unwrap().set_len(len - 5).unwrap();
+
+        let report = t_log.replay_from_end(2).unwrap();
+        let keys: Vec<u8> = report.records.iter().map(|r| r.key()[0]).collect();
+        assert_eq!(keys, vec![2, 1]);
+        assert!(report.torn_tail);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn corrupted_batch_tail_test() {
+        let t_log = TransactionLog::create(r"test_data\batch_corrupted_tail").unwrap();
+        t_log.push_batch(&batch_of(&[1, 2])).unwrap();
+        t_log.push_batch(&batch_of(&[3, 4])).unwrap();
+
+        let mut bytes = fs::read(&t_log.log).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&t_log.log, &bytes).unwrap();
+
+        let report = t_log.replay_from_end(2).unwrap();
+        assert_eq!(report.records.len(), 2);
+        assert!(report.torn_tail);
+
+        bytes[last] ^= 0xFF;
+        bytes[40] ^= 0xFF;
+        fs::write(&t_log.log, &bytes).unwrap();
+        assert!(t_log.replay_from_end(2).is_err());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn concurrent_push_seq_test() {
+        let dir = r"test_data\concurrent_push_seq";
+        let opts = LogOptions { segment_size: Some(300), format: RecordFormat::V2(TimestampPrecision::Millis), ..LogOptions::default() };
+        let t_log = Arc::new(TransactionLog::create_with(dir, opts).unwrap());
+        let handles: Vec<_> = (0..4u8).map(|t| {
+            let t_log = t_log.clone();
+            std::thread::spawn(move || {
+                for i in 0..50u8 {
+                    if i % 5 == 0 {
+                        t_log.push_batch(&batch_of(&[t, i])).unwrap();
+                    } else {
+                        t_log.push(&Record::insert_record(vec![t, i], vec![i])).unwrap();
+                    }
+                }
+            })
+        }).collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let total = t_log.next_seq();
+        assert_eq!(total, 4 * 60);
+        let seqs: Vec<Option<u64>> = t_log.replay_from_end(4 * 50).unwrap().records.iter().map(|r| r.seq()).collect();
+        assert_eq!(seqs, (0..total).rev().map(Some).collect::<Vec<_>>());
+        let segments = t_log.segments();
+        assert!(segments.len() > 1);
+        for w in segments.windows(2) {
+            assert_eq!(w[0].next_seq, w[1].first_seq);
+        }
+        t_log.remove_files().unwrap();
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn create_locked_dir_test() {
+        let dir = r"test_data\create_locked_dir";
+        let opts = LogOptions { segment_size: Some(100), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(dir, opts.clone()).unwrap();
+        for i in 0..6u8 {
+            t_log.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+        }
+        let sealed = t_log.segments();
+        assert!(!sealed.is_empty());
+
+        assert!(TransactionLog::create_with(dir, opts).is_err());
+        assert!(sealed.iter().all(|s| s.idx.exists() && s.log.exists()));
+        assert_eq!(t_log.replay_from_end(6).unwrap().records.len(), 6);
+        t_log.remove_files().unwrap();
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn segment_rotation_test() {
+        let opts = LogOptions { segment_size: Some(100), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\segments", opts).unwrap();
+        let mut tail = t_log.tail(0);
+        for i in 0..10u8 {
+            t_log.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+        }
+
+        let segments = t_log.segments();
+        let ranges: Vec<(u64, u64, u64)> = segments.iter().map(|s| (s.number, s.first_seq, s.next_seq)).collect();
+        assert_eq!(ranges, vec![(1, 0, 4), (2, 4, 8)]);
+        assert!(segments.iter().all(|s| s.idx.exists() && s.log.exists()));
+
+        let keys: Vec<u8> = t_log.replay_from_end(10).unwrap().records.iter().map(|r| r.key()[0]).collect();
+        assert_eq!(keys, (0..10).rev().collect::<Vec<u8>>());
+        assert!(t_log.replay_from_end(11).is_err());
+
+        let keys: Vec<u8> = (0..10).map(|_| tail.try_next().unwrap().unwrap().key()[0]).collect();
+        assert_eq!(keys, (0..10).collect::<Vec<u8>>());
+
+        let deleted = t_log.delete_segments_before(5).unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert!(!deleted[0].log.exists());
+        assert_eq!(t_log.segments().len(), 1);
+        assert!(t_log.tail(2).try_next().is_err());
+        assert_eq!(t_log.tail(4).try_next().unwrap().unwrap().key(), &[4]);
+        assert_eq!(t_log.replay_from_end(6).unwrap().records.len(), 6);
+
+        let sealed = t_log.segments()[0].log.clone();
+        drop(t_log);
+        let t_log = TransactionLog::create(r"test_data\segments").unwrap();
+        assert!(!sealed.exists());
+        assert!(t_log.segments().is_empty());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn iter_since_test() {
+        let opts = LogOptions { segment_size: Some(100), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\log_iter_since", opts).unwrap();
+        let base = time_now_millis() + 10_000;
+        for i in 0..8u8 {
+            t_log.push(&Record::insert_record(vec![i], vec![i]).with_timestamp_millis(base + i as u128 * 1000)).unwrap();
+        }
+        let b: Vec<Record> = batch_of(&[8, 9]).records().iter()
+            .map(|r| r.with_timestamp_millis(base + 8000))
+            .collect();
+        t_log.push_batch(&RecordBatch::new(b)).unwrap();
+
+        let ranges: Vec<(u128, u128)> = t_log.segments().iter().map(|s| (s.min_ts - base, s.max_ts - base)).collect();
+        assert_eq!(ranges, vec![(0, 3000), (4000, 7000)]);
+
+        let keys = |ts: u128| t_log.iter_since(ts).map(|r| r.unwrap().key()[0]).collect::<Vec<u8>>();
+        assert_eq!(keys(0), (0..10).collect::<Vec<u8>>());
+        assert_eq!(keys(base + 2500), (3..10).collect::<Vec<u8>>());
+        assert_eq!(keys(base + 8000), vec![8, 9]);
+        assert!(keys(base + 9000).is_empty());
+
+        fs::write(&t_log.segments()[0].log, b"").unwrap();
+        assert!(t_log.iter().any(|r| r.is_err()));
+        assert_eq!(keys(base + 5000), (5..10).collect::<Vec<u8>>());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn iter_test() {
+        let opts = LogOptions { segment_size: Some(100), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\log_iter", opts).unwrap();
+        assert_eq!(t_log.iter().count(), 0);
+        for i in 0..5u8 {
+            t_log.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+        }
+        t_log.push_batch(&batch_of(&[5, 6, 7])).unwrap();
+        t_log.push(&Record::insert_record(vec![8], vec![8])).unwrap();
+
+        let keys: Vec<u8> = t_log.iter().map(|r| r.unwrap().key()[0]).collect();
+        assert_eq!(keys, (0..9).collect::<Vec<u8>>());
+
+        t_log.delete_segments_before(4).unwrap();
+        let keys: Vec<u8> = t_log.iter().map(|r| r.unwrap().key()[0]).collect();
+        assert_eq!(keys, (4..9).collect::<Vec<u8>>());
+
+        fs::write(&t_log.segments()[0].log, b"").unwrap();
+        let res: Vec<StoreResult<Record>> = t_log.iter().collect();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].is_err());
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn tail_from_seq_test() {
+        let opts = LogOptions { segment_size: Some(100), ..LogOptions::default() };
+        let t_log = TransactionLog::create_with(r"test_data\tail_from_seq", opts).unwrap();
+        for i in 0..5u8 {
+            t_log.push(&Record::insert_record(vec![i], vec![i])).unwrap();
+        }
+        t_log.push_batch(&batch_of(&[5, 6, 7])).unwrap();
+
+        let mut tail = t_log.tail_from_seq(6).unwrap();
+        t_log.push(&Record::insert_record(vec![8], vec![8])).unwrap();
+        let keys: Vec<u8> = (0..3).map(|_| tail.try_next().unwrap().unwrap().key()[0]).collect();
+        assert_eq!(keys, vec![6, 7, 8]);
+        assert!(tail.try_next().unwrap().is_none());
+
+        assert_eq!(t_log.tail_from_seq(2).unwrap().try_next().unwrap().unwrap().key(), &[2]);
+        assert!(t_log.tail_from_seq(9).unwrap().try_next().unwrap().is_none());
+        assert!(t_log.tail_from_seq(10).is_err());
+
+        t_log.delete_segments_before(4).unwrap();
+        assert!(t_log.tail_from_seq(2).is_err());
+        assert_eq!(t_log.tail_from_seq(4).unwrap().try_next().unwrap().unwrap().key(), &[4]);
+        t_log.remove_files().unwrap();
+    }
+
+    #[test]
+    fn hooks_test() {
+        let t_log = TransactionLog::create(r"test_data\hooks").unwrap();
+        t_log.add_pre_write_hook(|b: RecordBatch| {
+            if b.records().iter().any(|r| r.key().starts_with(b"ro/")) {
+                return Err(StoreError(String::from("read only")));
+            }
+            Ok(b)
+        });
+        t_log.add_pre_write_hook(|b: RecordBatch| {
+            let records = b.into_records().into_iter()
+                .map(|r| Record::insert_record(r.key().to_vec(), [r.val(), b"@admin"].concat()))
+                .collect();
+            Ok(RecordBatch::new(records))
+        });
+        let committed = Arc::new(Mutex::new(vec![]));
+        let observer = committed.clone();
+        t_log.add_post_commit_hook(move |b: &RecordBatch| {
+            observer.lock().unwrap().push((b.first_seq(), b.last_seq(), b.records()[0].val().to_vec()))
+        });
+
+        t_log.push(&Record::insert_record(b"a".to_vec(), b"1".to_vec())).unwrap();
+        assert!(t_log.push(&Record::insert_record(b"ro/a".to_vec(), b"1".to_vec())).is_err());
+        t_log.push_batch(&RecordBatch::new(vec![
+            Record::insert_record(b"b".to_vec(), b"2".to_vec()),
+            Record::insert_record(b"c".to_vec(), b"3".to_vec()),
+        ])).unwrap();
+        assert!(t_log.push_batch(&batch_of(&[7, 8, 9])).is_ok());
+        assert!(t_log.push_batch(&RecordBatch::new(vec![Record::insert_record(b"ro/b".to_vec(), vec![])])).is_err());
+
+        assert_eq!(t_log.next_seq(), 6);
+        assert_eq!(*committed.lock().unwrap(), vec![
+            (0, 0, b"1@admin".to_vec()),
+            (1, 2, b"2@admin".to_vec()),
+            (3, 5, b"\x07@admin".to_vec()),
+        ]);
+        let vals: Vec<Vec<u8>> = t_log.iter().map(|r| r.unwrap().val().to_vec()).collect();
+        assert_eq!(vals[2], b"3@admin".to_vec());
+
+        t_log.add_pre_write_hook(|_| Ok(RecordBatch::new(vec![])));
+        assert!(t_log.push(&Record::insert_record(b"d".to_vec(), vec![])).is_err());
+        assert!(t_log.push_batch(&batch_of(&[1])).is_err());
+        t_log.remove_files().unwrap();
+    }
+
     #[test]
     fn index_test() {
         let idx = Index { val: 1000_000_000 };
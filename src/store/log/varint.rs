@@ -0,0 +1,71 @@
+//! Variable length encoding for unsigned numbers (LEB128).
+//! Every byte keeps 7 bits of the number, the highest bit says that the next byte follows.
+use crate::store::{StoreResult, StoreError};
+
+/// appends the encoded number to the bytes
+pub fn write_varint(val: u128, bytes: &mut Vec<u8>) {
+    let mut v = val;
+    while v >= 0x80 {
+        bytes.push((v as u8 & 0x7F) | 0x80);
+        v >>= 7;
+    }
+    bytes.push(v as u8);
+}
+
+/// decodes the number from the beginning of the bytes
+/// # Returns
+/// the number and the count of bytes it takes
+pub fn read_varint(bytes: &[u8]) -> StoreResult<(u128, usize)> {
+    let mut val: u128 = 0;
+    for (i, b) in bytes.iter().enumerate() {
+        if i * 7 >= 128 {
+            break;
+        }
+        val |= ((b & 0x7F) as u128) << (i * 7);
+        if b & 0x80 == 0 {
+            return Ok((val, i + 1));
+        }
+    }
+    Err(StoreError(String::from("the varint is broken")))
+}
+
+/// the count of bytes the number takes being encoded
+pub fn varint_len(val: u128) -> usize {
+    let mut v = val;
+    let mut len = 1;
+    while v >= 0x80 {
+        v >>= 7;
+        len += 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::log::varint::{write_varint, read_varint, varint_len};
+
+    #[test]
+    fn varint_test() {
+        for v in [0, 1, 127, 128, 300, 16_383, 16_384, u32::MAX as u128, u128::MAX] {
+            let mut bytes = vec![];
+            write_varint(v, &mut bytes);
+            assert_eq!(bytes.len(), varint_len(v));
+            assert_eq!(read_varint(&bytes).unwrap(), (v, bytes.len()));
+        }
+    }
+
+    #[test]
+    fn varint_bytes_test() {
+        let mut bytes = vec![];
+        write_varint(300, &mut bytes);
+        assert_eq!(bytes, vec![0xAC, 0x02]);
+        bytes.push(1);
+        assert_eq!(read_varint(&bytes).unwrap(), (300, 2));
+    }
+
+    #[test]
+    fn broken_varint_test() {
+        assert!(read_varint(&[]).is_err());
+        assert!(read_varint(&[0x80, 0x80]).is_err());
+    }
+}
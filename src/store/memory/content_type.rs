@@ -0,0 +1,102 @@
+//! The content type of a value set at put time
+//! so the tooling (pretty-printing, http responses, exporters) renders the value without guessing.
+//! The type is kept in the record (one byte, see the record v2 flags) and in the memtable entry.
+//! # Examples
+//! ```
+//!  table.put_typed(b"db".to_vec(), br#"{"host":"localhost"}"#.to_vec(), ContentType::Json);
+//!  let ct = table.content_type(&b"db".to_vec()).unwrap_or(ContentType::Binary);
+//! ```
+use std::fmt;
+use std::str::FromStr;
+use crate::store::{StoreResult, StoreError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    Json,
+    Toml,
+    Text,
+    Binary,
+    Yaml,
+}
+
+impl ContentType {
+    pub fn code(&self) -> u8 {
+        match self {
+            ContentType::Json => 1,
+            ContentType::Toml => 2,
+            ContentType::Text => 3,
+            ContentType::Binary => 4,
+            ContentType::Yaml => 5,
+        }
+    }
+
+    pub fn from_code(code: u8) -> StoreResult<ContentType> {
+        match code {
+            1 => Ok(ContentType::Json),
+            2 => Ok(ContentType::Toml),
+            3 => Ok(ContentType::Text),
+            4 => Ok(ContentType::Binary),
+            5 => Ok(ContentType::Yaml),
+            c => Err(StoreError(format!("the content type {} is not supported", c))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentType::Json => "json",
+            ContentType::Toml => "toml",
+            ContentType::Text => "text",
+            ContentType::Binary => "binary",
+            ContentType::Yaml => "yaml",
+        }
+    }
+
+    /// the mime type for the http responses
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ContentType::Json => "application/json",
+            ContentType::Toml => "application/toml",
+            ContentType::Text => "text/plain; charset=utf-8",
+            ContentType::Binary => "application/octet-stream",
+            ContentType::Yaml => "application/yaml",
+        }
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ContentType {
+    type Err = StoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(ContentType::Json),
+            "toml" => Ok(ContentType::Toml),
+            "text" => Ok(ContentType::Text),
+            "binary" => Ok(ContentType::Binary),
+            "yaml" | "yml" => Ok(ContentType::Yaml),
+            t => Err(StoreError(format!("the content type {} is unknown", t))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::content_type::ContentType;
+
+    #[test]
+    fn content_type_test() {
+        for ct in [ContentType::Json, ContentType::Toml, ContentType::Text, ContentType::Binary, ContentType::Yaml] {
+            assert_eq!(ContentType::from_code(ct.code()).unwrap(), ct);
+            assert_eq!(ct.to_string().parse::<ContentType>().unwrap(), ct);
+        }
+        assert_eq!(" JSON ".parse::<ContentType>().unwrap(), ContentType::Json);
+        assert_eq!("yml".parse::<ContentType>().unwrap(), ContentType::Yaml);
+        assert!("xml".parse::<ContentType>().is_err());
+        assert!(ContentType::from_code(0).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Feature flags stored as values.
+//! The value of a flag is `true`, `false` or a percentage like `25%`.
+//! The subjects are bucketed by the rabin checksum of the flag key and the subject id
+//! so the same subject gets the same answer as long as the percentage is the same
+//! and the subjects enabled at 10% stay enabled at 20%.
+//! # Examples
+//! ```
+//!  let flags = FeatureFlags::new(&table, b"flags/".to_vec());
+//!  if flags.is_enabled(b"new-ui", b"user-42")? {}
+//! ```
+use crate::store::memory::MemTable;
+use crate::store::structures::checksum::ChecksumKind;
+use crate::store::{StoreResult, StoreError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flag {
+    Off,
+    On,
+    /// enabled for the given percentage (0..=100) of subjects
+    Percentage(u8),
+}
+
+impl Flag {
+    pub fn parse(val: &[u8]) -> StoreResult<Flag> {
+        let text = std::str::from_utf8(val).map(|t| t.trim()).unwrap_or("");
+        match text {
+            "true" => Ok(Flag::On),
+            "false" => Ok(Flag::Off),
+            t if t.ends_with('%') => match t[..t.len() - 1].parse::<u8>() {
+                Ok(p) if p <= 100 => Ok(Flag::Percentage(p)),
+                _ => Err(StoreError(format!("the flag value {} is not a percentage", t))),
+            },
+            t => Err(StoreError(format!("the flag value {} is unknown", t))),
+        }
+    }
+
+    pub fn to_value(self) -> Vec<u8> {
+        match self {
+            Flag::Off => b"false".to_vec(),
+            Flag::On => b"true".to_vec(),
+            Flag::Percentage(p) => format!("{}%", p).into_bytes(),
+        }
+    }
+
+    /// checks the flag for the subject. The key is a part of the bucket
+    /// so the different flags enable different subjects
+    pub fn is_enabled(&self, key: &[u8], subject: &[u8]) -> bool {
+        match self {
+            Flag::Off => false,
+            Flag::On => true,
+            Flag::Percentage(p) => bucket(key, subject) < *p as u64,
+        }
+    }
+}
+
+/// the bucket 0..100 of the subject for the flag
+pub fn bucket(key: &[u8], subject: &[u8]) -> u64 {
+    let mut bytes = key.to_vec();
+    bytes.push(0);
+    bytes.extend_from_slice(subject);
+    ChecksumKind::Rabin.checksum(&bytes) % 100
+}
+
+/// the flags stored under the prefix
+pub struct FeatureFlags<'a, T: MemTable<Vec<u8>, Vec<u8>>> {
+    table: &'a T,
+    prefix: Vec<u8>,
+}
+
+impl<'a, T: MemTable<Vec<u8>, Vec<u8>>> FeatureFlags<'a, T> {
+    pub fn new(table: &'a T, prefix: Vec<u8>) -> Self {
+        FeatureFlags { table, prefix }
+    }
+
+    /// the flag or none if it is not defined
+    pub fn flag(&self, key: &[u8]) -> StoreResult<Option<Flag>> {
+        self.table.find(self.full_key(key)).map(|v| Flag::parse(&v)).transpose()
+    }
+
+    pub fn set(&self, key: &[u8], flag: Flag) -> StoreResult<()> {
+        self.table.put(self.full_key(key), flag.to_value())
+            .map_err(|_| StoreError(format!("the flag {} can not be set", String::from_utf8_lossy(key))))
+    }
+
+    /// the undefined flags are disabled
+    pub fn is_enabled(&self, key: &[u8], subject: &[u8]) -> StoreResult<bool> {
+        Ok(self.flag(key)?.map(|f| f.is_enabled(key, subject)).unwrap_or(false))
+    }
+
+    /// the watcher of the given flags
+    pub fn watch(&self, keys: Vec<Vec<u8>>) -> StoreResult<FlagWatcher> {
+        let mut last = Vec::with_capacity(keys.len());
+        for k in keys {
+            let f = self.flag(&k)?;
+            last.push((k, f));
+        }
+        Ok(FlagWatcher { last })
+    }
+
+    fn full_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut full = self.prefix.clone();
+        full.extend_from_slice(key);
+        full
+    }
+}
+
+/// remembers the flags and reports the ones which have been changed since the last poll
+pub struct FlagWatcher {
+    last: Vec<(Vec<u8>, Option<Flag>)>,
+}
+
+impl FlagWatcher {
+    /// # Returns
+    /// the changed flags with the new values (none if the flag has been removed)
+    pub fn poll<T: MemTable<Vec<u8>, Vec<u8>>>(&mut self, flags: &FeatureFlags<T>) -> StoreResult<Vec<(Vec<u8>, Option<Flag>)>> {
+        let mut changed = vec![];
+        for (k, last) in self.last.iter_mut() {
+            let curr = flags.flag(k)?;
+            if curr != *last {
+                *last = curr;
+                changed.push((k.clone(), curr));
+            }
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::flags::{Flag, FeatureFlags};
+    use crate::store::memory::memtable::BaseMemTable;
+    use crate::store::memory::MemTable;
+
+    #[test]
+    fn flag_parse_test() {
+        assert_eq!(Flag::parse(b"true").unwrap(), Flag::On);
+        assert_eq!(Flag::parse(b" false ").unwrap(), Flag::Off);
+        assert_eq!(Flag::parse(b"25%").unwrap(), Flag::Percentage(25));
+        assert!(Flag::parse(b"101%").is_err());
+        assert!(Flag::parse(b"yes").is_err());
+        for f in [Flag::On, Flag::Off, Flag::Percentage(7)] {
+            assert_eq!(Flag::parse(&f.to_value()).unwrap(), f);
+        }
+    }
+
+    #[test]
+    fn percentage_test() {
+        let subjects: Vec<Vec<u8>> = (0..1000).map(|i: i32| i.to_string().into_bytes()).collect();
+        let enabled = |p: u8| subjects.iter()
+            .filter(|s| Flag::Percentage(p).is_enabled(b"f", s))
+            .cloned()
+            .collect::<Vec<Vec<u8>>>();
+
+        let ten = enabled(10);
+        let twenty = enabled(20);
+        assert!(ten.len() > 50 && ten.len() < 150, "{}", ten.len());
+        assert!(ten.iter().all(|s| twenty.contains(s)));
+        assert_eq!(enabled(0).len(), 0);
+        assert_eq!(enabled(100).len(), 1000);
+        assert_eq!(ten, enabled(10));
+    }
+
+    #[test]
+    fn flags_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        let flags = FeatureFlags::new(&table, b"flags/".to_vec());
+        flags.set(b"on", Flag::On).unwrap();
+        table.put(b"flags/broken".to_vec(), b"maybe".to_vec()).unwrap();
+
+        assert!(flags.is_enabled(b"on", b"user").unwrap());
+        assert!(!flags.is_enabled(b"absent", b"user").unwrap());
+        assert!(flags.is_enabled(b"broken", b"user").is_err());
+
+        let mut watcher = flags.watch(vec![b"on".to_vec(), b"new".to_vec()]).unwrap();
+        assert!(watcher.poll(&flags).unwrap().is_empty());
+        flags.set(b"new", Flag::Percentage(50)).unwrap();
+        table.delete(b"flags/on".to_vec()).unwrap();
+        assert_eq!(watcher.poll(&flags).unwrap(), vec![
+            (b"on".to_vec(), None),
+            (b"new".to_vec(), Some(Flag::Percentage(50))),
+        ]);
+        assert!(watcher.poll(&flags).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! Patching of the json values.
+//! - JSON Patch (RFC 6902): the list of operations (add, remove, replace, move, copy, test)
+//!   addressing the document by JSON Pointers (RFC 6901)
+//! - JSON Merge Patch (RFC 7386): the object merged into the document, `null` removes the member
+//!
+//! The patch is applied to a copy of the document so a failed operation leaves the document untouched.
+//! # Examples
+//! ```
+//!  let patch = JsonPatch::parse_patch(br#"[{"op":"replace","path":"/pool/size","value":20}]"#)?;
+//!  table.patch_json(b"db".to_vec(), &patch)?;
+//!  let merge = JsonPatch::parse_merge(br#"{"pool":{"timeout":null}}"#)?;
+//!  table.patch_json(b"db".to_vec(), &merge)?;
+//! ```
+use serde_json::{Value, Map};
+use crate::store::{StoreResult, StoreError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    /// fails the patch if the value at the path differs
+    Test { path: String, value: Value },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonPatch {
+    Patch(Vec<PatchOp>),
+    Merge(Value),
+}
+
+impl JsonPatch {
+    /// parses the array of operations of JSON Patch
+    pub fn parse_patch(bytes: &[u8]) -> StoreResult<JsonPatch> {
+        let ops = match parse(bytes)? {
+            Value::Array(ops) => ops,
+            _ => return Err(StoreError(String::from("the json patch is not an array"))),
+        };
+        ops.iter().map(PatchOp::from_value).collect::<StoreResult<Vec<_>>>().map(JsonPatch::Patch)
+    }
+
+    pub fn parse_merge(bytes: &[u8]) -> StoreResult<JsonPatch> {
+        parse(bytes).map(JsonPatch::Merge)
+    }
+
+    pub fn apply(&self, doc: &Value) -> StoreResult<Value> {
+        let mut doc = doc.clone();
+        match self {
+            JsonPatch::Patch(ops) => {
+                for op in ops.iter() {
+                    op.apply(&mut doc)?;
+                }
+            }
+            JsonPatch::Merge(patch) => merge(&mut doc, patch),
+        }
+        Ok(doc)
+    }
+
+    /// applies the patch to the serialized document
+    pub fn apply_bytes(&self, doc: &[u8]) -> StoreResult<Vec<u8>> {
+        let patched = self.apply(&parse(doc)?)?;
+        serde_json::to_vec(&patched).map_err(|e| StoreError(e.to_string()))
+    }
+}
+
+impl PatchOp {
+    fn from_value(op: &Value) -> StoreResult<PatchOp> {
+        let field = |name: &str| op.get(name).ok_or_else(|| StoreError(format!("the operation {} has no {}", op, name)));
+        let text = |name: &str| field(name)?.as_str().map(String::from)
+            .ok_or_else(|| StoreError(format!("the {} of the operation {} is not a string", name, op)));
+        match field("op")?.as_str() {
+            Some("add") => Ok(PatchOp::Add { path: text("path")?, value: field("value")?.clone() }),
+            Some("remove") => Ok(PatchOp::Remove { path: text("path")? }),
+            Some("replace") => Ok(PatchOp::Replace { path: text("path")?, value: field("value")?.clone() }),
+            Some("move") => Ok(PatchOp::Move { from: text("from")?, path: text("path")? }),
+            Some("copy") => Ok(PatchOp::Copy { from: text("from")?, path: text("path")? }),
+            Some("test") => Ok(PatchOp::Test { path: text("path")?, value: field("value")?.clone() }),
+            _ => Err(StoreError(format!("the operation {} is unknown", op))),
+        }
+    }
+
+    fn apply(&self, doc: &mut Value) -> StoreResult<()> {
+        match self {
+            PatchOp::Add { path, value } => add(doc, path, value.clone()),
+            PatchOp::Remove { path } => remove(doc, path).map(|_| ()),
+            PatchOp::Replace { path, value } => {
+                remove(doc, path)?;
+                add(doc, path, value.clone())
+            }
+            PatchOp::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(StoreError(format!("the path {} can not be moved into itself", from)));
+                }
+                let value = remove(doc, from)?;
+                add(doc, path, value)
+            }
+            PatchOp::Copy { from, path } => {
+                let value = get(doc, from)?.clone();
+                add(doc, path, value)
+            }
+            PatchOp::Test { path, value } => {
+                if get(doc, path)? != value {
+                    return Err(StoreError(format!("the test of {} has failed", path)));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> StoreResult<Value> {
+    serde_json::from_slice(bytes).map_err(|e| StoreError(format!("the value is not a json: {}", e)))
+}
+
+/// splits the pointer into the parent pointer and the unescaped last token
+fn split(path: &str) -> StoreResult<(&str, String)> {
+    match path.rfind('/') {
+        Some(pos) if path.starts_with('/') =>
+            Ok((&path[..pos], path[pos + 1..].replace("~1", "/").replace("~0", "~"))),
+        _ => Err(StoreError(format!("the path {} is not a json pointer", path))),
+    }
+}
+
+fn get<'a>(doc: &'a Value, path: &str) -> StoreResult<&'a Value> {
+    doc.pointer(path).ok_or_else(|| StoreError(format!("the path {} does not exist", path)))
+}
+
+fn parent<'a>(doc: &'a mut Value, path: &str) -> StoreResult<(&'a mut Value, String)> {
+    let (parent, token) = split(path)?;
+    let parent = doc.pointer_mut(parent).ok_or_else(|| StoreError(format!("the parent of {} does not exist", path)))?;
+    Ok((parent, token))
+}
+
+fn index(token: &str, len: usize, path: &str) -> StoreResult<usize> {
+    match token.parse::<usize>() {
+        Ok(i) if i <= len && (token == "0" || !token.starts_with('0')) => Ok(i),
+        _ => Err(StoreError(format!("the index of {} is out of bounds", path))),
+    }
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> StoreResult<()> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    match parent(doc, path)? {
+        (Value::Object(map), token) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        (Value::Array(arr), token) => {
+            let i = if token == "-" { arr.len() } else { index(&token, arr.len(), path)? };
+            arr.insert(i, value);
+            Ok(())
+        }
+        _ => Err(StoreError(format!("the parent of {} is not a container", path))),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> StoreResult<Value> {
+    if path.is_empty() {
+        return Ok(std::mem::replace(doc, Value::Null));
+    }
+    let missing = || StoreError(format!("the path {} does not exist", path));
+    match parent(doc, path)? {
+        (Value::Object(map), token) => map.remove(&token).ok_or_else(missing),
+        (Value::Array(arr), token) => match index(&token, arr.len(), path) {
+            Ok(i) if i < arr.len() => Ok(arr.remove(i)),
+            _ => Err(missing()),
+        },
+        _ => Err(missing()),
+    }
+}
+
+fn merge(doc: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(members) => {
+            if !doc.is_object() {
+                *doc = Value::Object(Map::new());
+            }
+            if let Value::Object(map) = doc {
+                for (k, v) in members.iter() {
+                    if v.is_null() {
+                        map.remove(k);
+                    } else {
+                        merge(map.entry(k.clone()).or_insert(Value::Null), v);
+                    }
+                }
+            }
+        }
+        _ => *doc = patch.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::json_patch::JsonPatch;
+    use serde_json::{json, Value};
+
+    fn patch(ops: Value) -> JsonPatch {
+        JsonPatch::parse_patch(ops.to_string().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn patch_test() {
+        let doc = json!({"db": {"host": "localhost", "ports": [5432, 5433]}, "a/b": 1});
+        let p = patch(json!([
+            {"op": "test", "path": "/db/host", "value": "localhost"},
+            {"op": "replace", "path": "/db/host", "value": "db.local"},
+            {"op": "add", "path": "/db/ports/-", "value": 5434},
+            {"op": "add", "path": "/db/ports/0", "value": 5431},
+            {"op": "remove", "path": "/db/ports/1"},
+            {"op": "copy", "from": "/db/host", "path": "/replica"},
+            {"op": "move", "from": "/a~1b", "path": "/c"},
+        ]));
+        assert_eq!(p.apply(&doc).unwrap(), json!({
+            "db": {"host": "db.local", "ports": [5431, 5433, 5434]},
+            "replica": "db.local",
+            "c": 1,
+        }));
+
+        for ops in [
+            json!([{"op": "test", "path": "/db/host", "value": "remote"}]),
+            json!([{"op": "remove", "path": "/absent"}]),
+            json!([{"op": "replace", "path": "/db/ports/5", "value": 1}]),
+            json!([{"op": "add", "path": "/db/ports/01", "value": 1}]),
+            json!([{"op": "move", "from": "/db", "path": "/db/inner"}]),
+        ] {
+            assert!(patch(ops).apply(&doc).is_err());
+        }
+        assert!(JsonPatch::parse_patch(br#"[{"op":"rename","path":"/a"}]"#).is_err());
+        assert!(JsonPatch::parse_patch(br#"{"op":"add"}"#).is_err());
+    }
+
+    #[test]
+    fn merge_test() {
+        let doc = json!({"title": "cfg", "db": {"host": "localhost", "timeout": 30}, "tags": ["a"]});
+        let merge = JsonPatch::parse_merge(br#"{"db":{"timeout":null,"size":10},"tags":["b"],"new":{"x":1}}"#).unwrap();
+        assert_eq!(merge.apply(&doc).unwrap(), json!({
+            "title": "cfg",
+            "db": {"host": "localhost", "size": 10},
+            "tags": ["b"],
+            "new": {"x": 1},
+        }));
+
+        let bytes = merge.apply_bytes(br#"{"db":"plain"}"#).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&bytes).unwrap(),
+                   json!({"db": {"size": 10}, "tags": ["b"], "new": {"x": 1}}));
+        assert!(merge.apply_bytes(b"not json").is_err());
+    }
+}
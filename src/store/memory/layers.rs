@@ -0,0 +1,86 @@
+//! Layered resolution of configuration.
+//! The logical key is looked up under several prefixes (layers) given in priority order,
+//! the last layer having the value wins. The result keeps the layer it comes from.
+//! # Examples
+//! ```
+//!  let layers = Layers::new(vec![b"defaults/".to_vec(), b"env/prod/".to_vec(), b"override/".to_vec()]);
+//!  let timeout: u64 = layers.resolve(&table, b"db.timeout")?.unwrap().parse()?;
+//! ```
+use std::str::FromStr;
+use crate::store::memory::MemTable;
+use crate::store::{StoreResult, StoreError};
+
+/// prefixes from the lowest priority to the highest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layers {
+    prefixes: Vec<Vec<u8>>,
+}
+
+/// the winning value with its provenance
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub value: Vec<u8>,
+    /// the prefix of the layer the value comes from
+    pub layer: Vec<u8>,
+    /// the full key the value is stored under
+    pub key: Vec<u8>,
+}
+
+impl Layers {
+    pub fn new(prefixes: Vec<Vec<u8>>) -> Self {
+        Layers { prefixes }
+    }
+
+    pub fn prefixes(&self) -> &[Vec<u8>] {
+        &self.prefixes
+    }
+
+    /// the value of the logical key from the layer with the highest priority
+    /// or none if no layer has the key (the deleted keys are absent)
+    pub fn resolve<T: MemTable<Vec<u8>, Vec<u8>>>(&self, table: &T, key: &[u8]) -> Option<Resolved> {
+        self.prefixes.iter().rev().find_map(|p| {
+            let mut full = p.clone();
+            full.extend_from_slice(key);
+            table.find(full.clone()).map(|value| Resolved { value, layer: p.clone(), key: full })
+        })
+    }
+}
+
+impl Resolved {
+    /// parses the value as utf8 text to the given type
+    pub fn parse<V: FromStr>(&self) -> StoreResult<V> {
+        let text = std::str::from_utf8(&self.value)
+            .map_err(|_| StoreError(format!("the value of {} is not utf8", String::from_utf8_lossy(&self.key))))?;
+        text.parse::<V>()
+            .map_err(|_| StoreError(format!("the value of {} can not be parsed: {}", String::from_utf8_lossy(&self.key), text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::layers::Layers;
+    use crate::store::memory::memtable::BaseMemTable;
+    use crate::store::memory::MemTable;
+
+    #[test]
+    fn resolve_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        table.put(b"defaults/timeout".to_vec(), b"10".to_vec()).unwrap();
+        table.put(b"defaults/host".to_vec(), b"localhost".to_vec()).unwrap();
+        table.put(b"env/prod/timeout".to_vec(), b"30".to_vec()).unwrap();
+        table.put(b"override/host".to_vec(), b"db".to_vec()).unwrap();
+
+        let layers = Layers::new(vec![b"defaults/".to_vec(), b"env/prod/".to_vec(), b"override/".to_vec()]);
+        let timeout = layers.resolve(&table, b"timeout").unwrap();
+        assert_eq!(timeout.layer, b"env/prod/".to_vec());
+        assert_eq!(timeout.parse::<u64>().unwrap(), 30);
+
+        let host = layers.resolve(&table, b"host").unwrap();
+        assert_eq!((host.value, host.key), (b"db".to_vec(), b"override/host".to_vec()));
+        assert!(layers.resolve(&table, b"absent").is_none());
+
+        table.delete(b"env/prod/timeout".to_vec()).unwrap();
+        assert_eq!(layers.resolve(&table, b"timeout").unwrap().layer, b"defaults/".to_vec());
+        assert!(layers.resolve(&table, b"host").unwrap().parse::<u64>().is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! Advisory in-process locks on keys and prefixes for the transactional operations.
+//! The locks are exclusive, a prefix lock conflicts with the locks of all keys and prefixes under it.
+//! `lock_all` takes the locks in order of their bytes so the callers locking the same scopes
+//! can not deadlock each other. Otherwise the waiting owners make a wait-for graph
+//! and the owner closing a cycle gets `StoreError::deadlock` instead of waiting forever.
+//! # Examples
+//! ```
+//!  let locks = LockManager::new();
+//!  locks.lock_all(tx, vec![LockScope::Key(b"db.host".to_vec()), LockScope::Prefix(b"db.pool.".to_vec())])?;
+//!  // read-modify-write
+//!  locks.unlock_all(tx);
+//! ```
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, Condvar, MutexGuard};
+use crate::store::{StoreResult, StoreError};
+
+/// the id of the lock owner (e.g. a transaction)
+pub type OwnerId = u64;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LockScope {
+    Key(Vec<u8>),
+    /// all keys starting with the prefix
+    Prefix(Vec<u8>),
+}
+
+impl LockScope {
+    pub fn conflicts(&self, other: &LockScope) -> bool {
+        match (self, other) {
+            (LockScope::Key(a), LockScope::Key(b)) => a == b,
+            (LockScope::Key(k), LockScope::Prefix(p)) | (LockScope::Prefix(p), LockScope::Key(k)) => k.starts_with(p),
+            (LockScope::Prefix(a), LockScope::Prefix(b)) => a.starts_with(b) || b.starts_with(a),
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            LockScope::Key(b) | LockScope::Prefix(b) => b,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    held: Vec<(LockScope, OwnerId)>,
+    /// the owners each waiting owner waits for
+    waits: HashMap<OwnerId, Vec<OwnerId>>,
+}
+
+#[derive(Debug, Default)]
+pub struct LockManager {
+    state: Mutex<LockState>,
+    cond: Condvar,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        LockManager::default()
+    }
+
+    /// takes the lock waiting for the conflicting owners to release theirs.
+    /// The lock conflicting only with the locks of the same owner is granted.
+    /// Can return `StoreError::deadlock` if the waiting closes a cycle
+    pub fn lock(&self, owner: OwnerId, scope: LockScope) -> StoreResult<()> {
+        let mut state = self.lock_state();
+        loop {
+            let holders = state.holders(owner, &scope);
+            if holders.is_empty() {
+                state.waits.remove(&owner);
+                if !state.held.iter().any(|(s, o)| *o == owner && *s == scope) {
+                    state.held.push((scope, owner));
+                }
+                return Ok(());
+            }
+            state.waits.insert(owner, holders);
+            if state.in_cycle(owner) {
+                state.waits.remove(&owner);
+                return Err(StoreError::deadlock(&format!(
+                    "the owner {} waiting for {:?} makes a cycle", owner, String::from_utf8_lossy(scope.bytes()))));
+            }
+            state = self.cond.wait(state).expect("the locks lock is poisoned");
+        }
+    }
+
+    /// takes the lock if it is free
+    /// # Returns
+    /// false if the lock is held by another owner
+    pub fn try_lock(&self, owner: OwnerId, scope: LockScope) -> bool {
+        let mut state = self.lock_state();
+        if !state.holders(owner, &scope).is_empty() {
+            return false;
+        }
+        if !state.held.iter().any(|(s, o)| *o == owner && *s == scope) {
+            state.held.push((scope, owner));
+        }
+        true
+    }
+
+    /// takes the locks in order of their bytes.
+    /// If a lock can not be taken the locks taken by this call are released
+    pub fn lock_all(&self, owner: OwnerId, mut scopes: Vec<LockScope>) -> StoreResult<()> {
+        scopes.sort_by(|a, b| a.bytes().cmp(b.bytes()).then(a.cmp(b)));
+        scopes.dedup();
+        let before: HashSet<LockScope> = self.held_by(owner).into_iter().collect();
+        for s in scopes.iter() {
+            if let Err(e) = self.lock(owner, s.clone()) {
+                let taken: Vec<&LockScope> = scopes.iter().filter(|s| !before.contains(*s)).collect();
+                let mut state = self.lock_state();
+                state.held.retain(|(s, o)| *o != owner || !taken.contains(&s));
+                self.cond.notify_all();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// releases all locks of the owner
+    pub fn unlock_all(&self, owner: OwnerId) {
+        let mut state = self.lock_state();
+        state.held.retain(|(_, o)| *o != owner);
+        self.cond.notify_all();
+    }
+
+    pub fn held_by(&self, owner: OwnerId) -> Vec<LockScope> {
+        self.lock_state().held.iter().filter(|(_, o)| *o == owner).map(|(s, _)| s.clone()).collect()
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, LockState> {
+        self.state.lock().expect("the locks lock is poisoned")
+    }
+}
+
+impl LockState {
+    fn holders(&self, owner: OwnerId, scope: &LockScope) -> Vec<OwnerId> {
+        let mut holders: Vec<OwnerId> = self.held.iter()
+            .filter(|(s, o)| *o != owner && s.conflicts(scope))
+            .map(|(_, o)| *o)
+            .collect();
+        holders.sort_unstable();
+        holders.dedup();
+        holders
+    }
+
+    /// true if the owner waits for itself through the wait-for graph
+    fn in_cycle(&self, owner: OwnerId) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack: Vec<OwnerId> = self.waits.get(&owner).cloned().unwrap_or_default();
+        while let Some(o) = stack.pop() {
+            if o == owner {
+                return true;
+            }
+            if visited.insert(o) {
+                if let Some(next) = self.waits.get(&o) {
+                    stack.extend(next.iter().cloned());
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::locks::{LockManager, LockScope};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn key(k: &str) -> LockScope {
+        LockScope::Key(k.as_bytes().to_vec())
+    }
+
+    fn prefix(p: &str) -> LockScope {
+        LockScope::Prefix(p.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn conflicts_test() {
+        assert!(key("a").conflicts(&key("a")));
+        assert!(!key("a").conflicts(&key("ab")));
+        assert!(prefix("db.").conflicts(&key("db.host")));
+        assert!(!key("web.host").conflicts(&prefix("db.")));
+        assert!(prefix("db.").conflicts(&prefix("db.pool.")));
+        assert!(!prefix("db.").conflicts(&prefix("web.")));
+    }
+
+    #[test]
+    fn lock_test() {
+        let locks = LockManager::new();
+        locks.lock(1, prefix("db.")).unwrap();
+        locks.lock(1, key("db.host")).unwrap();
+        assert!(!locks.try_lock(2, key("db.port")));
+        assert!(locks.try_lock(2, key("web.port")));
+
+        locks.lock_all(2, vec![key("z"), key("a"), key("a")]).unwrap();
+        assert_eq!(locks.held_by(2), vec![key("web.port"), key("a"), key("z")]);
+
+        locks.unlock_all(1);
+        assert!(locks.try_lock(2, key("db.port")));
+        locks.unlock_all(2);
+        assert!(locks.held_by(2).is_empty());
+    }
+
+    #[test]
+    fn wait_test() {
+        let locks = Arc::new(LockManager::new());
+        locks.lock(1, key("a")).unwrap();
+        let waiting = locks.clone();
+        let waiter = thread::spawn(move || {
+            waiting.lock(2, prefix("")).unwrap();
+            waiting.held_by(2)
+        });
+        thread::sleep(Duration::from_millis(20));
+        locks.unlock_all(1);
+        assert_eq!(waiter.join().unwrap(), vec![prefix("")]);
+    }
+
+    #[test]
+    fn deadlock_test() {
+        let locks = Arc::new(LockManager::new());
+        locks.lock(1, key("a")).unwrap();
+        locks.lock(2, key("b")).unwrap();
+
+        let waiting = locks.clone();
+        let waiter = thread::spawn(move || waiting.lock(1, key("b")));
+        while !locks.lock_state().waits.contains_key(&1) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let err = locks.lock_all(2, vec![key("c"), key("a")]).unwrap_err();
+        assert!(err.is_deadlock(), "{}", err.0);
+        assert_eq!(locks.held_by(2), vec![key("b")]);
+
+        locks.unlock_all(2);
+        assert!(waiter.join().unwrap().is_ok());
+        assert_eq!(locks.held_by(1), vec![key("a"), key("b")]);
+    }
+}
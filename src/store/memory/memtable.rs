@@ -1,12 +1,735 @@
 use crate::store::structures::skip_list::SkipList;
-use crate::store::structures::cuckoo_filter::CuckooFilter;
+use crate::store::structures::cuckoo_filter::{CuckooFilter, InsertResult};
 use std::hash::Hash;
 use crate::store::ToBytes;
+use crate::store::memory::{MemTable, MemResult};
+use std::cell::{RefCell, Cell};
+use crate::store::memory::template::{resolve, DEFAULT_MAX_DEPTH};
+use crate::store::{StoreResult, StoreError};
+use crate::store::memory::json_patch::JsonPatch;
+use crate::store::memory::query::Query;
+use crate::codec::{self, Value};
+use crate::store::structures::expiry_index::ExpiryIndex;
+use crate::store::structures::tag_index::{TagIndex, Tag};
+use crate::store::log::transaction_log::{time_now_millis, Record, RecordType};
+use std::time::Duration;
+use crate::store::memory::content_type::ContentType;
 
-struct BaseMemTable<K, V>
+/// the operation which has produced the entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemOp {
+    Put,
+    Delete,
+}
+
+/// the last version of the key kept in the memtable.
+/// The deleted keys are kept as tombstones (without value) until they get flushed
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemEntry<V> {
+    pub seq: u64,
+    pub op: MemOp,
+    pub val: Option<V>,
+    /// the expiration time in millis
+    pub expires_at: Option<u128>,
+    pub content_type: Option<ContentType>,
+}
+
+impl<V> MemEntry<V> {
+    pub fn put(seq: u64, val: V) -> Self {
+        MemEntry { seq, op: MemOp::Put, val: Some(val), expires_at: None, content_type: None }
+    }
+
+    pub fn tombstone(seq: u64) -> Self {
+        MemEntry { seq, op: MemOp::Delete, val: None, expires_at: None, content_type: None }
+    }
+
+    pub fn is_expired(&self, now: u128) -> bool {
+        self.expires_at.map(|e| e <= now).unwrap_or(false)
+    }
+}
+
+/// the entry for the table writer
+pub(crate) type FlushEntry<K, V> = (K, MemEntry<V>);
+
+pub struct BaseMemTable<K, V>
     where K: Ord + Clone + Hash + ToBytes, V: Clone + ToBytes {
-    data: SkipList<K, V>,
-    filter: CuckooFilter<K>,
-    size: u64,
+    data: RefCell<SkipList<K, MemEntry<V>>>,
+    filter: RefCell<CuckooFilter<K>>,
+    /// the filter could not take a key so it can not be trusted for absence anymore
+    filter_full: Cell<bool>,
+    expiry: RefCell<ExpiryIndex<K>>,
+    /// the optional index of the values by tags, see `register_tags`
+    tags: RefCell<Option<TagIndex<K, V>>>,
+    size: Cell<u64>,
     limit: u64,
-}
\ No newline at end of file
+    /// the seq for the writes coming through `MemTable`
+    next_seq: Cell<u64>,
+}
+
+impl<K, V> BaseMemTable<K, V>
+    where K: Ord + Clone + Hash + ToBytes, V: Clone + ToBytes {
+    /// # Arguments
+    /// * `limit` the size in bytes (keys and values) after which the memtable should be flushed
+    pub fn new(limit: u64) -> Self {
+        BaseMemTable {
+            data: RefCell::new(SkipList::new()),
+            filter: RefCell::new(CuckooFilter::default()),
+            filter_full: Cell::new(false),
+            expiry: RefCell::new(ExpiryIndex::new()),
+            tags: RefCell::new(None),
+            size: Cell::new(0),
+            limit,
+            next_seq: Cell::new(0),
+        }
+    }
+
+    /// puts the value with the seq assigned by the log
+    pub fn put_at(&self, key: K, val: V, seq: u64) {
+        self.upsert(key, MemEntry::put(seq, val))
+    }
+
+    /// puts the value expiring at the time in millis with the seq assigned by the log
+    pub fn put_at_expiring(&self, key: K, val: V, seq: u64, expires_at: u128) {
+        self.upsert(key, MemEntry { expires_at: Some(expires_at), ..MemEntry::put(seq, val) })
+    }
+
+    /// puts the value which is not visible after the ttl passes
+    pub fn put_with_ttl(&self, key: K, val: V, ttl: Duration) {
+        let expires_at = time_now_millis().saturating_add(ttl.as_millis());
+        self.put_at_expiring(key, val, self.next_seq.get(), expires_at)
+    }
+
+    /// puts the value tagged with the content type with the seq assigned by the log
+    pub fn put_typed_at(&self, key: K, val: V, seq: u64, content_type: ContentType) {
+        self.upsert(key, MemEntry { content_type: Some(content_type), ..MemEntry::put(seq, val) })
+    }
+
+    pub fn put_typed(&self, key: K, val: V, content_type: ContentType) {
+        self.put_typed_expiring(key, val, content_type, None)
+    }
+
+    /// puts the value tagged with the content type as the next version of the key
+    fn put_typed_expiring(&self, key: K, val: V, content_type: ContentType, expires_at: Option<u128>) {
+        let seq = self.next_seq.get();
+        self.upsert(key, MemEntry { content_type: Some(content_type), expires_at, ..MemEntry::put(seq, val) })
+    }
+
+    /// keeps the tombstone for the key with the seq assigned by the log
+    pub fn delete_at(&self, key: K, seq: u64) {
+        self.upsert(key, MemEntry::tombstone(seq))
+    }
+
+    /// the content type of the visible value or none if it is absent or has no type
+    pub fn content_type(&self, key: &K) -> Option<ContentType> {
+        self.get(key).filter(|e| !e.is_expired(time_now_millis())).and_then(|e| e.content_type)
+    }
+
+    /// the last version of the key including tombstones and expired values
+    pub fn get(&self, key: &K) -> Option<MemEntry<V>> {
+        if !self.filter_full.get() && !self.filter.borrow_mut().contains(key) {
+            return None;
+        }
+        self.data.borrow().search(key)
+    }
+
+    /// the keys and values in order. The tombstones and expired values are skipped
+    pub fn iter(&self) -> impl Iterator<Item=(K, V)> {
+        self.iter_typed().map(|(k, v, _)| (k, v))
+    }
+
+    /// the keys and values with their content types in order
+    pub fn iter_typed(&self) -> impl Iterator<Item=(K, V, Option<ContentType>)> {
+        let now = time_now_millis();
+        self.data.borrow().entries()
+            .filter(move |(_, e)| !e.is_expired(now))
+            .filter_map(|(k, e)| {
+                let content_type = e.content_type;
+                e.val.map(|v| (k, v, content_type))
+            })
+    }
+
+    /// all entries in order with seqs and tombstones for flushing.
+    /// The expired values are flushed as tombstones
+    pub(crate) fn flush_iter(&self) -> impl Iterator<Item=FlushEntry<K, V>> {
+        let now = time_now_millis();
+        self.data.borrow().entries().map(move |(k, e)| {
+            if e.is_expired(now) {
+                (k, MemEntry::tombstone(e.seq))
+            } else {
+                (k, e)
+            }
+        })
+    }
+
+    /// indexes the values by the tags extracted by the function (replacing the previous one)
+    /// including the values which are already in the memtable, see `find_by_tag`
+    pub fn register_tags<F>(&self, extractor: F) where F: Fn(&V) -> Vec<Tag> + 'static {
+        let mut idx = TagIndex::new(extractor);
+        for (k, e) in self.data.borrow().entries() {
+            if let Some(v) = e.val {
+                idx.insert(k, &v);
+            }
+        }
+        *self.tags.borrow_mut() = Some(idx);
+    }
+
+    /// the keys having the tag and their values in order.
+    /// The expired values are skipped, nothing is found if the tags are not registered
+    pub fn find_by_tag(&self, tag: &str) -> Vec<(K, V)> {
+        let keys = match self.tags.borrow().as_ref() {
+            Some(idx) => idx.find(&tag.to_string()),
+            None => return vec![],
+        };
+        let now = time_now_millis();
+        keys.into_iter()
+            .filter_map(|k| self.get(&k).filter(|e| !e.is_expired(now)).and_then(|e| e.val).map(|v| (k, v)))
+            .collect()
+    }
+
+    /// the keys expiring before the time in millis in order of expiration
+    pub fn expiring_before(&self, ts: u128) -> Vec<(K, u128)> {
+        self.expiry.borrow().expiring_before(ts)
+    }
+
+    /// replaces the values expired at the time in millis with tombstones keeping their seqs
+    /// # Returns
+    /// the number of purged values
+    pub fn purge_expired(&self, now: u128) -> usize {
+        let expired = self.expiry.borrow_mut().pop_expired(now);
+        for (k, _) in expired.iter() {
+            let seq = self.data.borrow().search(k).map(|e| e.seq);
+            if let Some(seq) = seq {
+                self.upsert(k.clone(), MemEntry::tombstone(seq));
+            }
+        }
+        expired.len()
+    }
+
+    /// removes the tombstones with seqs less than `seq` (e.g. flushed to the tables)
+    /// from the memtable and the filter. The expired values get there as tombstones by `purge_expired`.
+    /// The filter which has been full is rebuilt so it can be trusted again
+    /// # Returns
+    /// the number of removed keys
+    pub fn purge_tombstones(&self, seq: u64) -> usize {
+        let keys: Vec<K> = self.data.borrow().entries()
+            .filter(|(_, e)| e.val.is_none() && e.seq < seq)
+            .map(|(k, _)| k)
+            .collect();
+        for k in keys.iter() {
+            self.remove(k);
+        }
+        if self.filter_full.get() && !keys.is_empty() {
+            self.rebuild_filter();
+        }
+        keys.len()
+    }
+
+    /// removes all entries (e.g. the memtable has been flushed) and resets the filter
+    pub fn clear(&self) {
+        self.data.borrow_mut().clear();
+        *self.expiry.borrow_mut() = ExpiryIndex::new();
+        if let Some(idx) = self.tags.borrow_mut().as_mut() {
+            idx.clear();
+        }
+        *self.filter.borrow_mut() = CuckooFilter::default();
+        self.filter_full.set(false);
+        self.size.set(0);
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size.get()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.size.get() >= self.limit
+    }
+
+    fn remove(&self, key: &K) {
+        let old = self.data.borrow_mut().delete(key);
+        if let Some(old) = old {
+            self.size.set(self.size.get() - entry_size(key, &old));
+            if !self.filter_full.get() {
+                self.filter.borrow_mut().remove(key);
+            }
+            self.expiry.borrow_mut().remove(key);
+            if let Some(idx) = self.tags.borrow_mut().as_mut() {
+                idx.remove(key);
+            }
+        }
+    }
+
+    /// puts the keys which are left into a new filter
+    fn rebuild_filter(&self) {
+        let mut filter = CuckooFilter::default();
+        let full = self.data.borrow().entries().any(|(k, _)| !matches!(filter.insert(&k), InsertResult::Done(_)));
+        *self.filter.borrow_mut() = filter;
+        self.filter_full.set(full);
+    }
+
+    fn upsert(&self, key: K, entry: MemEntry<V>) {
+        let new_size = entry_size(&key, &entry);
+        if entry.seq >= self.next_seq.get() {
+            self.next_seq.set(entry.seq + 1);
+        }
+        match entry.expires_at {
+            Some(e) => self.expiry.borrow_mut().insert(key.clone(), e),
+            None => self.expiry.borrow_mut().remove(&key),
+        };
+        if let Some(idx) = self.tags.borrow_mut().as_mut() {
+            match entry.val.as_ref() {
+                Some(v) => idx.insert(key.clone(), v),
+                None => { idx.remove(&key); }
+            }
+        }
+        let old = self.data.borrow_mut().insert(key.clone(), entry);
+        // the filter keeps one copy of the key, the key updated again is already there
+        if old.is_none() && !self.filter_full.get() {
+            match self.filter.borrow_mut().insert(&key) {
+                InsertResult::Done(_) => (),
+                InsertResult::Full | InsertResult::Fail(_) => self.filter_full.set(true),
+            }
+        }
+        match old {
+            Some(old) => self.size.set(self.size.get() + new_size - entry_size(&key, &old)),
+            None => self.size.set(self.size.get() + new_size),
+        }
+    }
+}
+
+impl<K, V> MemTable<K, V> for BaseMemTable<K, V>
+    where K: Ord + Clone + Hash + ToBytes, V: Clone + ToBytes {
+    /// false if the key is definitely absent
+    fn check(&self, key: K) -> bool {
+        self.filter_full.get() || self.filter.borrow_mut().contains(&key)
+    }
+
+    /// the value of the key or none if the key is absent, deleted or expired
+    fn find(&self, key: K) -> Option<V> {
+        self.get(&key).filter(|e| !e.is_expired(time_now_millis())).and_then(|e| e.val)
+    }
+
+    fn put(&self, key: K, value: V) -> MemResult {
+        self.put_at(key, value, self.next_seq.get());
+        Ok(())
+    }
+
+    fn delete(&self, key: K) -> MemResult {
+        self.delete_at(key, self.next_seq.get());
+        Ok(())
+    }
+}
+
+impl BaseMemTable<Vec<u8>, Vec<u8>> {
+    /// the value of the key with the references to other keys resolved.
+    /// see `template::resolve`
+    pub fn get_resolved(&self, key: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        match self.find(key.to_vec()) {
+            None => Ok(None),
+            Some(v) => resolve(&v, &|k: &[u8]| self.find(k.to_vec()), DEFAULT_MAX_DEPTH).map(Some),
+        }
+    }
+
+    /// the keys starting with the prefix and their values in order.
+    /// The tombstones and expired values are skipped
+    pub fn scan_prefix(&self, prefix: &[u8]) -> impl Iterator<Item=(Vec<u8>, Vec<u8>)> {
+        let now = time_now_millis();
+        let prefix = prefix.to_vec();
+        self.data.borrow().entries_from(&prefix)
+            .take_while(move |(k, _)| k.starts_with(&prefix))
+            .filter(move |(_, e)| !e.is_expired(now))
+            .filter_map(|(k, e)| e.val.map(|v| (k, v)))
+    }
+
+    /// the visible entries matching the query in order.
+    /// The longest prefix of the query is pushed down to the scan
+    pub fn query(&self, query: &Query) -> impl Iterator<Item=(Vec<u8>, MemEntry<Vec<u8>>)> {
+        let now = time_now_millis();
+        let prefix = query.prefix();
+        let query = query.clone();
+        self.data.borrow().entries_from(&prefix)
+            .take_while(move |(k, _)| k.starts_with(&prefix))
+            .filter(move |(k, e)| e.val.is_some() && !e.is_expired(now) && query.matches(k, e))
+    }
+
+    /// the value decoded according to its content type, the untagged values are decoded as binary.
+    /// see `codec`
+    pub fn get_value(&self, key: &[u8]) -> StoreResult<Option<Value>> {
+        match self.find(key.to_vec()) {
+            None => Ok(None),
+            Some(v) => {
+                let ct = self.content_type(&key.to_vec()).unwrap_or(ContentType::Binary);
+                codec::decode(ct, &v).map(Some)
+            }
+        }
+    }
+
+    /// encodes the value in the content type and puts it tagged with the type
+    pub fn put_value(&self, key: Vec<u8>, val: &Value, content_type: ContentType) -> StoreResult<()> {
+        let bytes = codec::encode(content_type, val)?;
+        self.put_typed(key, bytes, content_type);
+        Ok(())
+    }
+
+    /// applies the record read from the log with its seq.
+    /// The record older or equal to the version of the key in the memtable is ignored
+    /// so replaying the log again or out of order gives the same state.
+    /// The records without a seq (the v1 format) are applied as the newest ones,
+    /// the locks and unknown records are ignored
+    /// # Returns
+    /// true if the record has changed the memtable
+    pub fn apply(&self, record: &Record) -> bool {
+        let key = record.key().to_vec();
+        let seq = match record.seq() {
+            Some(seq) if self.get(&key).map(|e| e.seq >= seq).unwrap_or(false) => return false,
+            Some(seq) => seq,
+            None => self.next_seq.get(),
+        };
+        let entry = match record.operation() {
+            RecordType::Insert => MemEntry::put(seq, record.val().to_vec()),
+            RecordType::Delete => MemEntry::tombstone(seq),
+            RecordType::Lock | RecordType::Unknown(_) => return false,
+        };
+        self.upsert(key, MemEntry { expires_at: record.expires_at(), content_type: record.content_type(), ..entry });
+        true
+    }
+
+    /// patches the value tagged as json and puts the result keeping the tag and the expiration time.
+    /// If the patch fails the value is left untouched
+    /// # Returns
+    /// the patched value
+    pub fn patch_json(&self, key: Vec<u8>, patch: &JsonPatch) -> StoreResult<Vec<u8>> {
+        let entry = self.get(&key)
+            .filter(|e| !e.is_expired(time_now_millis()))
+            .and_then(|e| e.val.clone().map(|v| (v, e)));
+        let (val, entry) = match entry {
+            Some((v, e)) if e.content_type == Some(ContentType::Json) => (v, e),
+            Some(_) => return Err(StoreError(format!("the value of {} is not tagged as json", String::from_utf8_lossy(&key)))),
+            None => return Err(StoreError(format!("the key {} is absent", String::from_utf8_lossy(&key)))),
+        };
+        let patched = patch.apply_bytes(&val)?;
+        self.put_typed_expiring(key, patched.clone(), ContentType::Json, entry.expires_at);
+        Ok(patched)
+    }
+}
+
+fn entry_size<K: ToBytes, V: ToBytes>(key: &K, entry: &MemEntry<V>) -> u64 {
+    let val = entry.val.as_ref().map(|v| v.to_bytes().len()).unwrap_or(0);
+    (key.to_bytes().len() + val) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::memtable::{BaseMemTable, MemOp, MemEntry};
+    use crate::store::memory::MemTable;
+    use std::time::Duration;
+    use crate::store::memory::content_type::ContentType;
+    use crate::store::memory::json_patch::JsonPatch;
+    use crate::store::memory::query::Query;
+    use crate::codec::Value;
+    use crate::store::log::transaction_log::{Record, time_now_millis};
+
+    #[test]
+    fn put_find_test() {
+        let table: BaseMemTable<i64, i64> = BaseMemTable::new(100);
+        table.put_at(1, 10, 0);
+        table.put_at(2, 20, 1);
+        assert_eq!(table.find(1), Some(10));
+        assert_eq!(table.find(3), None);
+        assert!(table.check(1));
+
+        table.put_at(1, 11, 2);
+        assert_eq!(table.find(1), Some(11));
+        assert_eq!(table.size(), 32);
+        assert!(!table.is_full());
+    }
+
+    #[test]
+    fn tombstone_test() {
+        let table: BaseMemTable<i64, i64> = BaseMemTable::new(100);
+        table.put_at(1, 10, 0);
+        table.put_at(2, 20, 1);
+        table.delete_at(1, 2);
+        table.delete_at(3, 3);
+
+        assert_eq!(table.find(1), None);
+        assert_eq!(table.get(&1), Some(MemEntry::tombstone(2)));
+        assert_eq!(table.iter().collect::<Vec<(i64, i64)>>(), vec![(2, 20)]);
+        assert_eq!(table.size(), 32);
+
+        let flushed: Vec<_> = table.flush_iter().collect();
+        assert_eq!(flushed, vec![
+            (1, MemEntry::tombstone(2)),
+            (2, MemEntry::put(1, 20)),
+            (3, MemEntry::tombstone(3)),
+        ]);
+    }
+
+    #[test]
+    fn mem_table_delete_test() {
+        let table: BaseMemTable<i64, i64> = BaseMemTable::new(100);
+        table.put(1, 10).unwrap();
+        table.put(2, 20).unwrap();
+        table.delete(1).unwrap();
+
+        assert_eq!(table.find(1), None);
+        assert!(table.check(1));
+        assert_eq!(table.get(&1), Some(MemEntry::tombstone(2)));
+        assert_eq!(table.flush_iter().count(), 2);
+
+        table.put(1, 11).unwrap();
+        assert_eq!(table.get(&1), Some(MemEntry::put(3, 11)));
+    }
+
+    #[test]
+    fn ttl_test() {
+        let table: BaseMemTable<i64, i64> = BaseMemTable::new(100);
+        table.put_at_expiring(1, 10, 0, 1);
+        table.put_with_ttl(2, 20, Duration::from_secs(3600));
+        table.put_at(3, 30, 2);
+
+        assert_eq!(table.find(1), None);
+        assert_eq!(table.get(&1).map(|e| e.expires_at), Some(Some(1)));
+        assert_eq!(table.find(2), Some(20));
+        assert_eq!(table.iter().collect::<Vec<(i64, i64)>>(), vec![(2, 20), (3, 30)]);
+        assert_eq!(table.expiring_before(2), vec![(1, 1)]);
+
+        let flushed: Vec<_> = table.flush_iter().map(|(k, e)| (k, e.seq, e.op, e.val)).collect();
+        assert_eq!(flushed, vec![
+            (1, 0, MemOp::Delete, None),
+            (2, 1, MemOp::Put, Some(20)),
+            (3, 2, MemOp::Put, Some(30)),
+        ]);
+
+        table.put_at(2, 21, 3);
+        assert_eq!(table.expiring_before(u128::MAX), vec![(1, 1)]);
+        assert_eq!(table.purge_expired(1), 1);
+        assert_eq!(table.get(&1), Some(MemEntry::tombstone(0)));
+        assert!(table.expiring_before(u128::MAX).is_empty());
+        assert_eq!(table.size(), 40);
+    }
+
+    #[test]
+    fn content_type_test() {
+        let table: BaseMemTable<i64, i64> = BaseMemTable::new(100);
+        table.put_typed(1, 10, ContentType::Json);
+        table.put(2, 20).unwrap();
+        assert_eq!(table.content_type(&1), Some(ContentType::Json));
+        assert_eq!(table.content_type(&2), None);
+        assert_eq!(table.iter_typed().collect::<Vec<_>>(), vec![(1, 10, Some(ContentType::Json)), (2, 20, None)]);
+
+        table.put(1, 11).unwrap();
+        assert_eq!(table.content_type(&1), None);
+        table.put_typed_at(2, 21, 5, ContentType::Toml);
+        table.delete(1).unwrap();
+        assert_eq!(table.content_type(&1), None);
+        assert_eq!(table.get(&2).and_then(|e| e.content_type), Some(ContentType::Toml));
+        assert_eq!(table.flush_iter().map(|(_, e)| e.seq).collect::<Vec<_>>(), vec![6, 5]);
+    }
+
+    #[test]
+    fn scan_prefix_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        for k in ["service/db/host", "service/db/port", "service/dbx", "service/web/host", "service", "z"] {
+            table.put(k.as_bytes().to_vec(), k.as_bytes().to_vec()).unwrap();
+        }
+        table.delete(b"service/db/port".to_vec()).unwrap();
+        table.put_at_expiring(b"service/db/pool".to_vec(), b"10".to_vec(), 10, 1);
+
+        let keys = |p: &[u8]| table.scan_prefix(p).map(|(k, _)| String::from_utf8(k).unwrap()).collect::<Vec<_>>();
+        assert_eq!(keys(b"service/db/"), vec!["service/db/host"]);
+        assert_eq!(keys(b"service/"), vec!["service/db/host", "service/dbx", "service/web/host"]);
+        assert_eq!(keys(b"").len(), 5);
+        assert!(keys(b"absent").is_empty());
+    }
+
+    #[test]
+    fn query_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        table.put_typed(b"service.db".to_vec(), b"{}".to_vec(), ContentType::Json);
+        table.put_typed(b"service.web".to_vec(), b"a = 1".to_vec(), ContentType::Toml);
+        table.put_typed(b"service.cache".to_vec(), b"{}".to_vec(), ContentType::Json);
+        table.put_typed(b"other".to_vec(), b"{}".to_vec(), ContentType::Json);
+        table.delete(b"service.cache".to_vec()).unwrap();
+        table.put_typed(b"service.queue".to_vec(), b"[]".to_vec(), ContentType::Json);
+
+        let keys = |q: &str| table.query(&Query::parse(q).unwrap())
+            .map(|(k, _)| String::from_utf8(k).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(keys(r#"prefix = "service." AND value.type = json"#), vec!["service.db", "service.queue"]);
+        assert_eq!(keys("value.type = json AND seq < 5"), vec!["other", "service.db"]);
+        assert_eq!(keys("").len(), 4);
+    }
+
+    #[test]
+    fn typed_value_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        table.put_typed(b"db".to_vec(), b"[pool]\nsize = 10\nhosts = [\"a\", \"b\"]".to_vec(), ContentType::Toml);
+        table.put(b"raw".to_vec(), vec![0, 1]).unwrap();
+
+        let db = table.get_value(b"db").unwrap().unwrap();
+        assert_eq!(db.path("pool.size").and_then(Value::as_i64), Some(10));
+        assert_eq!(db.path("pool.hosts.0").and_then(Value::as_str), Some("a"));
+        assert_eq!(table.get_value(b"raw").unwrap(), Some(Value::Bytes(vec![0, 1])));
+        assert_eq!(table.get_value(b"absent").unwrap(), None);
+
+        table.put_value(b"db.yaml".to_vec(), &db, ContentType::Yaml).unwrap();
+        assert_eq!(table.content_type(&b"db.yaml".to_vec()), Some(ContentType::Yaml));
+        assert_eq!(table.get_value(b"db.yaml").unwrap(), Some(db));
+        assert!(table.put_value(b"bad".to_vec(), &Value::Null, ContentType::Toml).is_err());
+        assert_eq!(table.find(b"bad".to_vec()), None);
+    }
+
+    #[test]
+    fn patch_json_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        table.put_typed(b"db".to_vec(), br#"{"host":"localhost","size":10}"#.to_vec(), ContentType::Json);
+        table.put(b"raw".to_vec(), br#"{"size":1}"#.to_vec()).unwrap();
+
+        let merge = JsonPatch::parse_merge(br#"{"size":20}"#).unwrap();
+        assert_eq!(table.patch_json(b"db".to_vec(), &merge).unwrap(), br#"{"host":"localhost","size":20}"#.to_vec());
+        assert_eq!(table.find(b"db".to_vec()), Some(br#"{"host":"localhost","size":20}"#.to_vec()));
+        assert_eq!(table.get(&b"db".to_vec()).map(|e| (e.seq, e.content_type)), Some((2, Some(ContentType::Json))));
+
+        let failing = JsonPatch::parse_patch(br#"[{"op":"remove","path":"/host"},{"op":"remove","path":"/port"}]"#).unwrap();
+        assert!(table.patch_json(b"db".to_vec(), &failing).is_err());
+        assert_eq!(table.find(b"db".to_vec()), Some(br#"{"host":"localhost","size":20}"#.to_vec()));
+        assert!(table.patch_json(b"raw".to_vec(), &merge).is_err());
+        assert!(table.patch_json(b"absent".to_vec(), &merge).is_err());
+    }
+
+    #[test]
+    fn patch_json_version_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        let expires_at = time_now_millis() + 60_000;
+        table.put_typed_expiring(b"db".to_vec(), br#"{"size":1}"#.to_vec(), ContentType::Json, Some(expires_at));
+        table.put(b"other".to_vec(), b"1".to_vec()).unwrap();
+
+        let merge = JsonPatch::parse_merge(br#"{"size":2}"#).unwrap();
+        table.patch_json(b"db".to_vec(), &merge).unwrap();
+        let patched = table.get(&b"db".to_vec()).unwrap();
+        assert_eq!(patched, MemEntry {
+            expires_at: Some(expires_at),
+            content_type: Some(ContentType::Json),
+            ..MemEntry::put(2, br#"{"size":2}"#.to_vec())
+        });
+
+        table.put_value(b"other".to_vec(), &Value::Bool(true), ContentType::Json).unwrap();
+        assert_eq!(table.get(&b"other".to_vec()).map(|e| e.seq), Some(3));
+        table.patch_json(b"db".to_vec(), &merge).unwrap();
+        assert_eq!(table.get(&b"db".to_vec()).map(|e| e.seq), Some(4));
+    }
+
+    #[test]
+    fn get_resolved_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        table.put(b"host".to_vec(), b"localhost".to_vec()).unwrap();
+        table.put(b"url".to_vec(), b"http://${host}:8080".to_vec()).unwrap();
+        table.put(b"loop".to_vec(), b"${loop}".to_vec()).unwrap();
+
+        assert_eq!(table.get_resolved(b"url").unwrap(), Some(b"http://localhost:8080".to_vec()));
+        assert_eq!(table.get_resolved(b"absent").unwrap(), None);
+        assert!(table.get_resolved(b"loop").is_err());
+
+        table.delete(b"host".to_vec()).unwrap();
+        assert!(table.get_resolved(b"url").is_err());
+    }
+
+    #[test]
+    fn apply_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        let put = Record::insert_record(b"a".to_vec(), b"1".to_vec()).with_content_type(ContentType::Text).with_seq(3);
+        let older = Record::insert_record(b"a".to_vec(), b"0".to_vec()).with_seq(1);
+        let delete = Record::delete_record(b"a".to_vec(), vec![]).with_seq(5);
+
+        assert!(table.apply(&put));
+        assert!(!table.apply(&put));
+        assert!(!table.apply(&older));
+        assert_eq!(table.find(b"a".to_vec()), Some(b"1".to_vec()));
+        assert_eq!(table.content_type(&b"a".to_vec()), Some(ContentType::Text));
+
+        assert!(table.apply(&delete));
+        assert!(!table.apply(&put));
+        assert_eq!(table.get(&b"a".to_vec()).unwrap(), MemEntry::tombstone(5));
+        assert!(!table.apply(&Record::lock_record(b"b".to_vec(), vec![])));
+
+        assert!(table.apply(&Record::insert_record(b"a".to_vec(), b"2".to_vec())));
+        assert_eq!(table.get(&b"a".to_vec()).unwrap().seq, 6);
+    }
+
+    #[test]
+    fn purge_tombstones_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        for i in 0..10u8 {
+            table.put_at(vec![i], vec![i], i as u64);
+        }
+        table.delete_at(vec![1], 10);
+        table.delete_at(vec![2], 11);
+        table.put_at_expiring(vec![3], vec![3], 12, 1);
+        assert_eq!(table.purge_expired(2), 1);
+        let size = table.size();
+
+        assert_eq!(table.purge_tombstones(11), 1);
+        assert_eq!(table.get(&vec![1]), None);
+        assert!(!table.filter.borrow_mut().contains(&vec![1]));
+        assert_eq!(table.get(&vec![2]).map(|e| e.op), Some(MemOp::Delete));
+        assert_eq!(table.size(), size - 1);
+        assert_eq!(table.purge_tombstones(100), 2);
+        assert!(!table.check(vec![3]));
+        assert_eq!(table.filter.borrow().len(), 7);
+        assert_eq!(table.iter().count(), 7);
+
+        table.filter_full.set(true);
+        table.delete_at(vec![4], 13);
+        assert_eq!(table.purge_tombstones(100), 1);
+        assert!(!table.filter_full.get());
+        assert_eq!(table.filter.borrow().len(), 6);
+        assert_eq!(table.find(vec![5]), Some(vec![5]));
+
+        table.filter_full.set(true);
+        table.clear();
+        assert!(!table.filter_full.get());
+        assert!(table.filter.borrow().is_empty());
+        assert_eq!(table.size(), 0);
+        assert_eq!(table.iter().count(), 0);
+        table.put(vec![5], vec![50]).unwrap();
+        assert_eq!(table.find(vec![5]), Some(vec![50]));
+    }
+
+    #[test]
+    fn filter_update_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        for i in 0..10_000u32 {
+            table.put(b"host".to_vec(), i.to_be_bytes().to_vec()).unwrap();
+        }
+        assert!(!table.filter_full.get());
+        assert_eq!(table.filter.borrow().len(), 1);
+        assert!(!table.check(b"port".to_vec()));
+    }
+
+    #[test]
+    fn find_by_tag_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        table.put(b"db/a".to_vec(), br#"{"env":"prod"}"#.to_vec()).unwrap();
+        assert!(table.find_by_tag("env=prod").is_empty());
+
+        table.register_tags(|v: &Vec<u8>| {
+            serde_json::from_slice::<serde_json::Value>(v).ok()
+                .and_then(|doc| doc.get("env").and_then(|e| e.as_str()).map(|e| vec![format!("env={}", e)]))
+                .unwrap_or_default()
+        });
+        table.put(b"db/b".to_vec(), br#"{"env":"dev"}"#.to_vec()).unwrap();
+        table.put(b"db/c".to_vec(), br#"{"env":"prod"}"#.to_vec()).unwrap();
+        table.put(b"db/d".to_vec(), b"plain".to_vec()).unwrap();
+        table.put_with_ttl(b"db/e".to_vec(), br#"{"env":"prod"}"#.to_vec(), Duration::from_millis(0));
+        assert_eq!(table.find_by_tag("env=prod"), vec![
+            (b"db/a".to_vec(), br#"{"env":"prod"}"#.to_vec()),
+            (b"db/c".to_vec(), br#"{"env":"prod"}"#.to_vec()),
+        ]);
+
+        table.put(b"db/a".to_vec(), br#"{"env":"dev"}"#.to_vec()).unwrap();
+        table.delete(b"db/c".to_vec()).unwrap();
+        assert!(table.find_by_tag("env=prod").is_empty());
+        assert_eq!(table.find_by_tag("env=dev").len(), 2);
+        assert!(table.find_by_tag("env=qa").is_empty());
+    }
+}
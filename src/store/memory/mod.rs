@@ -3,17 +3,28 @@
 //! For memory checking for not existing entities the cuckoo filter is used
 //! For getting a fingerprint from bytes the rabin algorithm is used
 pub mod memtable;
+pub mod template;
+pub mod layers;
+pub mod flags;
+pub mod secrets;
+pub mod locks;
+pub mod content_type;
+pub mod json_patch;
+pub mod query;
 
 use std::path::{PathBuf, Path};
 use std::fmt::Error;
 
-type MemResult = Result<(), Error>;
+pub type MemResult = Result<(), Error>;
 
 
-trait MemTable<K: Ord + Clone, V: Clone> {
+pub trait MemTable<K: Ord + Clone, V: Clone> {
     fn check(&self, key: K) -> bool;
     fn find(&self, key: K) -> Option<V>;
     fn put(&self, key: K, value: V) -> MemResult;
+    /// keeps the tombstone for the key so the deletion can be reconciled
+    /// with the older versions on the disk
+    fn delete(&self, key: K) -> MemResult;
 }
 
 trait Loader<E> {
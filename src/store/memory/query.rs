@@ -0,0 +1,236 @@
+//! A tiny query language for the filtered scans of the memtable.
+//! The query is a list of conditions joined by `AND`:
+//! - `prefix = "service."` the key starts with the prefix, it is pushed down to the scan
+//! - `key >= "a"` compares the key bytes (`=`, `!=`, `<`, `<=`, `>`, `>=`)
+//! - `value.type = json` the content type of the value (`=`, `!=`)
+//! - `seq > 100` the seq of the last write
+//! - `expires < 1700000000000` the expiration time in millis, the values without ttl do not match
+//!
+//! The strings are quoted by `"` (with `\"` and `\\` escapes) or written as bare words.
+//! # Examples
+//! ```
+//!  let q = Query::parse(r#"prefix = "service." AND value.type = json AND seq > 10"#)?;
+//!  for (k, e) in table.query(&q) {}
+//! ```
+use std::cmp::Ordering;
+use crate::store::memory::content_type::ContentType;
+use crate::store::memory::memtable::MemEntry;
+use crate::store::{StoreResult, StoreError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn parse(op: &str) -> StoreResult<CmpOp> {
+        match op {
+            "=" => Ok(CmpOp::Eq),
+            "!=" => Ok(CmpOp::Ne),
+            "<" => Ok(CmpOp::Lt),
+            "<=" => Ok(CmpOp::Le),
+            ">" => Ok(CmpOp::Gt),
+            ">=" => Ok(CmpOp::Ge),
+            op => Err(StoreError(format!("the operator {} is unknown", op))),
+        }
+    }
+
+    pub fn test(&self, ord: Ordering) -> bool {
+        match self {
+            CmpOp::Eq => ord == Ordering::Equal,
+            CmpOp::Ne => ord != Ordering::Equal,
+            CmpOp::Lt => ord == Ordering::Less,
+            CmpOp::Le => ord != Ordering::Greater,
+            CmpOp::Gt => ord == Ordering::Greater,
+            CmpOp::Ge => ord != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Prefix(Vec<u8>),
+    Key(CmpOp, Vec<u8>),
+    ContentType(CmpOp, ContentType),
+    Seq(CmpOp, u64),
+    ExpiresAt(CmpOp, u128),
+}
+
+impl Condition {
+    pub fn matches<V>(&self, key: &[u8], entry: &MemEntry<V>) -> bool {
+        match self {
+            Condition::Prefix(p) => key.starts_with(p),
+            Condition::Key(op, k) => op.test(key.cmp(k.as_slice())),
+            Condition::ContentType(op, ct) => op.test(if entry.content_type == Some(*ct) { Ordering::Equal } else { Ordering::Less }),
+            Condition::Seq(op, seq) => op.test(entry.seq.cmp(seq)),
+            Condition::ExpiresAt(op, ts) => entry.expires_at.map(|e| op.test(e.cmp(ts))).unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Query {
+    pub conditions: Vec<Condition>,
+}
+
+impl Query {
+    /// parses the conditions joined by `AND`. The empty query matches everything
+    pub fn parse(text: &str) -> StoreResult<Query> {
+        let tokens = tokenize(text)?;
+        let mut conditions = vec![];
+        let mut rest = tokens.as_slice();
+        while !rest.is_empty() {
+            match rest {
+                [field, op, value, tail @ ..] => {
+                    conditions.push(condition(field.text(), CmpOp::parse(op.text())?, value.text())?);
+                    rest = match tail {
+                        [] => tail,
+                        [Token::Word(and), tail @ ..] if and.eq_ignore_ascii_case("and") && !tail.is_empty() => tail,
+                        _ => return Err(StoreError(format!("the query {} expects AND between conditions", text))),
+                    };
+                }
+                _ => return Err(StoreError(format!("the query {} has an incomplete condition", text))),
+            }
+        }
+        Ok(Query { conditions })
+    }
+
+    /// the longest prefix the keys should start with to push down to the scan
+    pub fn prefix(&self) -> Vec<u8> {
+        self.conditions.iter()
+            .filter_map(|c| match c {
+                Condition::Prefix(p) => Some(p),
+                _ => None,
+            })
+            .max_by_key(|p| p.len())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn matches<V>(&self, key: &[u8], entry: &MemEntry<V>) -> bool {
+        self.conditions.iter().all(|c| c.matches(key, entry))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(String),
+}
+
+impl Token {
+    fn text(&self) -> &str {
+        match self {
+            Token::Word(t) | Token::Quoted(t) | Token::Op(t) => t,
+        }
+    }
+}
+
+fn tokenize(text: &str) -> StoreResult<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(e) => s.push(e),
+                            None => return Err(StoreError(String::from("the query ends inside a string"))),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err(StoreError(String::from("the query ends inside a string"))),
+                    }
+                }
+                tokens.push(Token::Quoted(s));
+            }
+            '=' | '!' | '<' | '>' => {
+                let mut op = c.to_string();
+                if chars.peek() == Some(&'=') {
+                    op.push('=');
+                    chars.next();
+                }
+                tokens.push(Token::Op(op));
+            }
+            c => {
+                let mut w = c.to_string();
+                while let Some(&n) = chars.peek() {
+                    if n.is_whitespace() || "\"=!<>".contains(n) {
+                        break;
+                    }
+                    w.push(n);
+                    chars.next();
+                }
+                tokens.push(Token::Word(w));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn condition(field: &str, op: CmpOp, value: &str) -> StoreResult<Condition> {
+    let not_number = |_| StoreError(format!("the value {} of {} is not a number", value, field));
+    match field {
+        "prefix" if op == CmpOp::Eq => Ok(Condition::Prefix(value.as_bytes().to_vec())),
+        "prefix" => Err(StoreError(String::from("the prefix supports only ="))),
+        "key" => Ok(Condition::Key(op, value.as_bytes().to_vec())),
+        "value.type" if op == CmpOp::Eq || op == CmpOp::Ne => Ok(Condition::ContentType(op, value.parse()?)),
+        "value.type" => Err(StoreError(String::from("the value.type supports only = and !="))),
+        "seq" => Ok(Condition::Seq(op, value.parse().map_err(not_number)?)),
+        "expires" => Ok(Condition::ExpiresAt(op, value.parse().map_err(not_number)?)),
+        f => Err(StoreError(format!("the field {} is unknown", f))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::query::{Query, Condition, CmpOp};
+    use crate::store::memory::content_type::ContentType;
+    use crate::store::memory::memtable::MemEntry;
+
+    #[test]
+    fn parse_test() {
+        let q = Query::parse(r#"prefix = "service." AND value.type=json and seq >= 10 AND key != "a \"b\"" AND expires<5"#).unwrap();
+        assert_eq!(q.conditions, vec![
+            Condition::Prefix(b"service.".to_vec()),
+            Condition::ContentType(CmpOp::Eq, ContentType::Json),
+            Condition::Seq(CmpOp::Ge, 10),
+            Condition::Key(CmpOp::Ne, b"a \"b\"".to_vec()),
+            Condition::ExpiresAt(CmpOp::Lt, 5),
+        ]);
+        assert_eq!(Query::parse("  ").unwrap(), Query::default());
+        assert_eq!(Query::parse("prefix = a AND prefix = abc").unwrap().prefix(), b"abc".to_vec());
+
+        for q in ["prefix > a", "seq = x", "seq = 1 AND", "seq = 1 seq = 2", "color = red", "key = \"a", "value.type < json", "key ="] {
+            assert!(Query::parse(q).is_err(), "{}", q);
+        }
+    }
+
+    #[test]
+    fn seq_range_test() {
+        assert_eq!(Query::parse("seq <= 18446744073709551615").unwrap().conditions, vec![Condition::Seq(CmpOp::Le, u64::MAX)]);
+        assert!(Query::parse("seq > 18446744073709551617").is_err());
+        assert!(Query::parse("seq > -1").is_err());
+    }
+
+    #[test]
+    fn matches_test() {
+        let entry = MemEntry { content_type: Some(ContentType::Json), expires_at: Some(100), ..MemEntry::put(7, 1) };
+        let m = |q: &str, key: &[u8], e: &MemEntry<i32>| Query::parse(q).unwrap().matches(key, e);
+        assert!(m("prefix = db. AND value.type = json AND seq > 6 AND expires <= 100", b"db.host", &entry));
+        assert!(!m("value.type != json", b"db.host", &entry));
+        assert!(!m("seq < 7", b"db.host", &entry));
+        assert!(m("key > db AND key < dc", b"db.host", &entry));
+        assert!(!m("expires > 0", b"db.host", &MemEntry::put(1, 1)));
+        assert!(m("value.type != toml", b"db.host", &MemEntry::put(1, 1)));
+    }
+}
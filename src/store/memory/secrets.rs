@@ -0,0 +1,83 @@
+//! Values under the secret prefixes (e.g. `secrets/`).
+//! They are encrypted by the data key of `KeyProvider` before getting into the table
+//! and are returned only to the callers passing the provider to decrypt them.
+//! # Examples
+//! ```
+//!  let secrets = SecretTable::new(&table, vec![b"secrets/".to_vec()]);
+//!  secrets.put(b"secrets/db.password".to_vec(), b"pwd".to_vec(), &keys)?;
+//!  let pwd = secrets.get_secret(b"secrets/db.password", &keys)?;
+//! ```
+use crate::store::memory::MemTable;
+use crate::store::log::backup::{KeyProvider, encrypt_bytes, decrypt_bytes};
+use crate::store::{StoreResult, StoreError};
+
+pub struct SecretTable<'a, T: MemTable<Vec<u8>, Vec<u8>>> {
+    table: &'a T,
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl<'a, T: MemTable<Vec<u8>, Vec<u8>>> SecretTable<'a, T> {
+    pub fn new(table: &'a T, prefixes: Vec<Vec<u8>>) -> Self {
+        SecretTable { table, prefixes }
+    }
+
+    pub fn is_secret(&self, key: &[u8]) -> bool {
+        self.prefixes.iter().any(|p| key.starts_with(p))
+    }
+
+    /// puts the value encrypting it if the key is secret
+    pub fn put(&self, key: Vec<u8>, val: Vec<u8>, keys: &dyn KeyProvider) -> StoreResult<()> {
+        let val = if self.is_secret(&key) { encrypt_bytes(&val, keys)? } else { val };
+        self.table.put(key, val).map_err(|_| StoreError(String::from("the value can not be put")))
+    }
+
+    /// the value of the plain key.
+    /// Can return `StoreError` if the key is secret
+    pub fn get(&self, key: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        if self.is_secret(key) {
+            return Err(StoreError(format!("the key {} is secret", String::from_utf8_lossy(key))));
+        }
+        Ok(self.table.find(key.to_vec()))
+    }
+
+    /// the decrypted value of the key. The plain keys are returned as is
+    pub fn get_secret(&self, key: &[u8], keys: &dyn KeyProvider) -> StoreResult<Option<Vec<u8>>> {
+        match self.table.find(key.to_vec()) {
+            Some(v) if self.is_secret(key) => decrypt_bytes(&v, keys).map(Some),
+            v => Ok(v),
+        }
+    }
+
+    /// the value to export: the secret values are replaced by `redacted`
+    pub fn get_redacted(&self, key: &[u8], redacted: &[u8]) -> Option<Vec<u8>> {
+        self.table.find(key.to_vec()).map(|v| if self.is_secret(key) { redacted.to_vec() } else { v })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::secrets::SecretTable;
+    use crate::store::memory::memtable::BaseMemTable;
+    use crate::store::memory::MemTable;
+    use crate::store::log::backup::StaticKeys;
+
+    #[test]
+    fn secret_test() {
+        let table: BaseMemTable<Vec<u8>, Vec<u8>> = BaseMemTable::new(1000);
+        let secrets = SecretTable::new(&table, vec![b"secrets/".to_vec()]);
+        let keys = StaticKeys::new("k1", [1; 32]);
+
+        secrets.put(b"secrets/pwd".to_vec(), b"qwerty".to_vec(), &keys).unwrap();
+        secrets.put(b"host".to_vec(), b"localhost".to_vec(), &keys).unwrap();
+
+        assert_ne!(table.find(b"secrets/pwd".to_vec()), Some(b"qwerty".to_vec()));
+        assert!(secrets.get(b"secrets/pwd").is_err());
+        assert_eq!(secrets.get(b"host").unwrap(), Some(b"localhost".to_vec()));
+        assert_eq!(secrets.get_secret(b"secrets/pwd", &keys).unwrap(), Some(b"qwerty".to_vec()));
+        assert_eq!(secrets.get_secret(b"host", &keys).unwrap(), Some(b"localhost".to_vec()));
+        assert!(secrets.get_secret(b"secrets/pwd", &StaticKeys::new("k1", [2; 32])).is_err());
+
+        assert_eq!(secrets.get_redacted(b"secrets/pwd", b"***"), Some(b"***".to_vec()));
+        assert_eq!(secrets.get_redacted(b"host", b"***"), Some(b"localhost".to_vec()));
+    }
+}
@@ -0,0 +1,87 @@
+//! Interpolation of values referencing other keys.
+//! A value can contain `${other.key}` which is replaced by the value of `other.key`
+//! resolved recursively not deeper than the given depth. `$${` is kept as `${`.
+//! # Examples
+//! ```
+//!  // host = "localhost", url = "http://${host}:8080"
+//!  let url = table.get_resolved(b"url")?; // "http://localhost:8080"
+//! ```
+use crate::store::{StoreResult, StoreError};
+
+pub const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// replaces the references in the value by the values the lookup gives.
+/// Can return `StoreError` if a referenced key is absent, the references make a cycle
+/// or the nesting is deeper than `max_depth`
+pub fn resolve<F>(val: &[u8], lookup: &F, max_depth: usize) -> StoreResult<Vec<u8>>
+    where F: Fn(&[u8]) -> Option<Vec<u8>> {
+    resolve_in(val, lookup, max_depth, &mut vec![])
+}
+
+fn resolve_in<F>(val: &[u8], lookup: &F, max_depth: usize, path: &mut Vec<Vec<u8>>) -> StoreResult<Vec<u8>>
+    where F: Fn(&[u8]) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(val.len());
+    let mut pos = 0;
+    while pos < val.len() {
+        if val[pos..].starts_with(b"$${") {
+            res.extend_from_slice(b"${");
+            pos += 3;
+        } else if val[pos..].starts_with(b"${") {
+            let end = match val[pos + 2..].iter().position(|b| *b == b'}') {
+                Some(e) => pos + 2 + e,
+                None => {
+                    res.extend_from_slice(&val[pos..]);
+                    break;
+                }
+            };
+            let key = &val[pos + 2..end];
+            if path.iter().any(|k| k.as_slice() == key) {
+                return Err(StoreError(format!("the reference to {} makes a cycle", String::from_utf8_lossy(key))));
+            }
+            if path.len() >= max_depth {
+                return Err(StoreError(format!("the references are deeper than {}", max_depth)));
+            }
+            let referenced = lookup(key)
+                .ok_or_else(|| StoreError(format!("the referenced key {} is not found", String::from_utf8_lossy(key))))?;
+            path.push(key.to_vec());
+            res.extend(resolve_in(&referenced, lookup, max_depth, path)?);
+            path.pop();
+            pos = end + 1;
+        } else {
+            res.push(val[pos]);
+            pos += 1;
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::memory::template::resolve;
+    use std::collections::HashMap;
+
+    fn lookup(pairs: &[(&str, &str)]) -> impl Fn(&[u8]) -> Option<Vec<u8>> {
+        let map: HashMap<Vec<u8>, Vec<u8>> = pairs.iter()
+            .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect();
+        move |k| map.get(k).cloned()
+    }
+
+    #[test]
+    fn resolve_test() {
+        let l = lookup(&[("host", "localhost"), ("port", "80${zero}"), ("zero", "0")]);
+        assert_eq!(resolve(b"http://${host}:${port}/", &l, 8).unwrap(), b"http://localhost:800/".to_vec());
+        assert_eq!(resolve(b"no refs", &l, 8).unwrap(), b"no refs".to_vec());
+        assert_eq!(resolve(b"$${host} ${host", &l, 8).unwrap(), b"${host} ${host".to_vec());
+        assert!(resolve(b"${absent}", &l, 8).is_err());
+    }
+
+    #[test]
+    fn cycle_test() {
+        let l = lookup(&[("a", "${b}"), ("b", "${a}"), ("c", "${c}"), ("d", "${e}"), ("e", "1")]);
+        assert!(resolve(b"${a}", &l, 8).is_err());
+        assert!(resolve(b"${c}", &l, 8).is_err());
+        assert!(resolve(b"${d}", &l, 1).is_err());
+        assert_eq!(resolve(b"${d}${d}", &l, 2).unwrap(), b"11".to_vec());
+    }
+}
@@ -3,15 +3,47 @@ pub mod files;
 pub mod memory;
 pub mod disk;
 pub mod structures;
+pub mod diff;
+pub mod iter;
 
 pub trait ToBytes {
     fn to_bytes(&self) -> Vec<u8>;
 }
 
+impl ToBytes for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
 pub type StoreResult<K> = Result<K, StoreError>;
 #[derive(Debug, Clone)]
 pub struct StoreError(pub String);
 
+const CORRUPTION: &str = "corruption: ";
+const DEADLOCK: &str = "deadlock: ";
+
+impl StoreError {
+    /// the error of the data damaged on the disk (e.g. the checksum does not match)
+    /// so the reading should stop at it
+    pub fn corruption(msg: &str) -> Self {
+        StoreError(format!("{}{}", CORRUPTION, msg))
+    }
+
+    pub fn is_corruption(&self) -> bool {
+        self.0.starts_with(CORRUPTION)
+    }
+
+    /// the error of the lock which would wait forever, see `memory::locks`
+    pub fn deadlock(msg: &str) -> Self {
+        StoreError(format!("{}{}", DEADLOCK, msg))
+    }
+
+    pub fn is_deadlock(&self) -> bool {
+        self.0.starts_with(DEADLOCK)
+    }
+}
+
 
 
 pub trait FromBytes where Self: Sized {
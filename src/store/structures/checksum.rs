@@ -0,0 +1,164 @@
+//! Checksums for the data written to the disk.
+//! The algorithm is chosen by `ChecksumKind` which is recorded along with the checksum
+//! so the data can be verified regardless of the current settings.
+//! - crc32 (the default one, used by the first batch format)
+//! - crc32c (hardware accelerated on x86_64 and aarch64)
+//! - xxhash64
+//! - rabin fingerprint with the fixed irreducible polynomial
+//! # Examples
+//! ```
+//!  let sum = ChecksumKind::Crc32c.checksummer().checksum(&bytes);
+//! ```
+use crate::store::structures::fingerprint::{FixRabinFingerprint, Polynomial, Fingerprint};
+use crate::store::{StoreResult, StoreError};
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+
+/// the irreducible polynomial of degree 53 for rabin checksums.
+/// It can not be random since the checksums are persisted
+const RABIN_POLYNOMIAL: i64 = 0x003D_A335_8B4D_C173;
+
+lazy_static! {
+    /// the lookup table of the fingerprint is built once
+    static ref RABIN: Mutex<FixRabinFingerprint> =
+        Mutex::new(FixRabinFingerprint::new(Polynomial::from_u64(RABIN_POLYNOMIAL)));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChecksumKind {
+    #[default]
+    Crc32,
+    Crc32c,
+    XxHash64,
+    Rabin,
+}
+
+pub trait Checksummer {
+    fn kind(&self) -> ChecksumKind;
+    fn checksum(&self, bytes: &[u8]) -> u64;
+
+    fn verify(&self, bytes: &[u8], expected: u64) -> bool {
+        self.checksum(bytes) == expected
+    }
+}
+
+struct Crc32;
+
+struct Crc32c;
+
+struct XxHash64;
+
+struct Rabin;
+
+impl ChecksumKind {
+    pub fn code(&self) -> u8 {
+        match self {
+            ChecksumKind::Crc32 => 1,
+            ChecksumKind::Crc32c => 2,
+            ChecksumKind::XxHash64 => 3,
+            ChecksumKind::Rabin => 4,
+        }
+    }
+
+    pub fn from_code(code: u8) -> StoreResult<ChecksumKind> {
+        match code {
+            1 => Ok(ChecksumKind::Crc32),
+            2 => Ok(ChecksumKind::Crc32c),
+            3 => Ok(ChecksumKind::XxHash64),
+            4 => Ok(ChecksumKind::Rabin),
+            c => Err(StoreError(format!("the checksum kind {} is unknown", c))),
+        }
+    }
+
+    pub fn checksummer(&self) -> Box<dyn Checksummer> {
+        match self {
+            ChecksumKind::Crc32 => Box::new(Crc32),
+            ChecksumKind::Crc32c => Box::new(Crc32c),
+            ChecksumKind::XxHash64 => Box::new(XxHash64),
+            ChecksumKind::Rabin => Box::new(Rabin),
+        }
+    }
+
+    pub fn checksum(&self, bytes: &[u8]) -> u64 {
+        self.checksummer().checksum(bytes)
+    }
+}
+
+impl Checksummer for Crc32 {
+    fn kind(&self) -> ChecksumKind {
+        ChecksumKind::Crc32
+    }
+
+    fn checksum(&self, bytes: &[u8]) -> u64 {
+        crc32fast::hash(bytes) as u64
+    }
+}
+
+impl Checksummer for Crc32c {
+    fn kind(&self) -> ChecksumKind {
+        ChecksumKind::Crc32c
+    }
+
+    fn checksum(&self, bytes: &[u8]) -> u64 {
+        crc32c::crc32c(bytes) as u64
+    }
+}
+
+impl Checksummer for XxHash64 {
+    fn kind(&self) -> ChecksumKind {
+        ChecksumKind::XxHash64
+    }
+
+    fn checksum(&self, bytes: &[u8]) -> u64 {
+        xxhash_rust::xxh64::xxh64(bytes, 0)
+    }
+}
+
+impl Checksummer for Rabin {
+    fn kind(&self) -> ChecksumKind {
+        ChecksumKind::Rabin
+    }
+
+    fn checksum(&self, bytes: &[u8]) -> u64 {
+        let mut fpr = RABIN.lock().expect("the rabin lock is poisoned");
+        let sum: i64 = fpr.calculate(bytes.to_vec()).unwrap_or(0);
+        sum as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::structures::checksum::{ChecksumKind, RABIN_POLYNOMIAL};
+    use crate::store::structures::fingerprint::Polynomial;
+    use crate::store::structures::fingerprint::Reducibility::IRREDUCIBLE;
+
+    #[test]
+    fn checksum_test() {
+        let kinds = [ChecksumKind::Crc32, ChecksumKind::Crc32c, ChecksumKind::XxHash64, ChecksumKind::Rabin];
+        for k in kinds.iter() {
+            let sum = k.checksummer();
+            assert_eq!(sum.kind(), *k);
+            assert_eq!(ChecksumKind::from_code(k.code()).unwrap(), *k);
+
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+            let expected = sum.checksum(&bytes);
+            assert_eq!(expected, k.checksum(&bytes));
+            assert!(sum.verify(&bytes, expected));
+            assert!(!sum.verify(&[1, 2, 3, 4, 5, 6, 7, 8, 10], expected));
+        }
+        assert!(ChecksumKind::from_code(0).is_err());
+    }
+
+    #[test]
+    fn known_values_test() {
+        assert_eq!(ChecksumKind::Crc32.checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(ChecksumKind::Crc32c.checksum(b"123456789"), 0xE306_9283);
+        assert_eq!(ChecksumKind::XxHash64.checksum(b""), 0xEF46_DB37_51D8_E999);
+    }
+
+    #[test]
+    fn rabin_polynomial_test() {
+        let p = Polynomial::from_u64(RABIN_POLYNOMIAL);
+        assert!(matches!(p.reducibility(), IRREDUCIBLE));
+    }
+}
@@ -0,0 +1,381 @@
+//! The thread safe variant of [SkipList](crate::store::structures::skip_list::SkipList).
+//! It is the lazy skip list (Herlihy, Lev, Luchangco, Shavit) with a lock in every node:
+//! - the search goes without locks
+//! - the insert locks only the predecessors of the new node on its levels
+//! - the delete marks the node under its lock and then unlinks it locking its predecessors
+//!
+//! so the writers of different parts of the list go in parallel.
+//! The nodes are shared by `Arc` and freed when the last reader walking over them lets them go.
+//! The handles are cheap to clone and `Send + Sync`:
+//! ```
+//! let list = ConcurrentSkipList::with_capacity(1000_000);
+//! let writer = list.clone();
+//! std::thread::spawn(move || writer.insert(1, 1));
+//! ```
+//! The memtable still keeps the single threaded `SkipList`.
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use crate::store::structures::skip_list::LevelGenerator;
+
+type Link<K, V> = Option<Arc<Node<K, V>>>;
+/// the predecessors and the successors of the key on every level and the highest level having the key
+type Position<K, V> = (Vec<Arc<Node<K, V>>>, Vec<Link<K, V>>, Option<usize>);
+
+struct Node<K, V> {
+    /// none for the head which goes before all keys
+    key: Option<K>,
+    val: RwLock<Option<V>>,
+    /// the next node on every level the node takes part in
+    next: Vec<RwLock<Link<K, V>>>,
+    lock: Mutex<()>,
+    /// the node is being deleted
+    marked: AtomicBool,
+    /// the node is linked on all its levels
+    fully_linked: AtomicBool,
+}
+
+struct Shared<K, V> {
+    head: Arc<Node<K, V>>,
+    size: AtomicUsize,
+}
+
+pub struct ConcurrentSkipList<K: Ord + Clone, V: Clone> {
+    shared: Arc<Shared<K, V>>,
+}
+
+impl<K: Ord + Clone, V: Clone> Clone for ConcurrentSkipList<K, V> {
+    fn clone(&self) -> Self {
+        ConcurrentSkipList { shared: self.shared.clone() }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Default for ConcurrentSkipList<K, V> {
+    fn default() -> Self {
+        ConcurrentSkipList::new()
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> ConcurrentSkipList<K, V> {
+    /// new empty skiplist with default capacity = 66_0000 = 16 levels
+    pub fn new() -> Self {
+        ConcurrentSkipList::with_capacity(2 << 16)
+    }
+
+    /// new empty list with selected capacity
+    pub fn with_capacity(exp_cap: usize) -> Self {
+        let levels = ((exp_cap as f64).log2().floor() as usize).max(1);
+        let head = Arc::new(Node::new(None, None, levels));
+        head.fully_linked.store(true, Ordering::SeqCst);
+        ConcurrentSkipList { shared: Arc::new(Shared { head, size: AtomicUsize::new(0) }) }
+    }
+
+    /// search element in list
+    pub fn search(&self, key: &K) -> Option<V> {
+        let (_, succs, found) = self.find(key);
+        let node = succs[found?].clone()?;
+        if node.fully_linked.load(Ordering::SeqCst) && !node.marked.load(Ordering::SeqCst) {
+            node.val.read().expect("the skiplist lock is poisoned").clone()
+        } else {
+            None
+        }
+    }
+
+    /// insert a new value to list or replace old one. it returns old val or none
+    pub fn insert(&self, key: K, val: V) -> Option<V> {
+        let height = LevelGenerator::new().random(self.levels()) + 1;
+        let mut val = Some(val);
+        loop {
+            let (preds, succs, found) = self.find(&key);
+            if let Some(l) = found {
+                let node = succs[l].clone().expect("the found node is linked");
+                if node.marked.load(Ordering::SeqCst) {
+                    // the node is being deleted, the key is inserted again after it is unlinked
+                    thread::yield_now();
+                    continue;
+                }
+                while !node.fully_linked.load(Ordering::SeqCst) {
+                    thread::yield_now();
+                }
+                let _guard = node.lock.lock().expect("the skiplist lock is poisoned");
+                if node.marked.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let mut old = node.val.write().expect("the skiplist lock is poisoned");
+                return std::mem::replace(&mut *old, val.take());
+            }
+
+            let guards = lock_preds(&preds, height, |level, pred| {
+                !pred.marked.load(Ordering::SeqCst)
+                    && succs[level].as_ref().map(|s| !s.marked.load(Ordering::SeqCst)).unwrap_or(true)
+                    && same(&pred.next(level), &succs[level])
+            });
+            if guards.is_none() {
+                continue;
+            }
+            let node = Arc::new(Node::new(Some(key), val.take(), height));
+            for (level, succ) in succs.iter().enumerate().take(height) {
+                node.set_next(level, succ.clone());
+            }
+            for (level, pred) in preds.iter().enumerate().take(height) {
+                pred.set_next(level, Some(node.clone()));
+            }
+            node.fully_linked.store(true, Ordering::SeqCst);
+            self.shared.size.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+    }
+
+    /// delete the key from list. it returns old val or none
+    pub fn delete(&self, key: &K) -> Option<V> {
+        let (_, succs, found) = self.find(key);
+        let victim = succs[found?].clone()?;
+        if !victim.fully_linked.load(Ordering::SeqCst) || victim.next.len() != found? + 1 {
+            return None;
+        }
+        {
+            let _guard = victim.lock.lock().expect("the skiplist lock is poisoned");
+            if victim.marked.load(Ordering::SeqCst) {
+                return None;
+            }
+            // the marked node is not taken as a predecessor anymore so its links do not change
+            victim.marked.store(true, Ordering::SeqCst);
+        }
+        let height = victim.next.len();
+        loop {
+            let (preds, _, _) = self.find(key);
+            let guards = lock_preds(&preds, height, |level, pred| {
+                !pred.marked.load(Ordering::SeqCst) && same(&pred.next(level), &Some(victim.clone()))
+            });
+            if guards.is_none() {
+                continue;
+            }
+            for level in (0..height).rev() {
+                preds[level].set_next(level, victim.next(level));
+            }
+            self.shared.size.fetch_sub(1, Ordering::SeqCst);
+            return victim.val.read().expect("the skiplist lock is poisoned").clone();
+        }
+    }
+
+    /// keys and values in order.
+    /// The iterator goes over the snapshot taken at the moment of the call
+    pub fn iter(&self) -> impl Iterator<Item=(K, V)> {
+        let mut entries = Vec::with_capacity(self.size());
+        let mut curr = self.shared.head.next(0);
+        while let Some(node) = curr {
+            if node.fully_linked.load(Ordering::SeqCst) && !node.marked.load(Ordering::SeqCst) {
+                if let (Some(k), Some(v)) = (node.key.clone(), node.val.read().expect("the skiplist lock is poisoned").clone()) {
+                    entries.push((k, v));
+                }
+            }
+            curr = node.next(0);
+        }
+        entries.into_iter()
+    }
+
+    /// deletes the keys which are in the list at the moment of the call
+    pub fn clear(&self) {
+        for (k, _) in self.iter() {
+            self.delete(&k);
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.shared.size.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    fn levels(&self) -> usize {
+        self.shared.head.next.len()
+    }
+
+    /// the last node lesser than the key on every level (the head if none)
+    /// and the node after it
+    fn find(&self, key: &K) -> Position<K, V> {
+        let levels = self.levels();
+        let mut preds = Vec::with_capacity(levels);
+        let mut succs = Vec::with_capacity(levels);
+        let mut found = None;
+        let mut pred = self.shared.head.clone();
+        for level in (0..levels).rev() {
+            let mut curr = pred.next(level);
+            while let Some(c) = curr.clone().filter(|c| c.key.as_ref() < Some(key)) {
+                curr = c.next(level);
+                pred = c;
+            }
+            if found.is_none() && curr.as_ref().map(|c| c.key.as_ref() == Some(key)).unwrap_or(false) {
+                found = Some(level);
+            }
+            preds.push(pred.clone());
+            succs.push(curr);
+        }
+        preds.reverse();
+        succs.reverse();
+        (preds, succs, found)
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: Option<K>, val: Option<V>, height: usize) -> Self {
+        Node {
+            key,
+            val: RwLock::new(val),
+            next: (0..height).map(|_| RwLock::new(None)).collect(),
+            lock: Mutex::new(()),
+            marked: AtomicBool::new(false),
+            fully_linked: AtomicBool::new(false),
+        }
+    }
+
+    fn next(&self, level: usize) -> Link<K, V> {
+        self.next[level].read().expect("the skiplist lock is poisoned").clone()
+    }
+
+    fn set_next(&self, level: usize, next: Link<K, V>) {
+        *self.next[level].write().expect("the skiplist lock is poisoned") = next;
+    }
+}
+
+/// the chain of the next nodes is dropped in a loop, the recursive drop would overflow the stack
+impl<K, V> Drop for Node<K, V> {
+    fn drop(&mut self) {
+        let take = |links: &mut Vec<RwLock<Link<K, V>>>| -> Vec<Arc<Node<K, V>>> {
+            links.iter_mut().filter_map(|l| l.get_mut().unwrap_or_else(|e| e.into_inner()).take()).collect()
+        };
+        let mut nodes = take(&mut self.next);
+        while let Some(n) = nodes.pop() {
+            if let Ok(mut node) = Arc::try_unwrap(n) {
+                nodes.extend(take(&mut node.next));
+            }
+        }
+    }
+}
+
+/// locks the distinct predecessors of the levels below the height and checks them
+/// # Returns
+/// the guards or none if a predecessor has been changed by another writer
+fn lock_preds<'a, K, V, F>(preds: &'a [Arc<Node<K, V>>], height: usize, valid: F) -> Option<Vec<MutexGuard<'a, ()>>>
+    where F: Fn(usize, &Node<K, V>) -> bool {
+    let mut guards = vec![];
+    for level in 0..height {
+        let pred = &preds[level];
+        // the predecessors go from right to left with levels so the same node goes in a row
+        if level == 0 || !Arc::ptr_eq(pred, &preds[level - 1]) {
+            guards.push(pred.lock.lock().expect("the skiplist lock is poisoned"));
+        }
+        if !valid(level, pred) {
+            return None;
+        }
+    }
+    Some(guards)
+}
+
+fn same<K, V>(a: &Link<K, V>, b: &Link<K, V>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::structures::concurrent_skip_list::ConcurrentSkipList;
+    use std::thread;
+
+    #[test]
+    fn simple_test() {
+        let list: ConcurrentSkipList<u64, u64> = ConcurrentSkipList::with_capacity(16);
+        assert_eq!(list.insert(200, 200), None);
+        assert_eq!(list.insert(1, 1), None);
+        assert_eq!(list.insert(80, 800), None);
+        assert_eq!(list.insert(8, 800), None);
+        assert_eq!(list.insert(80, 80), Some(800));
+
+        assert_eq!(list.search(&80), Some(80));
+        assert_eq!(list.search(&7), None);
+        assert_eq!(list.size(), 4);
+        assert_eq!(list.iter().map(|(k, _)| k).collect::<Vec<u64>>(), vec![1, 8, 80, 200]);
+
+        assert_eq!(list.delete(&8), Some(800));
+        assert_eq!(list.delete(&8), None);
+        assert_eq!(list.insert(5, 5), None);
+        assert_eq!(list.iter().collect::<Vec<(u64, u64)>>(), vec![(1, 1), (5, 5), (80, 80), (200, 200)]);
+
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(list.search(&1), None);
+    }
+
+    #[test]
+    fn concurrent_test() {
+        let list: ConcurrentSkipList<u64, u64> = ConcurrentSkipList::new();
+        let writers: Vec<_> = (0..4).map(|t| {
+            let list = list.clone();
+            thread::spawn(move || {
+                for i in 0..500 {
+                    list.insert(i * 4 + t, i);
+                }
+                for i in 0..250 {
+                    list.delete(&(i * 4 + t));
+                }
+            })
+        }).collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+
+        assert_eq!(list.size(), 1000);
+        let keys: Vec<u64> = list.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, (1000..2000).collect::<Vec<u64>>());
+        assert_eq!(list.search(&1999), Some(499));
+    }
+
+    #[test]
+    fn contended_test() {
+        let list: ConcurrentSkipList<u64, u64> = ConcurrentSkipList::with_capacity(1 << 10);
+        let writers: Vec<_> = (0..8).map(|t| {
+            let list = list.clone();
+            thread::spawn(move || {
+                for round in 0..20 {
+                    for k in 0..100 {
+                        list.insert(k, t * 1000 + round);
+                        if k % 3 == t % 3 {
+                            list.delete(&k);
+                        }
+                        // the value of a round or of the threads which have finished
+                        if let Some(v) = list.search(&k) {
+                            assert!(v == k || (v / 1000 < 8 && v % 1000 < 20), "{}", v);
+                        }
+                    }
+                }
+                for k in 0..100 {
+                    list.insert(k, k);
+                }
+            })
+        }).collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+
+        assert_eq!(list.size(), 100);
+        assert_eq!(list.iter().collect::<Vec<_>>(), (0..100).map(|k| (k, k)).collect::<Vec<_>>());
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(list.iter().count(), 0);
+    }
+
+    #[test]
+    fn drop_long_test() {
+        let list: ConcurrentSkipList<u64, u64> = ConcurrentSkipList::with_capacity(1 << 18);
+        for k in 0..200_000 {
+            list.insert(k, k);
+        }
+        assert_eq!(list.size(), 200_000);
+        drop(list);
+    }
+}
@@ -17,20 +17,64 @@
 //!         assert_eq!(f.contains(&1), true);
 //!         assert_eq!(f.contains(&10), false);
 //! ```
-//!
-//!
-//!
+//! The filter can be written next to a table and reloaded on open:
+//! ```
+//!        let bytes = f.to_bytes();
+//!        let mut f: CuckooFilter<i64> = CuckooFilter::from_bytes(&bytes)?;
+//! ```
+//! The hasher and the size of fingerprints trade the space for the false positive rate
+//! (about `2 * bucket_cap / 2^bits`). The persisted filter should use a hasher with a fixed algorithm
+//! giving the same hashes after restart and after the toolchain is updated
+//! (not `RandomState` and not `DefaultHasher` whose algorithm is unspecified),
+//! otherwise the reloaded filter gives false negatives. The default one is xxh64 with the seed 0:
+//! ```
+//!        let f: CuckooFilter<i64, Xxh64Builder> =
+//!            CuckooFilter::with_hasher(1 << 12, 0.8, 4, FingerprintSize::Bits8, Xxh64Builder::new(0));
+//! ```
 use std::marker::PhantomData;
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, BuildHasher};
+use xxhash_rust::xxh64::Xxh64Builder;
 use rand::Rng;
-use crate::store::structures::fingerprint::{RabinFingerprint, Fingerprint};
-use crate::store::ToBytes;
+use crate::store::{ToBytes, FromBytes, StoreResult, StoreError};
+
+/// the number of bits of the fingerprint kept for a value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FingerprintSize {
+    Bits8,
+    Bits16,
+    Bits32,
+}
 
+impl FingerprintSize {
+    pub fn bits(&self) -> u32 {
+        match self {
+            FingerprintSize::Bits8 => 8,
+            FingerprintSize::Bits16 => 16,
+            FingerprintSize::Bits32 => 32,
+        }
+    }
+
+    pub fn from_bits(bits: u8) -> StoreResult<FingerprintSize> {
+        match bits {
+            8 => Ok(FingerprintSize::Bits8),
+            16 => Ok(FingerprintSize::Bits16),
+            32 => Ok(FingerprintSize::Bits32),
+            b => Err(StoreError(format!("the fingerprint of {} bits is not supported", b))),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.bits() as usize / 8
+    }
+}
+
+/// the fixed number of slots holding fingerprints packed by `FingerprintSize`.
+/// The zero fingerprint marks the free slot
+#[derive(Clone)]
 struct Bucket {
-    base: Vec<Option<i64>>,
-    idx: usize,
-    cap: usize,
+    slots: Vec<u8>,
+    width: usize,
+    count: usize,
 }
 
 #[derive(Debug)]
@@ -40,63 +84,100 @@ pub enum InsertResult {
     Fail(String),
 }
 
+/// the number of fingerprints relocated before the insertion gives up
+const MAX_KICKS: usize = 512;
 
 impl Bucket {
-    fn new(cap: usize) -> Self {
+    fn new(cap: usize, size: FingerprintSize) -> Self {
         Bucket {
-            base: vec![None; cap],
-            idx: 0,
-            cap,
+            slots: vec![0; cap * size.bytes()],
+            width: size.bytes(),
+            count: 0,
         }
     }
 
-    fn new_with(val: i64, cap: usize) -> Self {
-        let mut bucket = Bucket::new(cap);
-        bucket.insert(val);
-        bucket
+    fn cap(&self) -> usize {
+        self.slots.len() / self.width
     }
 
-    fn insert(&mut self, v: i64) {
-        if self.contains(v) {
-            return;
-        }
+    fn get(&self, slot: usize) -> u32 {
+        self.slots[slot * self.width..(slot + 1) * self.width]
+            .iter()
+            .fold(0, |fp, b| (fp << 8) | *b as u32)
+    }
 
-        self.base.insert(self.idx, Some(v));
-        self.idx += 1
+    fn set(&mut self, slot: usize, fp: u32) {
+        let bytes = fp.to_be_bytes();
+        self.slots[slot * self.width..(slot + 1) * self.width].copy_from_slice(&bytes[4 - self.width..]);
     }
 
-    fn swap(&mut self, v: i64) -> Option<i64> {
-        let mut rng = rand::thread_rng();
-        let idx_swap = rng.gen_range(0, self.idx);
-        let old_val =
-            self.base
-                .get(idx_swap)
-                .and_then(|v| v.clone());
-        self.base.insert(idx_swap, Some(v));
-        old_val
+    fn position(&self, fp: u32) -> Option<usize> {
+        (0..self.cap()).find(|i| self.get(*i) == fp)
     }
 
-    fn contains(&self, fp: i64) -> bool {
-        self.base.contains(&Some(fp))
+    /// puts the fingerprint into a free slot.
+    /// The fingerprint which is already there gets one more copy
+    /// (another value with the same fingerprint) so removing one of them keeps the other
+    /// # Returns
+    /// false if the bucket is full
+    fn insert(&mut self, v: u32) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        match self.position(0) {
+            Some(pos) => {
+                self.set(pos, v);
+                self.count += 1;
+                true
+            }
+            None => false,
+        }
     }
 
-    fn is_empty(&self) -> bool {
-        self.idx == 0
+    /// replaces the fingerprint in a random occupied slot
+    /// # Returns
+    /// the slot and the evicted fingerprint or none if the bucket is empty
+    fn swap(&mut self, v: u32) -> Option<(usize, u32)> {
+        let occupied: Vec<usize> = (0..self.cap()).filter(|i| self.get(*i) != 0).collect();
+        if occupied.is_empty() {
+            return None;
+        }
+        let slot = occupied[rand::thread_rng().gen_range(0, occupied.len())];
+        let old = self.get(slot);
+        self.set(slot, v);
+        Some((slot, old))
     }
-    fn is_full(&self) -> bool {
-        self.idx == self.cap
+
+    /// puts back the fingerprint evicted by `swap`
+    fn restore(&mut self, slot: usize, v: u32) {
+        self.set(slot, v);
     }
-}
 
+    fn contains(&self, fp: u32) -> bool {
+        self.position(fp).is_some()
+    }
 
-impl Clone for Bucket {
-    fn clone(&self) -> Self {
-        Bucket {
-            base: self.base.clone(),
-            idx: self.idx.clone(),
-            cap: self.cap.clone(),
+    fn remove(&mut self, fp: u32) -> bool {
+        match self.position(fp) {
+            Some(pos) => {
+                self.set(pos, 0);
+                self.count -= 1;
+                true
+            }
+            None => false,
         }
     }
+
+    fn fingerprints(&self) -> impl Iterator<Item=u32> + '_ {
+        (0..self.cap()).map(move |i| self.get(i)).filter(|fp| *fp != 0)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    fn is_full(&self) -> bool {
+        self.count == self.cap()
+    }
 }
 
 struct Table {
@@ -105,9 +186,11 @@ struct Table {
 }
 
 impl Table {
-    fn new(cap: usize, bucket_cap: usize) -> Self {
+    /// the number of buckets is rounded up to a power of two
+    /// so the alternate bucket of the alternate one is the first one
+    fn new(cap: usize, bucket_cap: usize, size: FingerprintSize) -> Self {
         Table {
-            delegate: vec![Bucket::new(bucket_cap); cap],
+            delegate: vec![Bucket::new(bucket_cap, size); cap.max(1).next_power_of_two()],
             bucket_cap,
         }
     }
@@ -115,129 +198,289 @@ impl Table {
     fn len(&self) -> usize {
         self.delegate.len()
     }
-    fn contains(&self, idx: usize, v: i64) -> bool {
+    fn contains(&self, idx: usize, v: u32) -> bool {
         match self.delegate.get(idx) {
             Some(b) => b.contains(v),
             None => false,
         }
     }
 
-    fn swap_rand(&mut self, idx: usize, v: i64) -> Option<i64> {
+    fn remove(&mut self, idx: usize, v: u32) -> bool {
+        match self.delegate.get_mut(idx) {
+            Some(b) => b.remove(v),
+            None => false,
+        }
+    }
+
+    fn swap_rand(&mut self, idx: usize, v: u32) -> Option<(usize, u32)> {
         self.delegate
             .get_mut(idx)
             .and_then(|b| b.swap(v))
     }
 
-    fn insert(&mut self, idx: usize, v: i64) -> InsertResult {
+    fn insert(&mut self, idx: usize, v: u32) -> InsertResult {
         let len = self.len();
-        if len <= idx {
-            return InsertResult::Fail(String::from(format!("idx {} > len {}", idx, len)));
-        }
-
         match self.delegate.get_mut(idx) {
-            Some(b) if b.is_full() => InsertResult::Full,
-            Some(b) => InsertResult::Done({
-                b.insert(v);
-                idx
-            }),
-            None => InsertResult::Done({
-                self.delegate.insert(idx, Bucket::new_with(v, self.bucket_cap));
-                idx
-            })
+            Some(b) => if b.insert(v) { InsertResult::Done(idx) } else { InsertResult::Full },
+            None => InsertResult::Fail(format!("idx {} > len {}", idx, len)),
         }
     }
+
+    /// the share of the occupied slots
+    fn occupancy(&self) -> f32 {
+        let used: usize = self.delegate.iter().map(|b| b.count).sum();
+        used as f32 / (self.len() * self.bucket_cap) as f32
+    }
 }
 
-pub struct CuckooFilter<T: Hash + ToBytes> {
+pub struct CuckooFilter<T: Hash, S: BuildHasher = Xxh64Builder> {
     table: Table,
-    fpr: RabinFingerprint,
+    hasher: S,
+    fp_size: FingerprintSize,
     load_factor: f32,
+    len: usize,
     _mark: PhantomData<T>,
 }
 
-impl<T: Hash + ToBytes> CuckooFilter<T> {
+impl<T: Hash> CuckooFilter<T> {
     pub fn default() -> Self {
         CuckooFilter::new(2 << 16, 0.8)
     }
     pub fn new_with(cap: usize, lf: f32, bucket_cap: usize) -> Self {
-        CuckooFilter {
-            table: Table::new(cap, bucket_cap),
-            load_factor: lf,
-            fpr: RabinFingerprint::default(),
-            _mark: PhantomData,
-        }
+        CuckooFilter::with_hasher(cap, lf, bucket_cap, FingerprintSize::Bits16, Xxh64Builder::default())
     }
     pub fn new(cap: usize, lf: f32) -> Self {
+        CuckooFilter::new_with(cap, lf, 8)
+    }
+}
+
+impl<T: Hash, S: BuildHasher> CuckooFilter<T, S> {
+    /// # Arguments
+    /// * `cap` the number of buckets, it is rounded up to a power of two
+    /// * `bucket_cap` the number of fingerprints in a bucket
+    /// * `fp_size` the bits of the fingerprint
+    /// * `hasher` gives the bucket and the fingerprint of the value
+    pub fn with_hasher(cap: usize, lf: f32, bucket_cap: usize, fp_size: FingerprintSize, hasher: S) -> Self {
         CuckooFilter {
-            table: Table::new(cap, 8),
+            table: Table::new(cap, bucket_cap, fp_size),
+            hasher,
+            fp_size,
             load_factor: lf,
-            fpr: RabinFingerprint::default(),
+            len: 0,
             _mark: PhantomData,
         }
     }
 
+    /// puts one more copy of the fingerprint of the value.
+    /// The value inserted twice is kept twice and needs to be removed twice,
+    /// so the caller should insert the value once (e.g. when the key is new)
     pub fn insert(&mut self, v: &T) -> InsertResult {
-        let fpr: i64 = self.fpr.calculate(v.to_bytes())
-            .expect("impossible to calculate the polynomial.");
-        let hash = find_hash(v);
-
-        let bucket = self.bucket(hash);
-
-        match self.table.insert(bucket, fpr) {
-            InsertResult::Full => {
-                let fpr_num = self.bucket(bucket as i64 ^ fpr);
-                match self.table.insert(fpr_num, fpr) {
-                    InsertResult::Full => {
-                        let mut idx = 0;
-                        let mut num = if bool_rand() { bucket } else { fpr_num };
-                        let mut v = fpr;
-
-                        while idx < 512 {
-                            match self.table.swap_rand(num, v) {
-                                None => return InsertResult::Fail(String::from("the value not found")),
-                                Some(next_v) => {
-                                    let next_num = self.bucket(next_v ^ num as i64);
-                                    match self.table.insert(next_num, v) {
-                                        InsertResult::Full => {
-                                            idx += 1;
-                                            v = next_v;
-                                            num = next_num;
-                                        }
-                                        r @ _ => return r,
-                                    }
-                                }
-                            }
-                        }
-                        InsertResult::Full
-                    }
-                    r @ _ => r
+        let res = self.insert_fpr(v);
+        if let InsertResult::Done(_) = res {
+            self.len += 1
+        }
+        res
+    }
+
+    /// removes one copy of the fingerprint of the value from either candidate bucket.
+    /// Since the fingerprints can collide it can remove the value which has not been inserted
+    /// # Returns
+    /// false if the fingerprint has not been found
+    pub fn remove(&mut self, v: &T) -> bool {
+        let (idx, fpr) = self.locate(v);
+        let removed = self.table.remove(idx, fpr) || {
+            let idx = self.alt_bucket(idx, fpr);
+            self.table.remove(idx, fpr)
+        };
+        if removed {
+            self.len = self.len.saturating_sub(1);
+        }
+        removed
+    }
+
+    /// the number of the values inserted and not removed
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn fingerprint_size(&self) -> FingerprintSize {
+        self.fp_size
+    }
+
+    /// puts the fingerprint into one of two candidate buckets.
+    /// If both are full the fingerprints are relocated to their alternate buckets
+    /// (a random one is evicted each step) until a free slot is found.
+    /// If it is not found after `MAX_KICKS` steps the relocations are rolled back
+    /// so the fingerprints inserted before are not lost
+    fn insert_fpr(&mut self, v: &T) -> InsertResult {
+        let (first, fpr) = self.locate(v);
+        let second = self.alt_bucket(first, fpr);
+        for idx in [first, second].iter().copied() {
+            match self.table.insert(idx, fpr) {
+                InsertResult::Full => (),
+                r => return r,
+            }
+        }
+
+        let start = if bool_rand() { second } else { first };
+
+        let mut num = start;
+        let mut v = fpr;
+        let mut path = Vec::with_capacity(MAX_KICKS);
+        while path.len() < MAX_KICKS {
+            let (slot, victim) = match self.table.swap_rand(num, v) {
+                Some(evicted) => evicted,
+                None => return InsertResult::Fail(String::from("the value not found")),
+            };
+            path.push((num, slot, victim));
+            let alt = self.alt_bucket(num, victim);
+            match self.table.insert(alt, victim) {
+                InsertResult::Full => {
+                    v = victim;
+                    num = alt;
                 }
+                InsertResult::Done(_) => return InsertResult::Done(start),
+                r @ InsertResult::Fail(_) => return r,
             }
-            r @ _ => r
         }
+        for (num, slot, victim) in path.into_iter().rev() {
+            self.table.delegate[num].restore(slot, victim);
+        }
+        InsertResult::Full
     }
+
+    /// the share of the occupied slots
+    pub fn occupancy(&self) -> f32 {
+        self.table.occupancy()
+    }
+
     pub fn cap(&self) -> usize {
         self.table.len() * self.table.bucket_cap
     }
     pub fn contains(&mut self, val: &T) -> bool {
-        let fpr: i64 = self.fpr.calculate(val.to_bytes())
-            .expect("impossible to calculate the polynomial.");
-        let hash = find_hash(val);
-
-        let idx = self.bucket(hash);
+        let (idx, fpr) = self.locate(val);
         if self.table.contains(idx, fpr) {
             return true;
         }
-        let idx = self.bucket(idx as i64 ^ fpr);
+        let idx = self.alt_bucket(idx, fpr);
         if self.table.contains(idx, fpr) {
             return true;
         }
 
         false
     }
+
+    /// the first bucket by the low bits of the hash
+    /// and the non zero fingerprint by the high bits
+    fn locate(&self, val: &T) -> (usize, u32) {
+        let hash = find_hash(&self.hasher, val);
+        let bits = self.fp_size.bits();
+        let fpr = ((hash as u64) >> (64 - bits)) as u32;
+        (self.bucket(hash), fpr.max(1))
+    }
+
     fn bucket(&self, hash: i64) -> usize {
         (hash & (self.table.len() - 1) as i64) as usize
     }
+
+    /// the other candidate bucket of the fingerprint.
+    /// The fingerprint is hashed so the short ones spread over the whole table.
+    /// Since the number of buckets is a power of two `alt_bucket(alt_bucket(i, fp), fp) == i`
+    fn alt_bucket(&self, idx: usize, fp: u32) -> usize {
+        self.bucket(idx as i64 ^ find_hash(&self.hasher, &fp))
+    }
+}
+
+/// the layout (big endian):
+/// `[buckets u32][bucket_cap u32][fingerprint bits u8][load_factor f32][len u64]`
+/// then for every bucket `[occupied u32][slots bucket_cap * fingerprint bytes]`
+impl<T: Hash, S: BuildHasher> ToBytes for CuckooFilter<T, S> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&(self.table.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&(self.table.bucket_cap as u32).to_be_bytes());
+        bytes.push(self.fp_size.bits() as u8);
+        bytes.extend_from_slice(&self.load_factor.to_be_bytes());
+        bytes.extend_from_slice(&(self.len as u64).to_be_bytes());
+        for b in self.table.delegate.iter() {
+            bytes.extend_from_slice(&(b.count as u32).to_be_bytes());
+            bytes.extend_from_slice(&b.slots);
+        }
+        bytes
+    }
+}
+
+/// the filter is restored with the default hasher of `S`
+/// which should give the same hashes as the one the filter has been built with
+impl<T: Hash, S: BuildHasher + Default> FromBytes for CuckooFilter<T, S> {
+    fn from_bytes(bytes: &[u8]) -> StoreResult<Self> {
+        let mut pos = 0;
+        let buckets = read_u32(bytes, &mut pos)? as usize;
+        let bucket_cap = read_u32(bytes, &mut pos)? as usize;
+        let fp_size = FingerprintSize::from_bits(take(bytes, &mut pos, 1)?[0])?;
+        let load_factor = f32::from_bits(read_u32(bytes, &mut pos)?);
+        let len = u64::from_be_bytes(to_array(take(bytes, &mut pos, 8)?)) as usize;
+        if buckets == 0 || !buckets.is_power_of_two() {
+            return Err(StoreError(format!("the number of buckets {} should be a power of two", buckets)));
+        }
+        if bucket_cap == 0 {
+            return Err(StoreError(String::from("the bucket should have slots")));
+        }
+        // the sizes come from the file so they are checked against its length before allocating
+        let expected = bucket_cap.checked_mul(fp_size.bytes())
+            .and_then(|slots| slots.checked_add(4))
+            .and_then(|bucket| bucket.checked_mul(buckets));
+        if expected != Some(bytes.len() - pos) {
+            return Err(StoreError(format!("the filter of {} buckets by {} slots does not fit {} bytes", buckets, bucket_cap, bytes.len() - pos)));
+        }
+
+        let mut delegate = Vec::with_capacity(buckets);
+        for _ in 0..buckets {
+            let occupied = read_u32(bytes, &mut pos)? as usize;
+            let mut bucket = Bucket::new(bucket_cap, fp_size);
+            bucket.slots.copy_from_slice(take(bytes, &mut pos, bucket_cap * fp_size.bytes())?);
+            bucket.count = bucket.fingerprints().count();
+            if occupied != bucket.count {
+                return Err(StoreError(format!("the bucket has {} fingerprints but {} occupied slots", bucket.count, occupied)));
+            }
+            delegate.push(bucket);
+        }
+        if pos != bytes.len() {
+            return Err(StoreError(format!("the filter has {} trailing bytes", bytes.len() - pos)));
+        }
+
+        Ok(CuckooFilter {
+            table: Table { delegate, bucket_cap },
+            hasher: S::default(),
+            fp_size,
+            load_factor,
+            len,
+            _mark: PhantomData,
+        })
+    }
+}
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, n: usize) -> StoreResult<&'a [u8]> {
+    if bytes.len() < *pos + n {
+        return Err(StoreError(format!("the filter is truncated at {}", pos)));
+    }
+    let slice = &bytes[*pos..*pos + n];
+    *pos += n;
+    Ok(slice)
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> StoreResult<u32> {
+    take(bytes, pos, 4).map(|b| u32::from_be_bytes(to_array(b)))
+}
+
+fn to_array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut arr = [0; N];
+    arr.copy_from_slice(bytes);
+    arr
 }
 
 fn bool_rand() -> bool {
@@ -245,16 +488,15 @@ fn bool_rand() -> bool {
     rng.gen_bool(0.5)
 }
 
-fn find_hash<T: Hash>(entity: &T) -> i64 {
-    let mut s = DefaultHasher::new();
-    entity.hash(&mut s);
-    s.finish() as i64
+fn find_hash<S: BuildHasher, T: Hash + ?Sized>(hasher: &S, entity: &T) -> i64 {
+    hasher.hash_one(entity) as i64
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::store::structures::cuckoo_filter::{Bucket, CuckooFilter, InsertResult, find_hash};
-    use crate::store::ToBytes;
+    use crate::store::structures::cuckoo_filter::{Bucket, CuckooFilter, InsertResult, FingerprintSize, find_hash};
+    use std::collections::hash_map::RandomState;
+    use crate::store::{ToBytes, FromBytes};
 
 
     impl ToBytes for i32 {
@@ -266,7 +508,7 @@ mod tests {
 
     #[test]
     fn bucket_test() {
-        let mut bucket = Bucket::new(8);
+        let mut bucket = Bucket::new(8, FingerprintSize::Bits16);
         assert_eq!(false, bucket.contains(1));
         assert_eq!(false, bucket.is_full());
         assert_eq!(true, bucket.is_empty());
@@ -307,11 +549,181 @@ mod tests {
         assert_eq!(false, f.contains(&10001))
     }
 
+    #[test]
+    fn remove_test() {
+        let mut f: CuckooFilter<i32> = CuckooFilter::new(2 << 10, 0.8);
+        assert!(f.is_empty());
+        for el in 1..100 {
+            f.insert(&el);
+        }
+        f.insert(&1);
+        assert_eq!(f.len(), 100);
+
+        assert!(f.remove(&1));
+        assert!(f.contains(&1));
+        assert!(f.remove(&1));
+        assert!(!f.contains(&1));
+        assert!(!f.remove(&1));
+        assert!(!f.remove(&1000));
+        assert_eq!(f.len(), 98);
+
+        for el in 2..100 {
+            assert!(f.remove(&el));
+        }
+        assert!(f.is_empty());
+        assert!(!f.contains(&50));
+
+        let mut f: CuckooFilter<i32> = CuckooFilter::new_with(1, 0.8, 1);
+        f.insert(&1);
+        assert!(f.remove(&1));
+        assert!(matches!(f.insert(&2), InsertResult::Done(_)));
+    }
+
+    #[test]
+    fn bytes_test() {
+        let mut f: CuckooFilter<i32> = CuckooFilter::new(2 << 8, 0.8);
+        for el in 1..300 {
+            f.insert(&el);
+        }
+        f.remove(&7);
+
+        let bytes = f.to_bytes();
+        let mut restored: CuckooFilter<i32> = CuckooFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.len(), 298);
+        assert_eq!(restored.cap(), f.cap());
+        assert_eq!(restored.load_factor, 0.8);
+        for el in 1..300 {
+            assert_eq!(restored.contains(&el), el != 7, "{}", el);
+        }
+        assert_eq!(restored.to_bytes(), bytes);
+
+        restored.insert(&1000);
+        assert!(restored.contains(&1000));
+        assert!(restored.remove(&1));
+
+        assert!(CuckooFilter::<i32>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(CuckooFilter::<i32>::from_bytes(&[]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(CuckooFilter::<i32>::from_bytes(&trailing).is_err());
+
+        let header = |buckets: u32, bucket_cap: u32| {
+            let mut broken = bytes.clone();
+            broken[..4].copy_from_slice(&buckets.to_be_bytes());
+            broken[4..8].copy_from_slice(&bucket_cap.to_be_bytes());
+            CuckooFilter::<i32>::from_bytes(&broken)
+        };
+        assert!(header(1 << 31, 8).is_err());
+        assert!(header(512, u32::MAX).is_err());
+        assert!(header(512, 0).is_err());
+        assert!(header(1024, 4).is_err());
+        assert!(header(512, 8).is_ok());
+    }
+
+    #[test]
+    fn eviction_test() {
+        let mut f: CuckooFilter<i32> = CuckooFilter::new_with(256, 0.8, 4);
+        let mut inserted = vec![];
+        for el in 0..2000 {
+            match f.insert(&el) {
+                InsertResult::Done(_) => inserted.push(el),
+                InsertResult::Full => break,
+                r => panic!("{:?}", r),
+            }
+        }
+        assert!(f.occupancy() > 0.9, "occupancy {}", f.occupancy());
+        assert_eq!(f.len(), inserted.len());
+        assert!(f.table.delegate.iter().all(|b| b.cap() == 4 && b.count <= 4));
+        for el in inserted.iter() {
+            assert!(f.contains(el), "{}", el);
+        }
+        let used: usize = f.table.delegate.iter().map(|b| b.fingerprints().count()).sum();
+        assert_eq!(used, inserted.len());
+    }
+
+    #[test]
+    fn same_fingerprint_test() {
+        let mut f: CuckooFilter<i32> = CuckooFilter::with_hasher(16, 0.8, 4, FingerprintSize::Bits8, Default::default());
+        let located: Vec<(i32, (usize, u32))> = (0..10_000).map(|el| (el, f.locate(&el))).collect();
+        let (a, b) = located.iter()
+            .find_map(|(a, la)| located.iter().find(|(b, lb)| b != a && lb == la).map(|(b, _)| (*a, *b)))
+            .unwrap();
+
+        assert!(matches!(f.insert(&a), InsertResult::Done(_)));
+        assert!(matches!(f.insert(&b), InsertResult::Done(_)));
+        assert_eq!(f.len(), 2);
+        assert!(f.remove(&a));
+        assert!(f.contains(&b));
+        assert_eq!(f.len(), 1);
+
+        // the value inserted again takes one more copy instead of being reported as full
+        let mut f: CuckooFilter<i32> = CuckooFilter::new_with(64, 0.8, 2);
+        for _ in 0..3 {
+            assert!(matches!(f.insert(&a), InsertResult::Done(_)));
+        }
+        assert_eq!(f.len(), 3);
+    }
+
+    #[test]
+    fn stable_hash_test() {
+        // the persisted filters rely on the hashes which do not depend on the toolchain
+        let f: CuckooFilter<Vec<u8>> = CuckooFilter::new_with(1 << 10, 0.8, 4);
+        assert_eq!(f.locate(&b"db/host".to_vec()), (52, 61735));
+        assert_eq!(f.locate(&b"".to_vec()), (955, 13513));
+    }
+
+    #[test]
+    fn fingerprint_size_test() {
+        let mut rates = vec![];
+        for size in [FingerprintSize::Bits8, FingerprintSize::Bits16, FingerprintSize::Bits32].iter().copied() {
+            let mut f: CuckooFilter<i32, RandomState> = CuckooFilter::with_hasher(1 << 12, 0.8, 4, size, RandomState::new());
+            for el in 0..10_000 {
+                assert!(matches!(f.insert(&el), InsertResult::Done(_)));
+            }
+            assert!((0..10_000).all(|el| f.contains(&el)));
+            assert_eq!(f.to_bytes().len(), 21 + (1 << 12) * (4 + 4 * size.bits() as usize / 8));
+            rates.push((10_000..110_000).filter(|el| f.contains(el)).count() as f64 / 100_000.0);
+        }
+        assert!(rates[0] < 0.05, "{:?}", rates);
+        assert!(rates[1] < 0.001, "{:?}", rates);
+        assert!(rates[2] < 0.0001, "{:?}", rates);
+        assert!(FingerprintSize::from_bits(12).is_err());
+
+        let mut f: CuckooFilter<i32> = CuckooFilter::new_with(16, 0.8, 2);
+        f.insert(&1);
+        let bytes = f.to_bytes();
+        let restored: CuckooFilter<i32> = CuckooFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.fingerprint_size(), FingerprintSize::Bits16);
+        let mut broken = bytes.clone();
+        broken[8] = 12;
+        assert!(CuckooFilter::<i32>::from_bytes(&broken).is_err());
+    }
+
+    #[test]
+    fn false_positive_rate_test() {
+        let mut f: CuckooFilter<i32> = CuckooFilter::new_with(1 << 12, 0.8, 4);
+        for el in 0..10_000 {
+            assert!(matches!(f.insert(&el), InsertResult::Done(_)));
+        }
+        let fp = (10_000..110_000).filter(|el| f.contains(el)).count();
+        assert!((fp as f64) / 100_000.0 < 0.001, "false positives {}", fp);
+    }
+
+    #[test]
+    fn not_power_of_two_test() {
+        let mut f: CuckooFilter<i32> = CuckooFilter::new_with(100, 0.8, 2);
+        assert_eq!(f.cap(), 256);
+        for el in 0..200 {
+            assert!(matches!(f.insert(&el), InsertResult::Done(_)));
+        }
+        assert!((0..200).all(|el| f.contains(&el)));
+    }
+
     #[test]
     fn hash_test() {
         let mut t: CuckooFilter<i64> = CuckooFilter::default();
         let fpr = 123;
-        let hash = find_hash(&567);
+        let hash = find_hash(&t.hasher, &567);
         let i1 = t.bucket(hash);
         let i2 = t.bucket((fpr ^ i1) as i64);
         let i3 = t.bucket((fpr ^ i2) as i64);
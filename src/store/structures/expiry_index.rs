@@ -0,0 +1,107 @@
+//! The secondary index from the expiration time (millis) to the keys.
+//! The entries are ordered by `(expires_at, key)` in a sorted set
+//! so the keys expiring soon are taken from the head without scanning the store.
+//! # Examples
+//! ```
+//!  let mut idx = ExpiryIndex::new();
+//!  idx.insert(b"session/1".to_vec(), now + 60_000);
+//!  let soon = idx.expiring_before(now + 5 * 60_000);
+//!  let expired = idx.pop_expired(now);
+//! ```
+use std::collections::{HashMap, BTreeSet};
+use std::hash::Hash;
+
+pub struct ExpiryIndex<K: Ord + Clone + Hash> {
+    by_time: BTreeSet<(u128, K)>,
+    by_key: HashMap<K, u128>,
+}
+
+impl<K: Ord + Clone + Hash> Default for ExpiryIndex<K> {
+    fn default() -> Self {
+        ExpiryIndex::new()
+    }
+}
+
+impl<K: Ord + Clone + Hash> ExpiryIndex<K> {
+    pub fn new() -> Self {
+        ExpiryIndex { by_time: BTreeSet::new(), by_key: HashMap::new() }
+    }
+
+    /// sets the expiration time of the key replacing the previous one.
+    /// it returns the previous time or none
+    pub fn insert(&mut self, key: K, expires_at: u128) -> Option<u128> {
+        let old = self.remove(&key);
+        self.by_time.insert((expires_at, key.clone()));
+        self.by_key.insert(key, expires_at);
+        old
+    }
+
+    /// removes the key (when it is deleted or put without ttl).
+    /// it returns the expiration time or none
+    pub fn remove(&mut self, key: &K) -> Option<u128> {
+        let expires_at = self.by_key.remove(key)?;
+        self.by_time.remove(&(expires_at, key.clone()));
+        Some(expires_at)
+    }
+
+    pub fn expires_at(&self, key: &K) -> Option<u128> {
+        self.by_key.get(key).cloned()
+    }
+
+    /// the keys expiring strictly before the time in order of expiration
+    pub fn expiring_before(&self, ts: u128) -> Vec<(K, u128)> {
+        self.by_time.iter()
+            .take_while(|(t, _)| *t < ts)
+            .map(|(t, k)| (k.clone(), *t))
+            .collect()
+    }
+
+    /// removes and returns the keys expired at the time (expiration time <= now)
+    pub fn pop_expired(&mut self, now: u128) -> Vec<(K, u128)> {
+        let expired = self.expiring_before(now.saturating_add(1));
+        for (k, _) in expired.iter() {
+            self.remove(k);
+        }
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_key.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_key.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::structures::expiry_index::ExpiryIndex;
+
+    #[test]
+    fn expiry_test() {
+        let mut idx: ExpiryIndex<Vec<u8>> = ExpiryIndex::new();
+        assert_eq!(idx.insert(b"a".to_vec(), 300), None);
+        assert_eq!(idx.insert(b"b".to_vec(), 100), None);
+        assert_eq!(idx.insert(b"c".to_vec(), 200), None);
+        assert_eq!(idx.insert(b"d".to_vec(), 100), None);
+        assert_eq!(idx.insert(b"a".to_vec(), 50), Some(300));
+        assert_eq!(idx.len(), 4);
+
+        assert_eq!(idx.expiring_before(200), vec![
+            (b"a".to_vec(), 50),
+            (b"b".to_vec(), 100),
+            (b"d".to_vec(), 100),
+        ]);
+
+        assert_eq!(idx.remove(&b"b".to_vec()), Some(100));
+        assert_eq!(idx.remove(&b"b".to_vec()), None);
+        assert_eq!(idx.expires_at(&b"c".to_vec()), Some(200));
+
+        assert_eq!(idx.pop_expired(100), vec![(b"a".to_vec(), 50), (b"d".to_vec(), 100)]);
+        assert!(idx.pop_expired(100).is_empty());
+        assert_eq!(idx.expiring_before(u128::MAX), vec![(b"c".to_vec(), 200)]);
+        assert_eq!(idx.pop_expired(u128::MAX).len(), 1);
+        assert!(idx.is_empty());
+    }
+}
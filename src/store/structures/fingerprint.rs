@@ -20,9 +20,19 @@ pub struct RabinFingerprint {
 }
 
 
-impl ToBytes for RabinFingerprint{
+/// only the base polynomial is kept, the current value is always empty between calculations
+impl ToBytes for RabinFingerprint {
     fn to_bytes(&self) -> Vec<u8> {
-        unimplemented!()
+        self.base.to_bytes()
+    }
+}
+
+impl FromBytes for RabinFingerprint {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, StoreError> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(8) {
+            return Err(StoreError(String::from("the base polynomial should consist of longs = 8")));
+        }
+        Ok(RabinFingerprint::new(Polynomial::from_bytes(bytes)?))
     }
 }
 
@@ -60,7 +70,7 @@ impl FromBytes for i64 {
         }
         let mut bts = [0; 8];
         bts.copy_from_slice(bytes);
-        Ok(i64::from_ne_bytes(bts))
+        Ok(i64::from_le_bytes(bts))
     }
 }
 
@@ -69,7 +79,7 @@ pub trait Fingerprint<T> {
 }
 
 
-enum Reducibility {
+pub(crate) enum Reducibility {
     REDUCIBLE,
     IRREDUCIBLE,
 }
@@ -229,7 +239,7 @@ impl Polynomial {
 
         Polynomial { degrees }
     }
-    fn reducibility(&self) -> Reducibility {
+    pub(crate) fn reducibility(&self) -> Reducibility {
         let one = Polynomial::from_u64(1);
         let two = Polynomial::from_u64(2);
 
@@ -500,7 +510,7 @@ mod test {
         let res = Polynomial::xor(right.clone(), left.clone());
         assert_eq!(res.degrees, vec![14, 13, 10, 9, 8, 7, 6, 2, 1, 0]);
         let res = Polynomial::xor(left.clone(), left.clone());
-        assert_eq!(res.degrees, vec![])
+        assert_eq!(res.degrees, Vec::<i64>::new())
     }
 
     #[test]
@@ -509,7 +519,7 @@ mod test {
         let vec2 = vec![1, 2, 3];
 
         assert_eq!(vec_rem_all(vec1.clone(), vec2.clone()), vec![4, 5]);
-        assert_eq!(vec_rem_all(vec2.clone(), vec1.clone()), vec![])
+        assert_eq!(vec_rem_all(vec2.clone(), vec1.clone()), Vec::<i32>::new())
     }
 
     #[test]
@@ -1,3 +1,8 @@
 pub mod cuckoo_filter;
 pub mod fingerprint;
-pub mod skip_list;
\ No newline at end of file
+pub mod skip_list;
+pub mod checksum;
+pub mod concurrent_skip_list;
+pub mod expiry_index;
+pub mod single_flight;
+pub mod tag_index;
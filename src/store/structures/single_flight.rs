@@ -0,0 +1,166 @@
+//! Single flight: the concurrent callers for the same key share one execution.
+//! The first caller (the leader) runs the function, the others wait for its result.
+//! The next call after the result has been given runs the function again.
+//! # Examples
+//! ```
+//!  let group: Group<Vec<u8>, Option<Vec<u8>>> = Group::new();
+//!  let val = group.call(key.clone(), || loader(&key))?;
+//! ```
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, Condvar, MutexGuard};
+use crate::store::{StoreResult, StoreError};
+
+#[derive(Debug)]
+enum CallState<V> {
+    Running,
+    Done(V),
+    /// the leader has panicked
+    Failed,
+}
+
+#[derive(Debug)]
+struct Call<V> {
+    state: Mutex<CallState<V>>,
+    cond: Condvar,
+}
+
+#[derive(Debug)]
+pub struct Group<K: Eq + Hash + Clone, V: Clone> {
+    calls: Mutex<HashMap<K, Arc<Call<V>>>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Default for Group<K, V> {
+    fn default() -> Self {
+        Group::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Group<K, V> {
+    pub fn new() -> Self {
+        Group { calls: Mutex::new(HashMap::new()) }
+    }
+
+    /// runs the function or waits for the call running for the same key.
+    /// Can return `StoreError` to the waiting callers if the running call has panicked
+    pub fn call<F>(&self, key: K, f: F) -> StoreResult<V>
+        where F: FnOnce() -> V {
+        let (call, leader) = {
+            let mut calls = self.lock_calls();
+            match calls.get(&key) {
+                Some(c) => (c.clone(), false),
+                None => {
+                    let c = Arc::new(Call { state: Mutex::new(CallState::Running), cond: Condvar::new() });
+                    calls.insert(key.clone(), c.clone());
+                    (c, true)
+                }
+            }
+        };
+        if !leader {
+            return call.wait();
+        }
+
+        let mut finish = Finish { group: self, key, call, done: None };
+        let val = f();
+        finish.done = Some(val.clone());
+        Ok(val)
+    }
+
+    /// the number of keys having a running call
+    pub fn running(&self) -> usize {
+        self.lock_calls().len()
+    }
+
+    fn lock_calls(&self) -> MutexGuard<'_, HashMap<K, Arc<Call<V>>>> {
+        self.calls.lock().expect("the single flight lock is poisoned")
+    }
+}
+
+impl<V: Clone> Call<V> {
+    fn wait(&self) -> StoreResult<V> {
+        let mut state = self.state.lock().expect("the single flight lock is poisoned");
+        loop {
+            match &*state {
+                CallState::Running => state = self.cond.wait(state).expect("the single flight lock is poisoned"),
+                CallState::Done(v) => return Ok(v.clone()),
+                CallState::Failed => return Err(StoreError(String::from("the shared call has panicked"))),
+            }
+        }
+    }
+}
+
+/// gives the result to the waiting callers even if the leader panics
+struct Finish<'a, K: Eq + Hash + Clone, V: Clone> {
+    group: &'a Group<K, V>,
+    key: K,
+    call: Arc<Call<V>>,
+    done: Option<V>,
+}
+
+impl<'a, K: Eq + Hash + Clone, V: Clone> Drop for Finish<'a, K, V> {
+    fn drop(&mut self) {
+        if let Ok(mut calls) = self.group.calls.lock() {
+            calls.remove(&self.key);
+        }
+        if let Ok(mut state) = self.call.state.lock() {
+            *state = match self.done.take() {
+                Some(v) => CallState::Done(v),
+                None => CallState::Failed,
+            };
+        }
+        self.call.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::structures::single_flight::Group;
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn shared_call_test() {
+        let group: Arc<Group<u8, usize>> = Arc::new(Group::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let callers: Vec<_> = (0..8).map(|_| {
+            let (group, runs, barrier) = (group.clone(), runs.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                group.call(1, || {
+                    thread::sleep(Duration::from_millis(50));
+                    runs.fetch_add(1, Ordering::SeqCst) + 100
+                }).unwrap()
+            })
+        }).collect();
+
+        let results: Vec<usize> = callers.into_iter().map(|c| c.join().unwrap()).collect();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|r| *r == 100));
+        assert_eq!(group.running(), 0);
+
+        assert_eq!(group.call(1, || 7).unwrap(), 7);
+        assert_eq!(group.call(2, || 8).unwrap(), 8);
+    }
+
+    #[test]
+    fn panic_test() {
+        let group: Arc<Group<u8, usize>> = Arc::new(Group::new());
+        let leader_group = group.clone();
+        let leader = thread::spawn(move || {
+            leader_group.call(1, || {
+                thread::sleep(Duration::from_millis(50));
+                panic!("the loader has failed")
+            })
+        });
+        while group.running() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(group.call(1, || 1).is_err());
+        assert!(leader.join().is_err());
+        assert_eq!(group.running(), 0);
+        assert_eq!(group.call(1, || 2).unwrap(), 2);
+    }
+}
@@ -3,6 +3,8 @@
 //! ```
 //! SkipList::with_capacity(1000_000)
 //! ```
+//! The keys are ordered by `Ord` so the byte keys (`Vec<u8>`) are ordered lexicographically:
+//! the empty key goes first and the keys differing by trailing zeros are different keys.
 use std::rc::Rc;
 use rand::distributions::{Uniform, Distribution};
 use rand::prelude::ThreadRng;
@@ -22,21 +24,21 @@ use std::cell::RefCell;
 
 type SkipNode<K, V> = Rc<RefCell<Node<K, V>>>;
 
-struct LevelGenerator {
+pub(crate) struct LevelGenerator {
     p: f64,
     sampler: Uniform<f64>,
     rand: ThreadRng,
 }
 
 impl LevelGenerator {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         LevelGenerator {
             sampler: Uniform::new(0.0f64, 1.0),
             rand: rand::thread_rng(),
             p: 0.5,
         }
     }
-    fn random(&mut self, total: usize) -> usize {
+    pub(crate) fn random(&mut self, total: usize) -> usize {
         let mut height = 0;
         let mut temp = self.p;
         let level =
@@ -253,6 +255,41 @@ impl<K: Ord + Clone, V: Clone> Node<K, V> {
             RefCell::borrow_mut(under).set_value(val.clone());
         }
     }
+    /// the last node of the level with the key less or equal to the given one
+    /// or the first node of the level if all keys are greater
+    fn closest(node: SkipNode<K, V>, key: &K) -> SkipNode<K, V> {
+        let mut curr = node;
+        while let Some(next) = Node::get_next(curr.clone()) {
+            if RefCell::borrow(&next).key > *key {
+                break;
+            }
+            curr = next;
+        }
+        while RefCell::borrow(&curr).key > *key {
+            match Node::get_prev(curr.clone()) {
+                Some(prev) => curr = prev,
+                None => break,
+            }
+        }
+        curr
+    }
+    /// adds the nodes with the same key and val on top of the node up to the level.
+    /// The levels above the node should be empty so the new nodes have no neighbours
+    fn raise(node: SkipNode<K, V>, level: usize) -> SkipNode<K, V> {
+        let mut top = node;
+        loop {
+            let (key, val, curr_lvl) = {
+                let n = RefCell::borrow(&top);
+                (n.key.clone(), n.val.clone(), n.level)
+            };
+            if curr_lvl >= level {
+                return top;
+            }
+            let new_top = Node::with(key, val, curr_lvl + 1);
+            Node::set_under(new_top.clone(), top);
+            top = new_top;
+        }
+    }
     fn find_first(node: SkipNode<K, V>) -> SkipNode<K, V> {
         let mut first_node = node.clone();
         if RefCell::borrow(&node.clone()).prev.is_some() {
@@ -304,6 +341,22 @@ impl<K: Ord + Clone, V: Clone> SkipList<K, V> {
     pub fn iter(&self) -> SkipListDistinctIterator<K, V> {
         SkipListDistinctIterator::new(self)
     }
+    /// keys and values in order
+    pub fn entries(&self) -> impl Iterator<Item=(K, V)> {
+        self.iter().map(|n| {
+            let node = n.borrow();
+            (node.key.clone(), node.val.clone())
+        })
+    }
+
+    /// keys and values in order starting from the first key greater or equal to the given one
+    pub fn entries_from(&self, key: &K) -> impl Iterator<Item=(K, V)> {
+        let curr = self.lower_bound(key);
+        SkipListDistinctIterator { size: self.size, curr }.map(|n| {
+            let node = n.borrow();
+            (node.key.clone(), node.val.clone())
+        })
+    }
 
     /// clear skiplist
     pub fn clear(&mut self) {
@@ -358,69 +411,50 @@ impl<K: Ord + Clone, V: Clone> SkipList<K, V> {
         }
     }
 
-    /// delete by key
+    /// changes the value of the key in place without cloning it out of the list.
+    /// Every node of the tower keeps the value so the function is applied to each of them.
+    /// It returns false if the key is absent
+    pub fn update<F>(&mut self, key: &K, mut update: F) -> bool where F: FnMut(&mut V) {
+        let mut node = match self.first().and_then(|head| SkipList::find_tower(head, key)) {
+            Some(top) => Some(top),
+            None => return false,
+        };
+        while let Some(n) = node {
+            update(&mut n.borrow_mut().val);
+            node = Node::get_under(n);
+        }
+        true
+    }
+
+    /// delete by key. It returns the deleted val or none.
+    /// The tower of the key is unlinked at every level. If the tower is the head
+    /// the leftmost node of the highest level left becomes the head and gets raised to the height of the old one
     pub fn delete(&mut self, key: &K) -> Option<V> {
-        match self.first() {
-            None => None,
-            Some(f) => {
-                let first = RefCell::borrow(&f);
-                let res = Some(first.val.clone());
-                match first.key.partial_cmp(key) {
-                    Some(Equal) => {
-                        match &first.next {
-                            None => {
-                                let mut under_opt = Node::get_under(f.clone());
-                                while under_opt.is_some() {
-                                    let under = under_opt.as_ref().unwrap().clone();
-                                    match (Node::get_prev(under.clone()),
-                                           Node::get_next(under.clone())) {
-                                        (None, None) => under_opt = Node::get_under(under.clone()),
-                                        (Some(n), _) |
-                                        (None, Some(n)) => {
-                                            Node::delete(f.clone());
-                                            self.dec_size();
-
-                                            let node_b = n.borrow();
-                                            let k = node_b.key.clone();
-                                            let v = node_b.val.clone();
-
-                                            let mut top_node = n.clone();
-                                            let under_node = n.clone();
-                                            let mut cur_lvl = node_b.level + 1;
-
-                                            while cur_lvl <= self.levels {
-                                                top_node = Node::with(
-                                                    k.clone(),
-                                                    v.clone(),
-                                                    cur_lvl,
-                                                );
-                                                Node::set_under(top_node.clone(), under_node.clone());
-                                                cur_lvl += 1;
-                                            }
-                                            self.head.borrow_mut().next = Some(top_node.clone());
-                                            return res;
-                                        }
-                                    }
-                                }
-                                self.dec_size();
-                                self.head.borrow_mut().next = None;
-                                return res;
-                            }
-                            Some(next) => {
-                                self.head.borrow_mut().next = Some(next.clone());
-                                Node::delete(f.clone());
-                                self.dec_size();
-                                return res;
-                            }
-                        }
-                    }
-                    _ => {
-                        self.dec_size();
-                        return SkipList::delete_elem(key, f.clone());
-                    }
-                };
+        let head = self.first()?;
+        let top = SkipList::find_tower(head.clone(), key)?;
+        let val = RefCell::borrow(&top).val.clone();
+        let height = RefCell::borrow(&top).level;
+
+        let mut tower = vec![top.clone()];
+        while let Some(under) = Node::get_under(tower[tower.len() - 1].clone()) {
+            tower.push(under);
+        }
+        Node::delete(top.clone());
+        self.dec_size();
+
+        if Rc::ptr_eq(&top, &head) {
+            // the nodes of the unlinked tower still point to their neighbours
+            let neighbour = tower.iter()
+                .find_map(|n| Node::get_prev(n.clone()).or_else(|| Node::get_next(n.clone())));
+            match neighbour {
+                None => self.head.borrow_mut().clear(),
+                Some(n) => {
+                    let new_head = Node::raise(Node::find_first(n), height);
+                    self.head.borrow_mut().next = Some(new_head);
+                }
             }
         }
+        Some(val)
     }
 
     pub fn size(&self) -> usize {
@@ -455,33 +489,29 @@ impl<K: Ord + Clone, V: Clone> SkipList<K, V> {
             }
         }
     }
-    fn delete_elem(key: &K, f: Rc<RefCell<Node<K, V>>>) -> Option<V> {
-        let mut curr_node = f.clone();
-        let mut prev_step = FromHead;
+    /// the top node of the tower of the key.
+    /// Every level is a sorted list so it is walked from the node the upper level has come down to
+    fn find_tower(head: SkipNode<K, V>, key: &K) -> Option<SkipNode<K, V>> {
+        let mut curr = head;
         loop {
-            match RefCell::borrow(&curr_node.clone()).compare(key, &prev_step) {
-                NotFound => {
-                    return None;
-                }
-                Backward(p) => {
-                    curr_node = p.clone();
-                    prev_step = FromRight;
-                }
-                Found(v) => {
-                    Node::delete(curr_node.clone());
-                    return Some(v);
-                }
-                Forward(n) => {
-                    curr_node = n.clone();
-                    prev_step = FromLeft;
-                }
-                Down(n) => {
-                    curr_node = n.clone();
-                    prev_step = FromAbove;
-                }
+            curr = Node::closest(curr, key);
+            if RefCell::borrow(&curr).key == *key {
+                return Some(curr);
             }
+            curr = Node::get_under(curr)?;
         }
     }
+    /// the node of the lowest level with the first key greater or equal to the given one
+    fn lower_bound(&self, key: &K) -> Option<SkipNode<K, V>> {
+        let mut curr = Node::closest(self.first()?, key);
+        while let Some(under) = Node::get_under(curr.clone()) {
+            curr = Node::closest(under, key);
+        }
+        if RefCell::borrow(&curr).key < *key {
+            return Node::get_next(curr);
+        }
+        Some(curr)
+    }
     fn first(&self) -> Option<SkipNode<K, V>> {
         RefCell::borrow(&self.head)
             .next
@@ -618,6 +648,9 @@ impl<K: Ord + Clone, V: Clone> Iterator for SkipListIterator<K, V> {
 #[cfg(test)]
 mod tests {
     use crate::store::structures::skip_list::{Node, LevelGenerator, SkipList};
+    use std::collections::BTreeMap;
+    use rand::Rng;
+    use rand::seq::SliceRandom;
 
     #[test]
     fn connect_node_test() {
@@ -788,6 +821,125 @@ mod tests {
         test_search_not(list.search(&1));
     }
 
+    /// runs random inserts, deletes and searches checking them against `BTreeMap`
+    fn check_against_model(list: &mut SkipList<u64, u64>, ops: usize, keys: u64) {
+        let mut rng = rand::thread_rng();
+        let mut model: BTreeMap<u64, u64> = BTreeMap::new();
+        for i in 0..ops {
+            let key = rng.gen_range(0, keys);
+            match rng.gen_range(0, 10) {
+                0..=3 => assert_eq!(list.insert(key, i as u64), model.insert(key, i as u64), "insert {}", key),
+                4..=7 => assert_eq!(list.delete(&key), model.remove(&key), "delete {}", key),
+                _ => assert_eq!(list.search(&key), model.get(&key).cloned(), "search {}", key),
+            }
+            assert_eq!(list.size(), model.len());
+            if i % 50 == 0 {
+                let entries: Vec<(u64, u64)> = list.entries().collect();
+                assert_eq!(entries, model.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>());
+            }
+        }
+        for (k, v) in model.iter() {
+            assert_eq!(list.search(k), Some(*v));
+        }
+    }
+
+    #[test]
+    fn model_test() {
+        for _ in 0..20 {
+            check_against_model(&mut SkipList::with_capacity(16), 2000, 64);
+            check_against_model(&mut SkipList::with_capacity(1024), 2000, 1000);
+        }
+    }
+
+    #[test]
+    fn model_drain_test() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..50 {
+            let mut list: SkipList<u64, u64> = SkipList::with_capacity(64);
+            let mut keys: Vec<u64> = (0..200).collect();
+            for k in keys.iter() {
+                assert_eq!(list.insert(*k, *k), None);
+            }
+            keys.shuffle(&mut rng);
+            for (i, k) in keys.iter().enumerate() {
+                assert_eq!(list.delete(k), Some(*k));
+                assert_eq!(list.delete(k), None);
+                assert_eq!(list.size(), keys.len() - i - 1);
+            }
+            assert_eq!(list.entries().count(), 0);
+            check_against_model(&mut list, 500, 32);
+        }
+    }
+
+    #[test]
+    fn entries_from_test() {
+        let mut list: SkipList<u64, u64> = SkipList::with_capacity(16);
+        assert_eq!(list.entries_from(&1).count(), 0);
+        for k in (10..=100).step_by(10) {
+            let _ = list.insert(k, k);
+        }
+        let keys = |from: u64| list.entries_from(&from).map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys(0), (10..=100).step_by(10).collect::<Vec<_>>());
+        assert_eq!(keys(70), vec![70, 80, 90, 100]);
+        assert_eq!(keys(71), vec![80, 90, 100]);
+        assert!(keys(101).is_empty());
+
+        let mut rng = rand::thread_rng();
+        let mut model = BTreeMap::new();
+        let mut list: SkipList<u64, u64> = SkipList::with_capacity(256);
+        for _ in 0..500 {
+            let k = rng.gen_range(0, 300);
+            if rng.gen_bool(0.3) {
+                assert_eq!(list.delete(&k), model.remove(&k));
+            } else {
+                assert_eq!(list.insert(k, k), model.insert(k, k));
+            }
+            let from = rng.gen_range(0, 300);
+            assert_eq!(list.entries_from(&from).collect::<Vec<_>>(),
+                       model.range(from..).map(|(k, v)| (*k, *v)).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn delete_head_test() {
+        let mut list: SkipList<u64, u64> = SkipList::with_capacity(16);
+        for k in [50, 10, 90, 30, 70] {
+            let _ = list.insert(k, k);
+        }
+        assert_eq!(list.delete(&50), Some(50));
+        assert_eq!(list.delete(&50), None);
+        assert_eq!(list.size(), 4);
+        assert_eq!(list.first().map(|h| h.borrow().level), Some(list.levels + 1));
+        assert_eq!(list.entries().map(|(k, _)| k).collect::<Vec<_>>(), vec![10, 30, 70, 90]);
+
+        let _ = list.insert(5, 5);
+        assert_eq!(list.search(&5), Some(5));
+        for k in [5, 10, 30, 70, 90] {
+            assert_eq!(list.delete(&k), Some(k));
+        }
+        assert!(list.first().is_none());
+        assert_eq!(list.insert(1, 1), None);
+        assert_eq!(list.search(&1), Some(1));
+    }
+
+    #[test]
+    fn update_test() {
+        let mut list: SkipList<u64, Vec<u64>> = SkipList::with_capacity(256);
+        assert!(!list.update(&1, |v| v.push(1)));
+        for k in 0..200 {
+            let _ = list.insert(k, vec![k]);
+        }
+        for k in 0..200 {
+            assert!(list.update(&k, |v| v.push(k + 1)));
+        }
+        assert!(!list.update(&200, |v| v.push(0)));
+        for k in 0..200 {
+            assert_eq!(list.search(&k), Some(vec![k, k + 1]));
+        }
+        assert!(list.entries().all(|(k, v)| v == vec![k, k + 1]));
+        assert_eq!(list.size(), 200);
+    }
+
     fn test_search(got_val: Option<u64>, exp_val: u64) {
         assert_eq!(got_val.is_some(), true);
         assert_eq!(got_val, Some(exp_val));
@@ -830,5 +982,57 @@ mod tests {
             assert_eq!(true, i < 16)
         }
     }
-}
 
+    fn edge_keys() -> Vec<Vec<u8>> {
+        vec![
+            vec![1, 0, 0],
+            vec![0xFF; 64],
+            vec![],
+            vec![1, 0],
+            vec![0],
+            vec![1],
+            vec![0xFF; 63],
+            vec![0, 0],
+        ]
+    }
+
+    #[test]
+    fn edge_keys_order_test() {
+        let mut list: SkipList<Vec<u8>, usize> = SkipList::with_capacity(16);
+        for (i, k) in edge_keys().into_iter().enumerate() {
+            assert_eq!(list.insert(k, i), None);
+        }
+        assert_eq!(list.size(), 8);
+
+        let keys: Vec<Vec<u8>> = list.iter().map(|n| n.borrow().key.clone()).collect();
+        let mut expected = edge_keys();
+        expected.sort();
+        assert_eq!(keys[0], Vec::<u8>::new());
+        assert_eq!(keys, expected);
+
+        for (i, k) in edge_keys().iter().enumerate() {
+            assert_eq!(list.search(k), Some(i));
+        }
+        assert_eq!(list.search(&vec![0, 0, 0]), None);
+    }
+
+    #[test]
+    fn edge_keys_replace_delete_test() {
+        let mut list: SkipList<Vec<u8>, usize> = SkipList::with_capacity(16);
+        for (i, k) in edge_keys().into_iter().enumerate() {
+            let _ = list.insert(k, i);
+        }
+        assert_eq!(list.insert(vec![], 100), Some(2));
+        assert_eq!(list.search(&vec![]), Some(100));
+
+        assert_eq!(list.delete(&vec![1, 0]), Some(3));
+        assert_eq!(list.search(&vec![1, 0]), None);
+        assert_eq!(list.search(&vec![1]), Some(5));
+        assert_eq!(list.search(&vec![1, 0, 0]), Some(0));
+
+        assert_eq!(list.delete(&vec![]), Some(100));
+        assert_eq!(list.search(&vec![]), None);
+        assert_eq!(list.search(&vec![0]), Some(4));
+        assert_eq!(list.size(), 6);
+    }
+}
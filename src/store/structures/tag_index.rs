@@ -0,0 +1,130 @@
+//! The secondary index from the tags of the values to the keys.
+//! The tags are extracted from the value by the function given by the user (e.g. `env=prod` from a json value)
+//! and kept in a skip list `tag -> keys` so the keys having the tag are found without scanning the store.
+//! The sets of keys are changed in place so adding a key to a tag does not copy the whole set.
+//! The index keeps the tags of every key to update the tag entries when the value changes or gets deleted.
+//! # Examples
+//! ```
+//!  let mut idx = TagIndex::new(|v: &Vec<u8>| vec![format!("size={}", v.len())]);
+//!  idx.insert(b"db".to_vec(), &b"localhost".to_vec());
+//!  let keys = idx.find(&"size=9".to_string());
+//! ```
+use std::collections::{HashMap, BTreeSet};
+use std::hash::Hash;
+use crate::store::structures::skip_list::SkipList;
+
+pub type Tag = String;
+
+/// extracts the tags of the value
+pub type TagExtractor<V> = Box<dyn Fn(&V) -> Vec<Tag>>;
+
+pub struct TagIndex<K: Ord + Clone + Hash, V> {
+    extractor: TagExtractor<V>,
+    by_tag: SkipList<Tag, BTreeSet<K>>,
+    by_key: HashMap<K, Vec<Tag>>,
+}
+
+impl<K: Ord + Clone + Hash, V> TagIndex<K, V> {
+    pub fn new<F>(extractor: F) -> Self where F: Fn(&V) -> Vec<Tag> + 'static {
+        TagIndex { extractor: Box::new(extractor), by_tag: SkipList::new(), by_key: HashMap::new() }
+    }
+
+    /// sets the tags of the key extracted from the value replacing the previous ones
+    pub fn insert(&mut self, key: K, val: &V) {
+        self.remove(&key);
+        let mut tags = (self.extractor)(val);
+        tags.sort();
+        tags.dedup();
+        for t in tags.iter() {
+            if !self.by_tag.update(t, |keys| { keys.insert(key.clone()); }) {
+                self.by_tag.insert(t.clone(), std::iter::once(key.clone()).collect());
+            }
+        }
+        if !tags.is_empty() {
+            self.by_key.insert(key, tags);
+        }
+    }
+
+    /// removes the key (when it is deleted or expired).
+    /// it returns the tags of the key or none
+    pub fn remove(&mut self, key: &K) -> Option<Vec<Tag>> {
+        let tags = self.by_key.remove(key)?;
+        for t in tags.iter() {
+            let mut empty = false;
+            self.by_tag.update(t, |keys| {
+                keys.remove(key);
+                empty = keys.is_empty();
+            });
+            if empty {
+                self.by_tag.delete(t);
+            }
+        }
+        Some(tags)
+    }
+
+    /// the keys having the tag in order
+    pub fn find(&self, tag: &Tag) -> Vec<K> {
+        self.by_tag.search(tag).map(|keys| keys.into_iter().collect()).unwrap_or_default()
+    }
+
+    pub fn tags(&self, key: &K) -> Vec<Tag> {
+        self.by_key.get(key).cloned().unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.by_tag.clear();
+        self.by_key.clear();
+    }
+
+    /// the number of the tagged keys
+    pub fn len(&self) -> usize {
+        self.by_key.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_key.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::structures::tag_index::TagIndex;
+
+    #[test]
+    fn tag_index_test() {
+        let mut idx: TagIndex<Vec<u8>, String> = TagIndex::new(|v: &String| v.split(',').filter(|t| !t.is_empty()).map(String::from).collect());
+        idx.insert(b"b".to_vec(), &"env=prod,team=a".to_string());
+        idx.insert(b"a".to_vec(), &"env=prod,env=prod".to_string());
+        idx.insert(b"c".to_vec(), &"env=dev".to_string());
+        assert_eq!(idx.find(&"env=prod".to_string()), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(idx.tags(&b"a".to_vec()), vec!["env=prod".to_string()]);
+        assert_eq!(idx.len(), 3);
+
+        idx.insert(b"b".to_vec(), &"env=dev".to_string());
+        assert_eq!(idx.find(&"env=prod".to_string()), vec![b"a".to_vec()]);
+        assert_eq!(idx.find(&"env=dev".to_string()), vec![b"b".to_vec(), b"c".to_vec()]);
+        assert!(idx.find(&"team=a".to_string()).is_empty());
+
+        assert_eq!(idx.remove(&b"a".to_vec()), Some(vec!["env=prod".to_string()]));
+        assert_eq!(idx.remove(&b"a".to_vec()), None);
+        assert!(idx.find(&"env=prod".to_string()).is_empty());
+
+        idx.insert(b"c".to_vec(), &String::new());
+        assert_eq!(idx.find(&"env=dev".to_string()), vec![b"b".to_vec()]);
+        assert_eq!(idx.len(), 1);
+    }
+
+    #[test]
+    fn shared_tag_test() {
+        let mut idx: TagIndex<u32, String> = TagIndex::new(|v: &String| vec![v.clone()]);
+        for k in 0..20_000 {
+            idx.insert(k, &"env=prod".to_string());
+        }
+        assert_eq!(idx.find(&"env=prod".to_string()).len(), 20_000);
+        for k in 0..20_000 {
+            idx.remove(&k);
+        }
+        assert!(idx.find(&"env=prod".to_string()).is_empty());
+        assert!(idx.is_empty());
+    }
+}